@@ -0,0 +1,3 @@
+ALTER TABLE transactions ADD COLUMN account_id VARCHAR NULL;
+
+CREATE INDEX IF NOT EXISTS transactions_user_account_idx ON transactions (user_id, account_id);
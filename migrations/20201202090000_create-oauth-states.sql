@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS oauth_states
+(
+    nonce uuid PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    created_at TIMESTAMP NOT NULL default current_timestamp,
+    expires_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS oauth_states_user_idx ON oauth_states (user_id);
@@ -0,0 +1,22 @@
+CREATE TABLE IF NOT EXISTS provider_credentials
+(
+    id     BIGSERIAL PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    provider VARCHAR NOT NULL,
+    access_token VARCHAR NOT NULL,
+    refresh_token VARCHAR NULL,
+    scopes VARCHAR NULL,
+    expires_at TIMESTAMP NOT NULL,
+    created_at TIMESTAMP NOT NULL default current_timestamp,
+    updated_at TIMESTAMP NOT NULL default current_timestamp,
+    UNIQUE (user_id, provider)
+);
+
+-- Tokens stored before this migration have no known expiry, so they are
+-- treated as already expired and will be refreshed (or re-linked) on next use.
+INSERT INTO provider_credentials (user_id, provider, access_token, expires_at)
+SELECT id, 'truelayer', access_token, current_timestamp
+FROM users
+WHERE access_token IS NOT NULL;
+
+ALTER TABLE users DROP COLUMN access_token;
@@ -0,0 +1,52 @@
+ALTER TABLE transactions RENAME COLUMN results TO raw;
+
+ALTER TABLE transactions
+    ADD COLUMN provider_transaction_id VARCHAR NULL,
+    ADD COLUMN booked_at TIMESTAMPTZ NULL,
+    ADD COLUMN amount NUMERIC(19, 4) NULL,
+    ADD COLUMN currency VARCHAR(3) NULL,
+    ADD COLUMN transaction_type VARCHAR NULL,
+    ADD COLUMN transaction_category VARCHAR NULL,
+    ADD COLUMN description VARCHAR NULL,
+    ADD COLUMN updated_at TIMESTAMP NOT NULL default current_timestamp;
+
+UPDATE transactions
+SET account_id = raw->>'account_id'
+WHERE account_id IS NULL;
+
+UPDATE transactions SET
+    provider_transaction_id = raw->>'transaction_id',
+    booked_at = (raw->>'timestamp')::timestamptz,
+    amount = (raw->>'amount')::numeric,
+    currency = raw->>'currency',
+    transaction_type = raw->>'transaction_type',
+    transaction_category = raw->>'transaction_category',
+    description = raw->>'description';
+
+-- Transactions stored before accounts were tracked don't say which account
+-- they belong to. They are set aside rather than dropped, as the provider may
+-- not return them again once the consent lapsed or they are older than the
+-- history it serves.
+CREATE TABLE IF NOT EXISTS legacy_transactions (LIKE transactions);
+INSERT INTO legacy_transactions SELECT * FROM transactions WHERE account_id IS NULL;
+DELETE FROM transactions WHERE account_id IS NULL;
+
+-- Keep only the latest copy of transactions that were stored more than once.
+DELETE FROM transactions older
+USING transactions newer
+WHERE older.user_id = newer.user_id
+  AND older.account_id = newer.account_id
+  AND older.provider_transaction_id = newer.provider_transaction_id
+  AND older.id < newer.id;
+
+ALTER TABLE transactions
+    ALTER COLUMN account_id SET NOT NULL,
+    ALTER COLUMN provider_transaction_id SET NOT NULL,
+    ALTER COLUMN booked_at SET NOT NULL,
+    ALTER COLUMN amount SET NOT NULL,
+    ALTER COLUMN currency SET NOT NULL,
+    ADD CONSTRAINT transactions_user_account_provider_id_key
+        UNIQUE (user_id, account_id, provider_transaction_id);
+
+DROP INDEX IF EXISTS transactions_user_account_idx;
+CREATE INDEX IF NOT EXISTS transactions_user_booked_at_idx ON transactions (user_id, booked_at);
@@ -0,0 +1,19 @@
+CREATE TABLE IF NOT EXISTS sync_accounts
+(
+    user_id uuid NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    provider VARCHAR NOT NULL,
+    account_id VARCHAR NOT NULL,
+    synced_until TIMESTAMPTZ NOT NULL,
+    updated_at TIMESTAMP NOT NULL default current_timestamp,
+    PRIMARY KEY (user_id, provider, account_id)
+);
+
+CREATE TABLE IF NOT EXISTS sync_status
+(
+    user_id uuid PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    last_started_at TIMESTAMPTZ NULL,
+    last_success_at TIMESTAMPTZ NULL,
+    last_failure_at TIMESTAMPTZ NULL,
+    last_error VARCHAR NULL,
+    next_sync_at TIMESTAMPTZ NULL
+);
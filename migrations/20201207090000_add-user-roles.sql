@@ -0,0 +1,3 @@
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS role VARCHAR NOT NULL default 'customer'
+        CHECK (role IN ('customer', 'support', 'admin'));
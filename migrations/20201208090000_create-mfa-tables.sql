@@ -0,0 +1,19 @@
+CREATE TABLE IF NOT EXISTS user_mfa
+(
+    user_id uuid PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    secret VARCHAR NOT NULL,
+    enabled_at TIMESTAMP NULL,
+    last_used_step BIGINT NULL,
+    created_at TIMESTAMP NOT NULL default current_timestamp
+);
+
+CREATE TABLE IF NOT EXISTS mfa_recovery_codes
+(
+    id uuid default uuid_generate_v4() PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    code_hash VARCHAR NOT NULL,
+    used_at TIMESTAMP NULL,
+    created_at TIMESTAMP NOT NULL default current_timestamp
+);
+
+CREATE INDEX IF NOT EXISTS mfa_recovery_codes_user_idx ON mfa_recovery_codes (user_id);
@@ -0,0 +1,21 @@
+CREATE TABLE IF NOT EXISTS auth_events
+(
+    id BIGSERIAL PRIMARY KEY,
+    user_id uuid NULL REFERENCES users (id) ON DELETE CASCADE,
+    username VARCHAR NOT NULL,
+    event VARCHAR NOT NULL,
+    ip VARCHAR NULL,
+    user_agent VARCHAR NULL,
+    created_at TIMESTAMPTZ NOT NULL default current_timestamp
+);
+
+CREATE INDEX IF NOT EXISTS auth_events_user_idx ON auth_events (user_id, id);
+
+-- Failed attempts per `user:<username>` and `ip:<address>` key.
+CREATE TABLE IF NOT EXISTS auth_throttles
+(
+    key VARCHAR PRIMARY KEY,
+    failures INTEGER NOT NULL default 0,
+    last_failure_at TIMESTAMPTZ NOT NULL default current_timestamp,
+    locked_until TIMESTAMPTZ NULL
+);
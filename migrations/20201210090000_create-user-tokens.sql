@@ -0,0 +1,16 @@
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email_verified_at TIMESTAMP NULL;
+
+-- Single use tokens sent by email, only their hash is stored.
+CREATE TABLE IF NOT EXISTS user_tokens
+(
+    id uuid default uuid_generate_v4() PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    purpose VARCHAR NOT NULL CHECK (purpose IN ('password_reset', 'email_verification')),
+    token_hash VARCHAR NOT NULL UNIQUE,
+    email VARCHAR NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ NULL,
+    created_at TIMESTAMPTZ NOT NULL default current_timestamp
+);
+
+CREATE INDEX IF NOT EXISTS user_tokens_user_idx ON user_tokens (user_id, purpose);
@@ -0,0 +1,8 @@
+-- Users who deleted their account are deactivated straight away, their data
+-- is purged once the grace period is over.
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMP NULL,
+    ADD COLUMN IF NOT EXISTS purged_at TIMESTAMP NULL;
+
+CREATE INDEX IF NOT EXISTS users_pending_purge_idx ON users (deleted_at)
+    WHERE deleted_at IS NOT NULL AND purged_at IS NULL;
@@ -0,0 +1,17 @@
+-- Archives of everything stored about a user, built in the background.
+CREATE TABLE IF NOT EXISTS data_exports
+(
+    id uuid default uuid_generate_v4() PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    status VARCHAR NOT NULL default 'pending'
+        CHECK (status IN ('pending', 'running', 'ready', 'failed', 'expired')),
+    archive BYTEA NULL,
+    error VARCHAR NULL,
+    created_at TIMESTAMPTZ NOT NULL default current_timestamp,
+    started_at TIMESTAMPTZ NULL,
+    completed_at TIMESTAMPTZ NULL,
+    expires_at TIMESTAMPTZ NULL
+);
+
+CREATE INDEX IF NOT EXISTS data_exports_user_idx ON data_exports (user_id, created_at);
+CREATE INDEX IF NOT EXISTS data_exports_status_idx ON data_exports (status);
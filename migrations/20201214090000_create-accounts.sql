@@ -0,0 +1,29 @@
+CREATE TABLE IF NOT EXISTS accounts
+(
+    user_id uuid NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    provider VARCHAR NOT NULL,
+    account_id VARCHAR NOT NULL,
+    account_type VARCHAR NULL,
+    display_name VARCHAR NULL,
+    currency VARCHAR(3) NULL,
+    created_at TIMESTAMPTZ NOT NULL default current_timestamp,
+    updated_at TIMESTAMPTZ NOT NULL default current_timestamp,
+    PRIMARY KEY (user_id, provider, account_id)
+);
+
+-- One row per account and day, the last balance fetched that day wins.
+CREATE TABLE IF NOT EXISTS balance_snapshots
+(
+    user_id uuid NOT NULL,
+    provider VARCHAR NOT NULL,
+    account_id VARCHAR NOT NULL,
+    snapshot_date DATE NOT NULL,
+    currency VARCHAR(3) NOT NULL,
+    current NUMERIC(19, 4) NOT NULL,
+    available NUMERIC(19, 4) NULL,
+    overdraft NUMERIC(19, 4) NULL,
+    fetched_at TIMESTAMPTZ NOT NULL default current_timestamp,
+    PRIMARY KEY (user_id, provider, account_id, snapshot_date),
+    FOREIGN KEY (user_id, provider, account_id)
+        REFERENCES accounts (user_id, provider, account_id) ON DELETE CASCADE
+);
@@ -0,0 +1,83 @@
+CREATE TABLE IF NOT EXISTS cards
+(
+    user_id uuid NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    provider VARCHAR NOT NULL,
+    account_id VARCHAR NOT NULL,
+    card_network VARCHAR NULL,
+    card_type VARCHAR NULL,
+    currency VARCHAR(3) NULL,
+    display_name VARCHAR NULL,
+    partial_card_number VARCHAR NULL,
+    name_on_card VARCHAR NULL,
+    valid_from VARCHAR NULL,
+    valid_to VARCHAR NULL,
+    created_at TIMESTAMPTZ NOT NULL default current_timestamp,
+    updated_at TIMESTAMPTZ NOT NULL default current_timestamp,
+    PRIMARY KEY (user_id, provider, account_id)
+);
+
+-- Same shape as transactions, account_id is the id of the card.
+CREATE TABLE IF NOT EXISTS card_transactions
+(
+    id BIGSERIAL PRIMARY KEY,
+    user_id uuid NOT NULL,
+    account_id VARCHAR NOT NULL,
+    provider_transaction_id VARCHAR NOT NULL,
+    booked_at TIMESTAMPTZ NOT NULL,
+    amount NUMERIC(19, 4) NOT NULL,
+    currency VARCHAR(3) NOT NULL,
+    transaction_type VARCHAR NULL,
+    transaction_category VARCHAR NULL,
+    description VARCHAR NULL,
+    raw jsonb NOT NULL,
+    created_at TIMESTAMP NOT NULL default current_timestamp,
+    updated_at TIMESTAMP NOT NULL default current_timestamp,
+    CONSTRAINT card_transactions_user_account_provider_id_key
+        UNIQUE (user_id, account_id, provider_transaction_id)
+);
+
+CREATE INDEX IF NOT EXISTS card_transactions_user_booked_at_idx ON card_transactions (user_id, booked_at);
+
+-- Direct debits and standing orders are replaced by what the provider
+-- returns on every sync, cancelled ones disappear.
+CREATE TABLE IF NOT EXISTS direct_debits
+(
+    id BIGSERIAL PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    provider VARCHAR NOT NULL,
+    account_id VARCHAR NOT NULL,
+    direct_debit_id VARCHAR NOT NULL,
+    name VARCHAR NOT NULL,
+    status VARCHAR NULL,
+    mandated_at TIMESTAMPTZ NULL,
+    previous_payment_at TIMESTAMPTZ NULL,
+    previous_payment_amount NUMERIC(19, 4) NULL,
+    currency VARCHAR(3) NULL,
+    raw jsonb NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL default current_timestamp
+);
+
+CREATE INDEX IF NOT EXISTS direct_debits_user_account_idx ON direct_debits (user_id, provider, account_id);
+
+CREATE TABLE IF NOT EXISTS standing_orders
+(
+    id BIGSERIAL PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    provider VARCHAR NOT NULL,
+    account_id VARCHAR NOT NULL,
+    frequency VARCHAR NULL,
+    status VARCHAR NULL,
+    reference VARCHAR NULL,
+    payee VARCHAR NULL,
+    currency VARCHAR(3) NOT NULL,
+    next_payment_at TIMESTAMPTZ NULL,
+    next_payment_amount NUMERIC(19, 4) NULL,
+    first_payment_at TIMESTAMPTZ NULL,
+    first_payment_amount NUMERIC(19, 4) NULL,
+    final_payment_at TIMESTAMPTZ NULL,
+    final_payment_amount NUMERIC(19, 4) NULL,
+    raw jsonb NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL default current_timestamp
+);
+
+CREATE INDEX IF NOT EXISTS standing_orders_user_account_idx ON standing_orders (user_id, provider, account_id);
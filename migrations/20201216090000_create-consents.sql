@@ -0,0 +1,26 @@
+CREATE TABLE IF NOT EXISTS consents
+(
+    id uuid default uuid_generate_v4() PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    provider VARCHAR NOT NULL,
+    scopes VARCHAR NULL,
+    status VARCHAR NOT NULL default 'active',
+    granted_at TIMESTAMPTZ NOT NULL default current_timestamp,
+    expires_at TIMESTAMPTZ NOT NULL,
+    revoked_at TIMESTAMPTZ NULL,
+    updated_at TIMESTAMPTZ NOT NULL default current_timestamp,
+    CONSTRAINT consents_status_check CHECK (status IN ('active', 'expired', 'revoked'))
+);
+
+-- A user has at most one active consent per provider.
+CREATE UNIQUE INDEX IF NOT EXISTS consents_user_provider_active_idx
+    ON consents (user_id, provider) WHERE status = 'active';
+
+-- Existing links are assumed to have been granted for the usual 90 days
+-- when they were first made.
+INSERT INTO consents (user_id, provider, scopes, granted_at, expires_at)
+SELECT user_id, provider, scopes, created_at AT TIME ZONE 'UTC',
+    (created_at AT TIME ZONE 'UTC') + interval '90 days'
+FROM provider_credentials;
+
+UPDATE consents SET status = 'expired' WHERE expires_at <= current_timestamp;
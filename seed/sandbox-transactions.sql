@@ -0,0 +1,23 @@
+-- Development data only, never run in production.
+--
+-- Before accounts were tracked the API always fetched the same TrueLayer
+-- sandbox account, so the transactions the normalize-transactions migration
+-- set aside on a development database belong to it. This files them back
+-- under that account:
+--
+--   psql "$DATABASE_URL" -f seed/sandbox-transactions.sql
+INSERT INTO transactions
+    (user_id, account_id, raw, provider_transaction_id, booked_at, amount, currency,
+     transaction_type, transaction_category, description, created_at)
+SELECT DISTINCT ON (user_id, provider_transaction_id)
+    user_id, '56c7b029e0f8ec5a2334fb0ffc2fface', raw, provider_transaction_id, booked_at,
+    amount, currency, transaction_type, transaction_category, description, created_at
+FROM legacy_transactions
+WHERE provider_transaction_id IS NOT NULL
+  AND booked_at IS NOT NULL
+  AND amount IS NOT NULL
+  AND currency IS NOT NULL
+ORDER BY user_id, provider_transaction_id, id DESC
+ON CONFLICT (user_id, account_id, provider_transaction_id) DO NOTHING;
+
+DELETE FROM legacy_transactions;
@@ -0,0 +1,56 @@
+use crate::{
+    config::crypto::CryptoService,
+    db::{mfa::MfaRepository, provider::ProviderRepository, user::UserRepository},
+};
+use color_eyre::Result;
+use sqlx::PgPool;
+use std::sync::Arc;
+use tracing::{info, instrument};
+
+pub const ROTATE_KEYS: &str = "rotate-keys";
+
+/// Re-encrypts every encrypted column under the current `ENCRYPTION_KEY`.
+///
+/// Run it after moving the old key to `PREVIOUS_ENCRYPTION_KEYS`; once it
+/// finishes the old key can be dropped from the configuration.
+#[instrument(skip(pool, crypto))]
+pub async fn rotate_keys(pool: PgPool, crypto: &CryptoService) -> Result<()> {
+    let pool = Arc::new(pool);
+
+    let credentials = ProviderRepository::new(pool.clone())
+        .rotate_encryption(crypto)
+        .await?;
+    info!("Re-encrypted {} provider credentials", credentials);
+
+    let codes = UserRepository::new(pool.clone())
+        .rotate_encryption(crypto)
+        .await?;
+    info!("Re-encrypted {} authorization codes", codes);
+
+    let secrets = MfaRepository::new(pool).rotate_encryption(crypto).await?;
+    info!("Re-encrypted {} two-factor secrets", secrets);
+
+    Ok(())
+}
+
+/// Encrypts the values stored in plaintext before encryption at rest was
+/// introduced. Runs on every start and only touches rows that are still in
+/// plaintext, so upgraded databases are left alone.
+#[instrument(skip(pool, crypto))]
+pub async fn encrypt_legacy(pool: PgPool, crypto: &CryptoService) -> Result<()> {
+    let pool = Arc::new(pool);
+
+    let credentials = ProviderRepository::new(pool.clone())
+        .encrypt_legacy(crypto)
+        .await?;
+    if credentials > 0 {
+        info!("Encrypted {} legacy provider credentials", credentials);
+    }
+
+    let codes = UserRepository::new(pool).encrypt_legacy(crypto).await?;
+    if codes > 0 {
+        info!("Encrypted {} legacy authorization codes", codes);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,33 @@
+use chrono::{DateTime, Duration, Utc};
+
+/// How long the consent a user grants at the bank lasts, 90 days for most
+/// Open Banking providers.
+#[derive(Debug, Clone)]
+pub struct ConsentPolicy {
+    pub validity: Duration,
+}
+
+impl ConsentPolicy {
+    pub fn expires_at(&self, granted_at: DateTime<Utc>) -> DateTime<Utc> {
+        granted_at + self.validity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn consent_expires_after_validity() {
+        let policy = ConsentPolicy {
+            validity: Duration::days(90),
+        };
+        let granted_at = Utc.with_ymd_and_hms(2020, 12, 4, 9, 30, 0).unwrap();
+
+        assert_eq!(
+            policy.expires_at(granted_at),
+            Utc.with_ymd_and_hms(2021, 3, 4, 9, 30, 0).unwrap()
+        );
+    }
+}
@@ -1,11 +1,19 @@
+use super::jwt::{JwkSet, JwtKeys};
+use crate::models::role::{Permission, Role};
 use actix_web::web::block;
+use aes_gcm::aead::{Aead, NewAead};
+use aes_gcm::Aes256Gcm;
 use argonautica::{Hasher, Verifier};
 use chrono::{Duration, Utc};
 use color_eyre::Result;
 use eyre::eyre;
 use futures::compat::Future01CompatExt;
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, TokenData, Validation};
+use jsonwebtoken::{TokenData, Validation};
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::convert::TryFrom;
+use std::fmt;
 use std::sync::Arc;
 use tracing::instrument;
 use uuid::Uuid;
@@ -13,28 +21,145 @@ use uuid::Uuid;
 #[derive(Debug, Clone)]
 pub struct CryptoService {
     pub key: Arc<String>,
-    pub jwt_secret: Arc<String>,
+    pub jwt_keys: Arc<JwtKeys>,
+    /// Key encryption keys, the first one is used for new values and the
+    /// others are only kept to decrypt values written before a rotation.
+    pub encryption_keys: Arc<Vec<EncryptionKey>>,
+    pub argon2: Argon2Params,
+}
+
+/// Cost of the Argon2 password hashes. Hashes made with other settings are
+/// upgraded the next time the user logs in.
+#[derive(Debug, Clone, Copy)]
+pub struct Argon2Params {
+    /// Memory size in kibibytes.
+    pub memory_size: u32,
+    pub iterations: u32,
+}
+
+pub const ENCRYPTED_PREFIX: &str = "enc:v1";
+const NONCE_LENGTH: usize = 12;
+
+#[derive(Clone)]
+pub struct EncryptionKey {
+    pub id: String,
+    key: [u8; 32],
+}
+
+impl EncryptionKey {
+    /// Derives a key encryption key from a configured secret. The key id is
+    /// derived from the key itself so rotated keys can be told apart without
+    /// extra configuration.
+    pub fn from_secret(secret: &str) -> Self {
+        let mut key = [0u8; 32];
+        key.copy_from_slice(&Sha256::digest(
+            format!("bank-api-kek:{}", secret).as_bytes(),
+        ));
+        let id = Sha256::digest(&key)
+            .iter()
+            .take(4)
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        EncryptionKey { id, key }
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptionKey")
+            .field("id", &self.id)
+            .finish()
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Claims {
     pub sub: Uuid,
     pub exp: i64,
+    /// Token id, checked against the revocation list on every request.
+    pub jti: Uuid,
+    pub role: Role,
+    pub perms: Vec<Permission>,
     // aud
-    // role
-    // perms
 }
 
+/// Claims carried by the OAuth `state` parameter sent to the provider.
+///
+/// `uid` is used instead of `sub` so a state token can never be accepted as an
+/// access token and vice versa.
+#[derive(Serialize, Deserialize)]
+pub struct StateClaims {
+    pub uid: Uuid,
+    pub nonce: Uuid,
+    pub aud: String,
+    pub exp: i64,
+}
+
+const STATE_AUDIENCE: &str = "oauth-callback";
+const OPAQUE_TOKEN_LENGTH: usize = 32;
+
+/// Claims of the token returned by `/auth` to users with 2FA enabled, only
+/// good to complete the login at `/auth/mfa`.
+#[derive(Serialize, Deserialize)]
+pub struct MfaClaims {
+    pub uid: Uuid,
+    pub aud: String,
+    pub exp: i64,
+}
+
+/// Claims of the link a data export is downloaded with. It expires along
+/// with the archive.
+#[derive(Serialize, Deserialize)]
+pub struct ExportClaims {
+    pub eid: Uuid,
+    pub aud: String,
+    pub exp: i64,
+}
+
+const EXPORT_AUDIENCE: &str = "data-export";
+const MFA_AUDIENCE: &str = "mfa_pending";
+const MFA_TOKEN_EXPIRATION_MINUTES: i64 = 5;
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
 #[derive(Serialize)]
 pub struct Auth {
     pub token: String,
+    pub refresh_token: String,
     pub url: String,
 }
 
+fn cipher(key: &[u8]) -> Result<Aes256Gcm> {
+    let key = <[u8; 32]>::try_from(key).map_err(|_| eyre!("Invalid encryption key length"))?;
+    Ok(Aes256Gcm::new(&key.into()))
+}
+
+fn seal(key: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let mut nonce = [0u8; NONCE_LENGTH];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let ciphertext = cipher(key)?
+        .encrypt(&nonce.into(), plaintext)
+        .map_err(|_| eyre!("Encrypting value"))?;
+    Ok([&nonce[..], &ciphertext[..]].concat())
+}
+
+fn open(key: &[u8], sealed: &[u8]) -> Result<Vec<u8>> {
+    if sealed.len() < NONCE_LENGTH {
+        return Err(eyre!("Encrypted value is too short"));
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LENGTH);
+    let nonce = <[u8; NONCE_LENGTH]>::try_from(nonce)?;
+    cipher(key)?
+        .decrypt(&nonce.into(), ciphertext)
+        .map_err(|_| eyre!("Decrypting value"))
+}
+
 impl CryptoService {
     #[instrument(skip(self, password), err)]
     pub async fn hash_password(&self, password: String) -> Result<String> {
         Hasher::default()
+            .configure_memory_size(self.argon2.memory_size)
+            .configure_iterations(self.argon2.iterations)
             .with_secret_key(&*self.key)
             .with_password(password)
             .hash_non_blocking()
@@ -55,18 +180,36 @@ impl CryptoService {
             .map_err(|err| eyre!("Verifying error: {}", err))
     }
 
+    /// Whether a stored hash was made with other Argon2 settings than the
+    /// configured ones, reading them from its `m=<memory>,t=<iterations>`
+    /// section.
+    pub fn needs_rehash(&self, password_hash: &str) -> bool {
+        let params = match password_hash.split('$').find(|part| part.starts_with("m=")) {
+            Some(params) => params,
+            None => return true,
+        };
+        let param = |name: &str| {
+            params
+                .split(',')
+                .find_map(|param| param.strip_prefix(name)?.strip_prefix('='))
+                .and_then(|value| value.parse::<u32>().ok())
+        };
+        param("m") != Some(self.argon2.memory_size) || param("t") != Some(self.argon2.iterations)
+    }
+
     #[instrument(skip(self))]
-    pub async fn generate_jwt(&self, user_id: Uuid) -> Result<String> {
-        let jwt_key = self.jwt_secret.clone();
+    pub async fn generate_jwt(&self, user_id: Uuid, role: Role) -> Result<String> {
+        let jwt_keys = self.jwt_keys.clone();
         block(move || {
-            let headers = Header::default();
-            let encoding_key = EncodingKey::from_secret(jwt_key.as_bytes());
             let now = Utc::now() + Duration::minutes(60); // Expires in 1 hour
             let claims = Claims {
                 sub: user_id,
                 exp: now.timestamp(),
+                jti: Uuid::new_v4(),
+                role,
+                perms: role.permissions(),
             };
-            encode(&headers, &claims, &encoding_key)
+            jwt_keys.encode(&claims)
         })
         .await
         .map_err(|err| eyre!("Creating jwt token: {}", err))
@@ -74,13 +217,359 @@ impl CryptoService {
 
     #[instrument(skip(self, token))]
     pub async fn check_jwt(&self, token: String) -> Result<TokenData<Claims>> {
-        let jwt_key = self.jwt_secret.clone();
+        let jwt_keys = self.jwt_keys.clone();
+        block(move || jwt_keys.decode::<Claims>(&token, Validation::default()))
+            .await
+            .map_err(|err| eyre!("Verifying jwt token: {}", err))
+    }
+
+    /// Public keys other services can verify our tokens with.
+    pub fn jwks(&self) -> JwkSet {
+        self.jwt_keys.jwks()
+    }
+
+    /// Generates an opaque token, for refresh tokens and the tokens sent by
+    /// email, returning it along with the hash to store.
+    pub fn generate_token(&self) -> (String, String) {
+        let mut bytes = [0u8; OPAQUE_TOKEN_LENGTH];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let token = base64::encode_config(bytes, base64::URL_SAFE_NO_PAD);
+        let hash = self.hash_token(&token);
+        (token, hash)
+    }
+
+    /// Hashes a random token for storage. The tokens are random so a plain
+    /// digest is enough, unlike passwords.
+    pub fn hash_token(&self, token: &str) -> String {
+        Sha256::digest(token.as_bytes())
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    #[instrument(skip(self))]
+    pub async fn generate_state(
+        &self,
+        user_id: Uuid,
+        nonce: Uuid,
+        expires_at: i64,
+    ) -> Result<String> {
+        let jwt_keys = self.jwt_keys.clone();
+        block(move || {
+            let claims = StateClaims {
+                uid: user_id,
+                nonce,
+                aud: STATE_AUDIENCE.to_string(),
+                exp: expires_at,
+            };
+            jwt_keys.encode(&claims)
+        })
+        .await
+        .map_err(|err| eyre!("Creating state token: {}", err))
+    }
+
+    #[instrument(skip(self, state))]
+    pub async fn check_state(&self, state: String) -> Result<TokenData<StateClaims>> {
+        let jwt_keys = self.jwt_keys.clone();
+        block(move || {
+            let mut validation = Validation::default();
+            validation.set_audience(&[STATE_AUDIENCE]);
+            jwt_keys.decode::<StateClaims>(&state, validation)
+        })
+        .await
+        .map_err(|err| eyre!("Verifying state token: {}", err))
+    }
+
+    #[instrument(skip(self))]
+    pub async fn generate_mfa_token(&self, user_id: Uuid) -> Result<String> {
+        let jwt_keys = self.jwt_keys.clone();
+        block(move || {
+            let expires_at = Utc::now() + Duration::minutes(MFA_TOKEN_EXPIRATION_MINUTES);
+            let claims = MfaClaims {
+                uid: user_id,
+                aud: MFA_AUDIENCE.to_string(),
+                exp: expires_at.timestamp(),
+            };
+            jwt_keys.encode(&claims)
+        })
+        .await
+        .map_err(|err| eyre!("Creating mfa token: {}", err))
+    }
+
+    #[instrument(skip(self, token))]
+    pub async fn check_mfa_token(&self, token: String) -> Result<TokenData<MfaClaims>> {
+        let jwt_keys = self.jwt_keys.clone();
+        block(move || {
+            let mut validation = Validation::default();
+            validation.set_audience(&[MFA_AUDIENCE]);
+            jwt_keys.decode::<MfaClaims>(&token, validation)
+        })
+        .await
+        .map_err(|err| eyre!("Verifying mfa token: {}", err))
+    }
+
+    #[instrument(skip(self))]
+    pub async fn generate_export_token(&self, export_id: Uuid, expires_at: i64) -> Result<String> {
+        let jwt_keys = self.jwt_keys.clone();
+        block(move || {
+            let claims = ExportClaims {
+                eid: export_id,
+                aud: EXPORT_AUDIENCE.to_string(),
+                exp: expires_at,
+            };
+            jwt_keys.encode(&claims)
+        })
+        .await
+        .map_err(|err| eyre!("Creating export token: {}", err))
+    }
+
+    #[instrument(skip(self, token))]
+    pub async fn check_export_token(&self, token: String) -> Result<TokenData<ExportClaims>> {
+        let jwt_keys = self.jwt_keys.clone();
         block(move || {
-            let decoding_key = DecodingKey::from_secret(jwt_key.as_bytes());
-            let validation = Validation::default();
-            decode::<Claims>(&token, &decoding_key, &validation)
+            let mut validation = Validation::default();
+            validation.set_audience(&[EXPORT_AUDIENCE]);
+            jwt_keys.decode::<ExportClaims>(&token, validation)
         })
         .await
-        .map_err(|err| eyre!("Verifying jwt token: {}", err))
+        .map_err(|err| eyre!("Verifying export token: {}", err))
+    }
+
+    /// Generates the one-off 2FA recovery codes, returned along with the
+    /// hashes to store.
+    pub fn generate_recovery_codes(&self) -> Vec<(String, String)> {
+        let mut rng = rand::thread_rng();
+        (0..RECOVERY_CODE_COUNT)
+            .map(|_| {
+                let chars: Vec<char> = (0..16)
+                    .map(|_| {
+                        let index = rng.next_u32() as usize % RECOVERY_CODE_ALPHABET.len();
+                        RECOVERY_CODE_ALPHABET[index] as char
+                    })
+                    .collect();
+                let code = chars
+                    .chunks(4)
+                    .map(|chunk| chunk.iter().collect::<String>())
+                    .collect::<Vec<_>>()
+                    .join("-");
+                let hash = self.hash_recovery_code(&code);
+                (code, hash)
+            })
+            .collect()
+    }
+
+    /// Hashes a recovery code, ignoring the case and separators it was typed
+    /// with.
+    pub fn hash_recovery_code(&self, code: &str) -> String {
+        let normalized: String = code
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .map(|c| c.to_ascii_uppercase())
+            .collect();
+        self.hash_token(&normalized)
+    }
+
+    /// Envelope encrypts a value: it is sealed with a fresh data key which is
+    /// itself sealed with the current key encryption key.
+    ///
+    /// The result has the form `enc:v1:<key id>:<sealed data key>:<sealed value>`.
+    #[instrument(skip(self, plaintext), err)]
+    pub fn encrypt(&self, plaintext: &str) -> Result<String> {
+        let kek = self
+            .encryption_keys
+            .first()
+            .ok_or_else(|| eyre!("No encryption key configured"))?;
+
+        let mut data_key = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut data_key);
+
+        let sealed_key = seal(&kek.key, &data_key)?;
+        let sealed_value = seal(&data_key, plaintext.as_bytes())?;
+
+        Ok(format!(
+            "{}:{}:{}:{}",
+            ENCRYPTED_PREFIX,
+            kek.id,
+            base64::encode(&sealed_key),
+            base64::encode(&sealed_value)
+        ))
+    }
+
+    #[instrument(skip(self, encrypted), err)]
+    pub fn decrypt(&self, encrypted: &str) -> Result<String> {
+        let (key_id, sealed_key, sealed_value) = parse_encrypted(encrypted)?;
+        let kek = self
+            .encryption_keys
+            .iter()
+            .find(|key| key.id == key_id)
+            .ok_or_else(|| eyre!("Unknown encryption key {}", key_id))?;
+
+        let data_key = open(&kek.key, &base64::decode(sealed_key)?)?;
+        let plaintext = open(&data_key, &base64::decode(sealed_value)?)?;
+        Ok(String::from_utf8(plaintext)?)
+    }
+
+    pub fn is_encrypted(&self, value: &str) -> bool {
+        value.starts_with(ENCRYPTED_PREFIX)
+    }
+
+    /// Encrypts a value stored in plaintext before encryption was introduced,
+    /// values already encrypted are returned as they are.
+    pub fn encrypt_legacy(&self, value: &str) -> Result<String> {
+        if self.is_encrypted(value) {
+            Ok(value.to_string())
+        } else {
+            self.encrypt(value)
+        }
+    }
+
+    /// Whether a stored value must be rewritten to be under the current key.
+    pub fn needs_rotation(&self, value: &str) -> bool {
+        match (parse_encrypted(value), self.encryption_keys.first()) {
+            (Ok((key_id, _, _)), Some(current)) => key_id != current.id,
+            _ => true,
+        }
+    }
+
+    /// Re-encrypts a stored value under the current key. Values stored in
+    /// plaintext before encryption was introduced are encrypted as they are.
+    pub fn reencrypt(&self, value: &str) -> Result<String> {
+        if self.is_encrypted(value) {
+            self.encrypt(&self.decrypt(value)?)
+        } else {
+            self.encrypt(value)
+        }
+    }
+}
+
+fn parse_encrypted(value: &str) -> Result<(&str, &str, &str)> {
+    let rest = value
+        .strip_prefix(ENCRYPTED_PREFIX)
+        .and_then(|rest| rest.strip_prefix(':'))
+        .ok_or_else(|| eyre!("Value is not encrypted"))?;
+    let mut parts = rest.splitn(3, ':');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(key_id), Some(sealed_key), Some(sealed_value)) => {
+            Ok((key_id, sealed_key, sealed_value))
+        }
+        _ => Err(eyre!("Malformed encrypted value")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn crypto(secrets: &[&str]) -> CryptoService {
+        CryptoService {
+            key: Arc::new("hashing-key".to_string()),
+            jwt_keys: Arc::new(JwtKeys::from_secret("jwt-secret")),
+            encryption_keys: Arc::new(
+                secrets
+                    .iter()
+                    .map(|s| EncryptionKey::from_secret(s))
+                    .collect(),
+            ),
+            argon2: Argon2Params {
+                memory_size: 4096,
+                iterations: 192,
+            },
+        }
+    }
+
+    #[test]
+    fn encryption_round_trips() {
+        let crypto = crypto(&["current"]);
+        let encrypted = crypto.encrypt("access-token").unwrap();
+
+        assert!(crypto.is_encrypted(&encrypted));
+        assert!(!encrypted.contains("access-token"));
+        assert_ne!(encrypted, crypto.encrypt("access-token").unwrap());
+        assert_eq!(crypto.decrypt(&encrypted).unwrap(), "access-token");
+        assert_eq!(crypto.decrypt(&crypto.encrypt("").unwrap()).unwrap(), "");
+    }
+
+    #[test]
+    fn decrypting_with_wrong_key_fails() {
+        let encrypted = crypto(&["current"]).encrypt("access-token").unwrap();
+        assert!(crypto(&["other"]).decrypt(&encrypted).is_err());
+
+        // a key claiming the same id still can't open the value
+        let (key_id, sealed_key, sealed_value) = parse_encrypted(&encrypted).unwrap();
+        let forged = crypto(&["other"]);
+        let forged_value = format!(
+            "{}:{}:{}:{}",
+            ENCRYPTED_PREFIX, forged.encryption_keys[0].id, sealed_key, sealed_value
+        );
+        assert_ne!(key_id, forged.encryption_keys[0].id);
+        assert!(forged.decrypt(&forged_value).is_err());
+    }
+
+    #[test]
+    fn decrypting_tampered_or_legacy_values_fails() {
+        let crypto = crypto(&["current"]);
+        let encrypted = crypto.encrypt("access-token").unwrap();
+
+        let (key_id, sealed_key, sealed_value) = parse_encrypted(&encrypted).unwrap();
+        let mut flipped = base64::decode(sealed_value).unwrap();
+        flipped[NONCE_LENGTH] ^= 1;
+        let tampered = format!(
+            "{}:{}:{}:{}",
+            ENCRYPTED_PREFIX,
+            key_id,
+            sealed_key,
+            base64::encode(&flipped)
+        );
+        assert!(crypto.decrypt(&tampered).is_err());
+
+        assert!(crypto.decrypt("plaintext-token").is_err());
+        assert!(crypto.decrypt("enc:v1:truncated").is_err());
+        assert!(!crypto.is_encrypted("plaintext-token"));
+    }
+
+    #[test]
+    fn legacy_values_are_encrypted_once() {
+        let crypto = crypto(&["current"]);
+
+        let encrypted = crypto.encrypt_legacy("plaintext-token").unwrap();
+        assert!(crypto.is_encrypted(&encrypted));
+        assert_eq!(crypto.decrypt(&encrypted).unwrap(), "plaintext-token");
+        assert_eq!(crypto.encrypt_legacy(&encrypted).unwrap(), encrypted);
+    }
+
+    #[test]
+    fn rotation_moves_values_to_current_key() {
+        let old = crypto(&["old"]);
+        let rotated = crypto(&["new", "old"]);
+        let encrypted = old.encrypt("refresh-token").unwrap();
+
+        assert!(!old.needs_rotation(&encrypted));
+        assert!(rotated.needs_rotation(&encrypted));
+        assert!(rotated.needs_rotation("plaintext-token"));
+        assert_eq!(rotated.decrypt(&encrypted).unwrap(), "refresh-token");
+
+        let reencrypted = rotated.reencrypt(&encrypted).unwrap();
+        assert!(!rotated.needs_rotation(&reencrypted));
+        assert!(old.decrypt(&reencrypted).is_err());
+        assert_eq!(
+            crypto(&["new"]).decrypt(&reencrypted).unwrap(),
+            "refresh-token"
+        );
+
+        let legacy = rotated.reencrypt("plaintext-token").unwrap();
+        assert_eq!(rotated.decrypt(&legacy).unwrap(), "plaintext-token");
+    }
+
+    #[test]
+    fn rehashes_hashes_with_other_settings() {
+        let crypto = crypto(&["current"]);
+        let hash = |params: &str| format!("$argon2id$v=19${}$c29tZXNhbHQ$aGFzaA", params);
+
+        assert!(!crypto.needs_rehash(&hash("m=4096,t=192,p=1")));
+        assert!(crypto.needs_rehash(&hash("m=4096,t=3,p=1")));
+        assert!(crypto.needs_rehash(&hash("m=65536,t=192,p=1")));
+        assert!(crypto.needs_rehash(&hash("m=4096,p=1")));
+        assert!(crypto.needs_rehash("$2b$12$not-an-argon2-hash"));
+        assert!(crypto.needs_rehash(""));
     }
 }
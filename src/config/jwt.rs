@@ -0,0 +1,188 @@
+use color_eyre::Result;
+use eyre::{eyre, WrapErr};
+use jsonwebtoken::{
+    decode, decode_header, encode, errors::Error as JwtError, Algorithm, DecodingKey, EncodingKey,
+    Header, TokenData, Validation,
+};
+use rsa::{PublicKeyParts, RSAPrivateKey, RSAPublicKey};
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256};
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+
+/// Keys used to sign and verify the tokens we issue.
+///
+/// With RSA keys configured tokens are signed with RS256 and the public keys
+/// are published as a JWKS, so other services can verify them without holding
+/// any secret. Without them tokens fall back to HS256 with `JWT_SECRET`.
+pub struct JwtKeys {
+    signing: SigningKey,
+    /// The signing key first, then keys that were rotated out but whose tokens
+    /// may still be alive.
+    verifying: Vec<VerifyingKey>,
+}
+
+struct SigningKey {
+    kid: String,
+    algorithm: Algorithm,
+    key: EncodingKey,
+}
+
+struct VerifyingKey {
+    kid: String,
+    algorithm: Algorithm,
+    key: DecodingKey<'static>,
+    jwk: Option<Jwk>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Jwk {
+    pub kty: &'static str,
+    #[serde(rename = "use")]
+    pub key_use: &'static str,
+    pub alg: &'static str,
+    pub kid: String,
+    pub n: String,
+    pub e: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
+}
+
+/// Derives a key id from the key material, so rotated keys can be told apart
+/// without extra configuration.
+fn key_id(material: &[u8]) -> String {
+    Sha256::digest(material)
+        .iter()
+        .take(8)
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn encode_uint(bytes: Vec<u8>) -> String {
+    base64::encode_config(&bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn rsa_verifying_key(public_key: &RSAPublicKey) -> VerifyingKey {
+    let n = encode_uint(public_key.n().to_bytes_be());
+    let e = encode_uint(public_key.e().to_bytes_be());
+    let kid = key_id(format!("{}.{}", n, e).as_bytes());
+    let key = DecodingKey::from_rsa_components(&n, &e).into_static();
+    VerifyingKey {
+        kid: kid.clone(),
+        algorithm: Algorithm::RS256,
+        key,
+        jwk: Some(Jwk {
+            kty: "RSA",
+            key_use: "sig",
+            alg: "RS256",
+            kid,
+            n,
+            e,
+        }),
+    }
+}
+
+fn read_pem(path: &str) -> Result<(String, rsa::pem::Pem)> {
+    let content = fs::read_to_string(path).wrap_err_with(|| format!("reading {}", path))?;
+    let pem = rsa::pem::parse(&content).map_err(|err| eyre!("Parsing {}: {}", path, err))?;
+    Ok((content, pem))
+}
+
+impl JwtKeys {
+    pub fn from_secret(secret: &str) -> Self {
+        let kid = key_id(format!("bank-api-jwt:{}", secret).as_bytes());
+        JwtKeys {
+            signing: SigningKey {
+                kid: kid.clone(),
+                algorithm: Algorithm::HS256,
+                key: EncodingKey::from_secret(secret.as_bytes()),
+            },
+            verifying: vec![VerifyingKey {
+                kid,
+                algorithm: Algorithm::HS256,
+                key: DecodingKey::from_secret(secret.as_bytes()).into_static(),
+                jwk: None,
+            }],
+        }
+    }
+
+    /// Loads the RSA private key used for signing and the public keys of
+    /// previous signing keys that are still accepted.
+    pub fn from_pem_files(private_key: &str, previous_public_keys: &[&str]) -> Result<Self> {
+        let (content, pem) = read_pem(private_key)?;
+        let signing_key = RSAPrivateKey::try_from(pem)
+            .map_err(|err| eyre!("Parsing {}: {}", private_key, err))?;
+        let encoding_key = EncodingKey::from_rsa_pem(content.as_bytes())?;
+
+        let current = rsa_verifying_key(&signing_key.to_public_key());
+        let mut verifying = vec![current];
+        for path in previous_public_keys {
+            let (_, pem) = read_pem(path)?;
+            let public_key =
+                RSAPublicKey::try_from(pem).map_err(|err| eyre!("Parsing {}: {}", path, err))?;
+            verifying.push(rsa_verifying_key(&public_key));
+        }
+
+        Ok(JwtKeys {
+            signing: SigningKey {
+                kid: verifying[0].kid.clone(),
+                algorithm: Algorithm::RS256,
+                key: encoding_key,
+            },
+            verifying,
+        })
+    }
+
+    pub fn encode<T: Serialize>(&self, claims: &T) -> Result<String, JwtError> {
+        let mut header = Header::new(self.signing.algorithm);
+        header.kid = Some(self.signing.kid.clone());
+        encode(&header, claims, &self.signing.key)
+    }
+
+    /// Verifies a token with the key named by its `kid`. Tokens issued before
+    /// key ids were introduced carry none and are checked against the signing
+    /// key.
+    pub fn decode<T: DeserializeOwned>(
+        &self,
+        token: &str,
+        mut validation: Validation,
+    ) -> Result<TokenData<T>> {
+        let header = decode_header(token)?;
+        let key = match header.kid {
+            Some(kid) => self
+                .verifying
+                .iter()
+                .find(|key| key.kid == kid)
+                .ok_or_else(|| eyre!("Unknown signing key {}", kid))?,
+            None => &self.verifying[0],
+        };
+        validation.algorithms = vec![key.algorithm];
+        Ok(decode::<T>(token, &key.key, &validation)?)
+    }
+
+    /// The public keys, in the JSON Web Key Set format.
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self
+                .verifying
+                .iter()
+                .filter_map(|key| key.jwk.clone())
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Debug for JwtKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kids: Vec<&str> = self.verifying.iter().map(|key| key.kid.as_str()).collect();
+        f.debug_struct("JwtKeys")
+            .field("algorithm", &self.signing.algorithm)
+            .field("kid", &self.signing.kid)
+            .field("verifying", &kids)
+            .finish()
+    }
+}
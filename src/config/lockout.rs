@@ -0,0 +1,171 @@
+use chrono::{DateTime, Duration, Utc};
+use std::net::{IpAddr, SocketAddr};
+
+/// Throttling of failed logins, per username and per client IP.
+///
+/// Each failure delays the next attempt exponentially, starting at one
+/// second, and the key is locked out for `lockout` once it reaches its
+/// maximum number of failures.
+#[derive(Debug, Clone)]
+pub struct LockoutPolicy {
+    pub max_failures: i32,
+    pub max_ip_failures: i32,
+    pub lockout: Duration,
+    /// Proxies whose `X-Forwarded-For` is trusted to name the client. When
+    /// empty the client is the peer of the connection.
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl LockoutPolicy {
+    /// Until when a key is locked after `failures` consecutive failures.
+    pub fn locked_until(
+        &self,
+        failures: i32,
+        max_failures: i32,
+        now: DateTime<Utc>,
+    ) -> DateTime<Utc> {
+        if failures >= max_failures {
+            return now + self.lockout;
+        }
+        let exponent = (failures - 1).clamp(0, 20) as u32;
+        let delay = Duration::seconds(2i64.pow(exponent));
+        now + delay.min(self.lockout)
+    }
+
+    /// The IP failures are counted against. `X-Forwarded-For` is only read
+    /// when the peer is a trusted proxy, and the client is then the
+    /// right-most hop that isn't one, as the hops left of it could have been
+    /// set by the client itself.
+    pub fn client_ip(&self, peer: Option<IpAddr>, forwarded_for: Option<&str>) -> Option<IpAddr> {
+        let mut client = peer?;
+        if !self.trusted_proxies.contains(&client) {
+            return Some(client);
+        }
+
+        let hops = forwarded_for.unwrap_or_default().rsplit(',');
+        for hop in hops.map(str::trim).filter(|hop| !hop.is_empty()) {
+            client = match parse_ip(hop) {
+                Some(ip) => ip,
+                None => break,
+            };
+            if !self.trusted_proxies.contains(&client) {
+                break;
+            }
+        }
+        Some(client)
+    }
+
+    /// Failures older than this are forgotten.
+    pub fn reset_before(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        now - self.lockout
+    }
+}
+
+fn parse_ip(value: &str) -> Option<IpAddr> {
+    value
+        .parse::<IpAddr>()
+        .or_else(|_| value.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn policy(trusted_proxies: &[&str]) -> LockoutPolicy {
+        LockoutPolicy {
+            max_failures: 5,
+            max_ip_failures: 20,
+            lockout: Duration::minutes(15),
+            trusted_proxies: trusted_proxies
+                .iter()
+                .map(|ip| ip.parse().unwrap())
+                .collect(),
+        }
+    }
+
+    fn ip(value: &str) -> Option<IpAddr> {
+        Some(value.parse().unwrap())
+    }
+
+    #[test]
+    fn delays_grow_exponentially() {
+        let policy = policy(&[]);
+        let now = Utc.with_ymd_and_hms(2020, 12, 4, 9, 0, 0).unwrap();
+        let delay = |failures| policy.locked_until(failures, policy.max_failures, now) - now;
+
+        assert_eq!(delay(0), Duration::seconds(1));
+        assert_eq!(delay(1), Duration::seconds(1));
+        assert_eq!(delay(2), Duration::seconds(2));
+        assert_eq!(delay(3), Duration::seconds(4));
+        assert_eq!(delay(4), Duration::seconds(8));
+    }
+
+    #[test]
+    fn locks_out_at_max_failures() {
+        let policy = policy(&[]);
+        let now = Utc.with_ymd_and_hms(2020, 12, 4, 9, 0, 0).unwrap();
+
+        assert_eq!(policy.locked_until(5, 5, now), now + Duration::minutes(15));
+        assert_eq!(policy.locked_until(6, 5, now), now + Duration::minutes(15));
+        // delays never exceed the lockout, however high the maximum
+        assert_eq!(
+            policy.locked_until(19, 20, now),
+            now + Duration::minutes(15)
+        );
+        assert_eq!(
+            policy.locked_until(500, 1000, now),
+            now + Duration::minutes(15)
+        );
+        assert_eq!(policy.reset_before(now), now - Duration::minutes(15));
+    }
+
+    #[test]
+    fn ignores_forwarded_for_without_trusted_proxies() {
+        let policy = policy(&[]);
+        assert_eq!(
+            policy.client_ip(ip("203.0.113.7"), Some("198.51.100.1")),
+            ip("203.0.113.7")
+        );
+        assert_eq!(policy.client_ip(None, Some("198.51.100.1")), None);
+    }
+
+    #[test]
+    fn takes_right_most_untrusted_hop() {
+        let policy = policy(&["10.0.0.1", "10.0.0.2"]);
+
+        assert_eq!(
+            policy.client_ip(ip("10.0.0.1"), Some("1.2.3.4, 198.51.100.1, 10.0.0.2")),
+            ip("198.51.100.1")
+        );
+        assert_eq!(
+            policy.client_ip(ip("10.0.0.1"), Some("198.51.100.1:4711")),
+            ip("198.51.100.1")
+        );
+        assert_eq!(
+            policy.client_ip(ip("10.0.0.1"), Some("[2001:db8::1]:443")),
+            ip("2001:db8::1")
+        );
+        // untrusted peers can't spoof their address
+        assert_eq!(
+            policy.client_ip(ip("203.0.113.7"), Some("10.0.0.2")),
+            ip("203.0.113.7")
+        );
+    }
+
+    #[test]
+    fn stops_at_missing_or_garbled_hops() {
+        let policy = policy(&["10.0.0.1", "10.0.0.2"]);
+
+        assert_eq!(policy.client_ip(ip("10.0.0.1"), None), ip("10.0.0.1"));
+        assert_eq!(
+            policy.client_ip(ip("10.0.0.1"), Some("10.0.0.2")),
+            ip("10.0.0.2")
+        );
+        assert_eq!(
+            policy.client_ip(ip("10.0.0.1"), Some("198.51.100.1, unknown, 10.0.0.2")),
+            ip("10.0.0.2")
+        );
+    }
+}
@@ -1,5 +1,10 @@
+pub mod consent;
 pub mod crypto;
+pub mod jwt;
+pub mod lockout;
 pub mod params;
+pub mod password;
+pub mod totp;
 
 use color_eyre::Result;
 use dotenv::dotenv;
@@ -8,8 +13,23 @@ use eyre::WrapErr;
 use serde::Deserialize;
 use sqlx::postgres::PgPool;
 
-use crypto::CryptoService;
+use crate::export::Exporter;
+use crate::mailer::{self, log::LogMailer, smtp::SmtpMailer, MailService, Mailer};
+use crate::providers::client::{ClientSettings, ProviderClient};
+use crate::providers::{
+    mock::MockProvider, truelayer::TrueLayer, BankingProvider, MOCK, TRUELAYER,
+};
+use crate::purge::Purger;
+use crate::sync::Syncer;
+use chrono::Duration;
+use consent::ConsentPolicy;
+use crypto::{Argon2Params, CryptoService, EncryptionKey};
+use eyre::eyre;
+use jwt::JwtKeys;
+use lockout::LockoutPolicy;
 use params::Params;
+use password::PasswordPolicy;
+use std::path::PathBuf;
 use std::sync::Arc;
 use tracing::{info, instrument};
 use tracing_subscriber::EnvFilter;
@@ -21,12 +41,70 @@ pub struct Config {
     pub database_url: String,
     pub secret_key: String,
     pub jwt_secret: String,
+    /// RSA private key (PEM) to sign tokens with RS256 instead of HS256.
+    pub jwt_private_key_file: Option<String>,
+    /// Comma separated public keys (PEM) of previous signing keys, accepted
+    /// until the tokens they signed have expired.
+    pub jwt_previous_public_key_files: Option<String>,
+    pub encryption_key: String,
+    /// Comma separated keys that were rotated out but may still protect rows.
+    pub previous_encryption_keys: Option<String>,
     pub client_id: String,
     pub client_secret: String,
     pub token_uri: String,
     pub redirect_uri: String,
     pub auth_uri: String,
     pub api_uri: String,
+    /// `truelayer` (default) or `mock` for offline development.
+    pub banking_provider: Option<String>,
+    /// Seconds to connect to the provider, 5 by default.
+    pub provider_connect_timeout_seconds: Option<u64>,
+    /// Seconds a provider request may take in total, 30 by default.
+    pub provider_timeout_seconds: Option<u64>,
+    /// Retries of a failed provider GET, 2 by default.
+    pub provider_max_retries: Option<u32>,
+    /// Delay before the first retry in milliseconds, doubled on each retry,
+    /// 200 by default.
+    pub provider_retry_backoff_ms: Option<u64>,
+    /// Consecutive provider failures that open its circuit, 5 by default.
+    pub provider_breaker_failures: Option<u32>,
+    /// Seconds an open circuit rejects requests, 30 by default.
+    pub provider_breaker_cooldown_seconds: Option<u64>,
+    /// Days a consent granted at the bank lasts, 90 by default.
+    pub consent_days: Option<i64>,
+    /// Failed logins before a username is locked out, 5 by default.
+    pub auth_max_failures: Option<i32>,
+    /// Failed logins before a client IP is locked out, 20 by default.
+    pub auth_max_ip_failures: Option<i32>,
+    pub auth_lockout_minutes: Option<i64>,
+    /// Comma separated IPs of the proxies trusted to set `X-Forwarded-For`.
+    pub trusted_proxies: Option<String>,
+    /// Argon2 memory size in KiB, 4096 by default.
+    pub argon2_memory_kib: Option<u32>,
+    /// Argon2 passes over the memory, 192 by default.
+    pub argon2_iterations: Option<u32>,
+    /// Minimum password length, 8 by default.
+    pub password_min_length: Option<usize>,
+    /// How many character classes (lowercase, uppercase, digits, symbols) a
+    /// password must mix, 3 by default.
+    pub password_min_classes: Option<usize>,
+    /// `log` (default) or `smtp`.
+    pub mailer: Option<String>,
+    pub smtp_host: Option<String>,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    pub mail_from: Option<String>,
+    /// Where the `log` mailer also writes emails to, if set.
+    pub mail_dir: Option<String>,
+    /// Base URL of the API used in links sent by email.
+    pub public_url: Option<String>,
+    /// Days the data of a deleted account is kept before it is purged, 30 by
+    /// default.
+    pub account_purge_days: Option<i64>,
+    /// Hours a data export can be downloaded, 24 by default.
+    pub export_expiration_hours: Option<i64>,
+    pub sync_interval_seconds: Option<i64>,
+    pub sync_jitter_seconds: Option<i64>,
 }
 
 
@@ -60,13 +138,52 @@ impl Config {
     }
 
     #[instrument(skip(self))]
-    pub fn hashing(&self) -> CryptoService {
-        CryptoService {
+    pub fn hashing(&self) -> Result<CryptoService> {
+        Ok(CryptoService {
             key: Arc::new(self.secret_key.clone()),
-            jwt_secret: Arc::new(self.jwt_secret.clone()),
+            jwt_keys: Arc::new(self.jwt_keys()?),
+            encryption_keys: Arc::new(self.encryption_keys()),
+            argon2: Argon2Params {
+                memory_size: self.argon2_memory_kib.unwrap_or(4096),
+                iterations: self.argon2_iterations.unwrap_or(192),
+            },
+        })
+    }
+
+    fn jwt_keys(&self) -> Result<JwtKeys> {
+        match &self.jwt_private_key_file {
+            Some(private_key) => {
+                let previous = self
+                    .jwt_previous_public_key_files
+                    .as_deref()
+                    .unwrap_or_default();
+                let previous: Vec<&str> = previous
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|path| !path.is_empty())
+                    .collect();
+                let keys = JwtKeys::from_pem_files(private_key, &previous)
+                    .context("loading jwt signing keys")?;
+                info!("Signing tokens with {:?}", keys);
+                Ok(keys)
+            }
+            None => Ok(JwtKeys::from_secret(&self.jwt_secret)),
         }
     }
 
+    fn encryption_keys(&self) -> Vec<EncryptionKey> {
+        let previous = self.previous_encryption_keys.as_deref().unwrap_or_default();
+        std::iter::once(self.encryption_key.as_str())
+            .chain(
+                previous
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|key| !key.is_empty()),
+            )
+            .map(EncryptionKey::from_secret)
+            .collect()
+    }
+
     #[instrument(skip(self))]
     pub fn params(&self) -> Params {
         Params {
@@ -75,7 +192,128 @@ impl Config {
             token_uri: Arc::new(self.token_uri.clone()),
             redirect_uri: Arc::new(self.redirect_uri.clone()),
             auth_uri: Arc::new(self.auth_uri.clone()),
-            api_uri: Arc::new(self.auth_uri.clone()),
+            api_uri: Arc::new(self.api_uri.clone()),
+        }
+    }
+
+    #[instrument(skip(self))]
+    pub fn provider_client(&self) -> Result<ProviderClient> {
+        let seconds = std::time::Duration::from_secs;
+        ProviderClient::new(ClientSettings {
+            connect_timeout: seconds(self.provider_connect_timeout_seconds.unwrap_or(5)),
+            timeout: seconds(self.provider_timeout_seconds.unwrap_or(30)),
+            max_retries: self.provider_max_retries.unwrap_or(2),
+            backoff: std::time::Duration::from_millis(
+                self.provider_retry_backoff_ms.unwrap_or(200),
+            ),
+            breaker_failures: self.provider_breaker_failures.unwrap_or(5),
+            breaker_cooldown: seconds(self.provider_breaker_cooldown_seconds.unwrap_or(30)),
+        })
+        .context("creating provider http client")
+    }
+
+    #[instrument(skip(self, client))]
+    pub fn provider(&self, client: ProviderClient) -> Result<Arc<dyn BankingProvider>> {
+        let provider: Arc<dyn BankingProvider> =
+            match self.banking_provider.as_deref().unwrap_or(TRUELAYER) {
+                TRUELAYER => Arc::new(TrueLayer::new(self.params(), client)),
+                MOCK => Arc::new(MockProvider::new(self.params())),
+                other => return Err(eyre!("Unknown banking provider {}", other)),
+            };
+        info!("Using {} banking provider", provider.name());
+        Ok(provider)
+    }
+
+    #[instrument(skip(self))]
+    pub fn consent_policy(&self) -> ConsentPolicy {
+        ConsentPolicy {
+            validity: Duration::days(self.consent_days.unwrap_or(90)),
         }
     }
+
+    #[instrument(skip(self))]
+    pub fn mail_service(&self) -> Result<MailService> {
+        let mailer: Arc<dyn Mailer> = match self.mailer.as_deref().unwrap_or(mailer::LOG) {
+            mailer::LOG => Arc::new(LogMailer::new(self.mail_dir.as_ref().map(PathBuf::from))),
+            mailer::SMTP => {
+                let setting = |value: &Option<String>, name: &str| {
+                    value
+                        .clone()
+                        .ok_or_else(|| eyre!("{} is required by the smtp mailer", name))
+                };
+                Arc::new(SmtpMailer::new(
+                    setting(&self.smtp_host, "SMTP_HOST")?,
+                    setting(&self.smtp_username, "SMTP_USERNAME")?,
+                    setting(&self.smtp_password, "SMTP_PASSWORD")?,
+                    self.mail_from
+                        .clone()
+                        .unwrap_or_else(|| "no-reply@localhost".to_string()),
+                ))
+            }
+            other => return Err(eyre!("Unknown mailer {}", other)),
+        };
+        info!("Using {} mailer", mailer.name());
+
+        Ok(MailService::new(mailer, self.public_url()))
+    }
+
+    fn public_url(&self) -> String {
+        self.public_url
+            .clone()
+            .unwrap_or_else(|| format!("http://{}:{}", self.host, self.port))
+    }
+
+    #[instrument(skip(self))]
+    pub fn lockout(&self) -> Result<LockoutPolicy> {
+        let trusted_proxies = self
+            .trusted_proxies
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|proxy| !proxy.is_empty())
+            .map(|proxy| {
+                proxy
+                    .parse()
+                    .wrap_err_with(|| format!("parsing trusted proxy {}", proxy))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(LockoutPolicy {
+            max_failures: self.auth_max_failures.unwrap_or(5),
+            max_ip_failures: self.auth_max_ip_failures.unwrap_or(20),
+            lockout: Duration::minutes(self.auth_lockout_minutes.unwrap_or(15)),
+            trusted_proxies,
+        })
+    }
+
+    #[instrument(skip(self))]
+    pub fn password_policy(&self) -> PasswordPolicy {
+        PasswordPolicy::new(
+            self.password_min_length.unwrap_or(8),
+            self.password_min_classes.unwrap_or(3),
+        )
+    }
+
+    #[instrument(skip(self, pool))]
+    pub fn exporter(&self, pool: PgPool) -> Exporter {
+        let ttl = Duration::hours(self.export_expiration_hours.unwrap_or(24));
+        Exporter::new(pool, ttl, self.public_url())
+    }
+
+    #[instrument(skip(self, pool))]
+    pub fn purger(&self, pool: PgPool) -> Purger {
+        Purger::new(pool, Duration::days(self.account_purge_days.unwrap_or(30)))
+    }
+
+    #[instrument(skip(self, pool, crypto, provider))]
+    pub fn syncer(
+        &self,
+        pool: PgPool,
+        crypto: CryptoService,
+        provider: Arc<dyn BankingProvider>,
+    ) -> Syncer {
+        let interval = Duration::seconds(self.sync_interval_seconds.unwrap_or(3600));
+        let jitter = Duration::seconds(self.sync_jitter_seconds.unwrap_or(300));
+        Syncer::new(pool, crypto, provider, interval, jitter)
+    }
 }
@@ -0,0 +1,131 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+const COMMON_PASSWORDS: &str = include_str!("common-passwords.txt");
+
+/// Rules new passwords must follow.
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    /// How many of lowercase letters, uppercase letters, digits and symbols a
+    /// password must mix.
+    pub min_classes: usize,
+    common: Arc<HashSet<String>>,
+}
+
+impl PasswordPolicy {
+    pub fn new(min_length: usize, min_classes: usize) -> Self {
+        let common = COMMON_PASSWORDS
+            .lines()
+            .map(|line| line.trim().to_lowercase())
+            .filter(|line| !line.is_empty())
+            .collect();
+        PasswordPolicy {
+            min_length,
+            min_classes,
+            common: Arc::new(common),
+        }
+    }
+
+    /// Checks a password, returning the rules it breaks.
+    pub fn check(&self, password: &str, username: &str, email: &str) -> Result<(), Vec<String>> {
+        let mut problems = Vec::new();
+
+        if password.chars().count() < self.min_length {
+            problems.push(format!("be at least {} characters long", self.min_length));
+        }
+
+        let classes = [
+            password.chars().any(|c| c.is_lowercase()),
+            password.chars().any(|c| c.is_uppercase()),
+            password.chars().any(|c| c.is_ascii_digit()),
+            password.chars().any(|c| !c.is_alphanumeric()),
+        ]
+        .iter()
+        .filter(|present| **present)
+        .count();
+        if classes < self.min_classes {
+            problems.push(format!(
+                "mix at least {} of lowercase letters, uppercase letters, digits and symbols",
+                self.min_classes
+            ));
+        }
+
+        let lowercase = password.to_lowercase();
+        let local_part = email.split('@').next().unwrap_or_default().to_lowercase();
+        let matches_identity = [username.to_lowercase(), email.to_lowercase(), local_part]
+            .iter()
+            .any(|identity| !identity.is_empty() && lowercase.contains(identity.as_str()));
+        if matches_identity {
+            problems.push("not contain the username or email".to_string());
+        }
+
+        if self.common.contains(&lowercase) {
+            problems.push("not be a commonly used password".to_string());
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(password: &str) -> Result<(), Vec<String>> {
+        PasswordPolicy::new(8, 3).check(password, "jdoe", "jane.doe@example.com")
+    }
+
+    #[test]
+    fn accepts_strong_passwords() {
+        assert_eq!(check("Correct-Horse-7"), Ok(()));
+        assert_eq!(check("tr0mbone.staple"), Ok(()));
+    }
+
+    #[test]
+    fn requires_length_and_classes() {
+        assert_eq!(
+            check("Ab1!"),
+            Err(vec!["be at least 8 characters long".to_string()])
+        );
+        assert_eq!(
+            check("alllowercase"),
+            Err(vec![
+                "mix at least 3 of lowercase letters, uppercase letters, digits and symbols"
+                    .to_string()
+            ])
+        );
+        // length is counted in characters, not bytes
+        assert!(PasswordPolicy::new(8, 1).check("ééééééé", "", "").is_err());
+        assert!(PasswordPolicy::new(8, 1).check("éééééééé", "", "").is_ok());
+    }
+
+    #[test]
+    fn rejects_username_and_email() {
+        let identity = vec!["not contain the username or email".to_string()];
+        assert_eq!(check("My-JDoe-Pass1"), Err(identity.clone()));
+        assert_eq!(check("Jane.Doe@example.com1"), Err(identity.clone()));
+        assert_eq!(check("x1-JANE.DOE"), Err(identity));
+        // an empty username doesn't match every password
+        let policy = PasswordPolicy::new(8, 3);
+        assert_eq!(policy.check("Correct-Horse-7", "", ""), Ok(()));
+    }
+
+    #[test]
+    fn rejects_common_passwords() {
+        let policy = PasswordPolicy::new(8, 1);
+        assert_eq!(
+            policy.check("PASSWORD", "jdoe", "jane.doe@example.com"),
+            Err(vec!["not be a commonly used password".to_string()])
+        );
+        assert_eq!(
+            check("Password1"),
+            Err(vec!["not be a commonly used password".to_string()])
+        );
+        assert_eq!(check("12345678").unwrap_err().len(), 2);
+    }
+}
@@ -0,0 +1,194 @@
+use crate::{
+    errors::AppError,
+    models::account::{Account, AccountBalance, Balance, BalanceSnapshot},
+};
+use actix_web::{web::Data, FromRequest};
+use chrono::NaiveDate;
+use color_eyre::Result;
+use futures::future::{ready, Ready};
+use sqlx::postgres::PgQueryAs;
+use sqlx::PgPool;
+use std::{ops::Deref, sync::Arc};
+use tracing::instrument;
+use uuid::Uuid;
+
+const SNAPSHOT_COLUMNS: &str = "provider, account_id, snapshot_date, currency, current, \
+    available, overdraft, fetched_at";
+
+pub struct AccountRepository {
+    pool: Arc<PgPool>,
+}
+
+impl AccountRepository {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        Self { pool }
+    }
+
+    /// Stores the accounts returned by the provider. Accounts it no longer
+    /// returns are kept along with their balance history.
+    #[instrument(skip(self, accounts))]
+    pub async fn save(&self, user_id: Uuid, provider: &str, accounts: &[Account]) -> Result<()> {
+        for account in accounts {
+            sqlx::query(
+                r#"INSERT INTO accounts (user_id, provider, account_id, account_type, display_name, currency)
+                VALUES ($1, $2, $3, $4, $5, $6)
+                ON CONFLICT (user_id, provider, account_id) DO UPDATE SET
+                    account_type = excluded.account_type,
+                    display_name = excluded.display_name,
+                    currency = excluded.currency,
+                    updated_at = current_timestamp"#,
+            )
+            .bind(user_id)
+            .bind(provider)
+            .bind(&account.account_id)
+            .bind(&account.account_type)
+            .bind(&account.display_name)
+            .bind(&account.currency)
+            .execute(&*self.pool)
+            .await?;
+        }
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn list(&self, user_id: Uuid, provider: &str) -> Result<Vec<Account>> {
+        let accounts = sqlx::query_as::<_, Account>(
+            r#"SELECT account_id, account_type, display_name, currency FROM accounts
+            WHERE user_id = $1 AND provider = $2 ORDER BY created_at, account_id"#,
+        )
+        .bind(user_id)
+        .bind(provider)
+        .fetch_all(&*self.pool)
+        .await?;
+        Ok(accounts)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        account_id: &str,
+    ) -> Result<Option<Account>> {
+        let account = sqlx::query_as::<_, Account>(
+            r#"SELECT account_id, account_type, display_name, currency FROM accounts
+            WHERE user_id = $1 AND provider = $2 AND account_id = $3"#,
+        )
+        .bind(user_id)
+        .bind(provider)
+        .bind(account_id)
+        .fetch_optional(&*self.pool)
+        .await?;
+        Ok(account)
+    }
+
+    /// Records the balance as today's snapshot of the account, replacing the
+    /// one fetched earlier today if any.
+    #[instrument(skip(self, balance))]
+    pub async fn save_balance(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        account_id: &str,
+        balance: &Balance,
+    ) -> Result<AccountBalance> {
+        let snapshot = sqlx::query_as::<_, BalanceSnapshot>(&format!(
+            r#"INSERT INTO balance_snapshots
+                (user_id, provider, account_id, snapshot_date, currency, current, available, overdraft)
+            VALUES ($1, $2, $3, (current_timestamp AT TIME ZONE 'UTC')::date, $4, $5, $6, $7)
+            ON CONFLICT (user_id, provider, account_id, snapshot_date) DO UPDATE SET
+                currency = excluded.currency,
+                current = excluded.current,
+                available = excluded.available,
+                overdraft = excluded.overdraft,
+                fetched_at = current_timestamp
+            RETURNING {}"#,
+            SNAPSHOT_COLUMNS
+        ))
+        .bind(user_id)
+        .bind(provider)
+        .bind(account_id)
+        .bind(balance.currency.trim().to_uppercase())
+        .bind(&balance.current)
+        .bind(&balance.available)
+        .bind(&balance.overdraft)
+        .fetch_one(&*self.pool)
+        .await?;
+        Ok(snapshot.into_balance()?)
+    }
+
+    /// Daily balances of an account between two days included, oldest first.
+    #[instrument(skip(self))]
+    pub async fn balance_history(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        account_id: &str,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<AccountBalance>> {
+        let snapshots = sqlx::query_as::<_, BalanceSnapshot>(&format!(
+            r#"SELECT {} FROM balance_snapshots
+            WHERE user_id = $1 AND provider = $2 AND account_id = $3
+            AND snapshot_date >= $4 AND snapshot_date <= $5
+            ORDER BY snapshot_date"#,
+            SNAPSHOT_COLUMNS
+        ))
+        .bind(user_id)
+        .bind(provider)
+        .bind(account_id)
+        .bind(from)
+        .bind(to)
+        .fetch_all(&*self.pool)
+        .await?;
+        snapshots
+            .into_iter()
+            .map(|snapshot| -> Result<AccountBalance> { Ok(snapshot.into_balance()?) })
+            .collect()
+    }
+
+    /// Every account of the user whatever the provider, for data exports.
+    #[instrument(skip(self))]
+    pub async fn all(&self, user_id: Uuid) -> Result<Vec<Account>> {
+        let accounts = sqlx::query_as::<_, Account>(
+            r#"SELECT account_id, account_type, display_name, currency FROM accounts
+            WHERE user_id = $1 ORDER BY provider, created_at, account_id"#,
+        )
+        .bind(user_id)
+        .fetch_all(&*self.pool)
+        .await?;
+        Ok(accounts)
+    }
+
+    /// Every balance snapshot of the user, for data exports.
+    #[instrument(skip(self))]
+    pub async fn snapshots(&self, user_id: Uuid) -> Result<Vec<BalanceSnapshot>> {
+        let snapshots = sqlx::query_as::<_, BalanceSnapshot>(&format!(
+            r#"SELECT {} FROM balance_snapshots WHERE user_id = $1
+            ORDER BY provider, account_id, snapshot_date"#,
+            SNAPSHOT_COLUMNS
+        ))
+        .bind(user_id)
+        .fetch_all(&*self.pool)
+        .await?;
+        Ok(snapshots)
+    }
+}
+
+impl FromRequest for AccountRepository {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+    #[instrument(skip(req, payload))]
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let pool_result = Data::<PgPool>::from_request(req, payload).into_inner();
+
+        match pool_result {
+            Ok(pool) => ready(Ok(AccountRepository::new(pool.deref().clone()))),
+            _ => ready(Err(AppError::NOT_AUTHORIZED.default())),
+        }
+    }
+}
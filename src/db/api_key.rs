@@ -0,0 +1,117 @@
+use crate::{errors::AppError, models::api_key::ApiKey, models::trans::CheckCache};
+use actix_web::{web::Data, FromRequest};
+use chrono::{DateTime, Utc};
+use color_eyre::Result;
+use futures::future::{ready, Ready};
+use sqlx::postgres::PgQueryAs;
+use sqlx::PgPool;
+use std::{ops::Deref, sync::Arc};
+use tracing::instrument;
+use uuid::Uuid;
+
+pub struct ApiKeyRepository {
+    pool: Arc<PgPool>,
+}
+
+impl ApiKeyRepository {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        Self { pool }
+    }
+
+    #[instrument(skip(self, key_hash))]
+    pub async fn create(
+        &self,
+        user_id: Uuid,
+        name: &str,
+        prefix: &str,
+        key_hash: String,
+        scopes: &str,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<ApiKey> {
+        let key = sqlx::query_as::<_, ApiKey>(
+            r#"INSERT INTO api_keys (user_id, name, prefix, key_hash, scopes, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, user_id, name, prefix, scopes, expires_at, last_used_at, created_at"#,
+        )
+        .bind(user_id)
+        .bind(name)
+        .bind(prefix)
+        .bind(key_hash)
+        .bind(scopes)
+        .bind(expires_at)
+        .fetch_one(&*self.pool)
+        .await?;
+        Ok(key)
+    }
+
+    /// Keys of a user that haven't been revoked, newest first.
+    #[instrument(skip(self))]
+    pub async fn list(&self, user_id: Uuid) -> Result<Vec<ApiKey>> {
+        let keys = sqlx::query_as::<_, ApiKey>(
+            r#"SELECT id, user_id, name, prefix, scopes, expires_at, last_used_at, created_at
+            FROM api_keys WHERE user_id = $1 AND revoked_at IS NULL ORDER BY created_at DESC"#,
+        )
+        .bind(user_id)
+        .fetch_all(&*self.pool)
+        .await?;
+        Ok(keys)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn count_active(&self, user_id: Uuid) -> Result<i64> {
+        let count = sqlx::query_as::<_, CheckCache>(
+            r#"SELECT count(*) as results FROM api_keys
+            WHERE user_id = $1 AND revoked_at IS NULL AND (expires_at IS NULL OR expires_at > current_timestamp)"#,
+        )
+        .bind(user_id)
+        .fetch_one(&*self.pool)
+        .await?
+        .results;
+        Ok(count)
+    }
+
+    /// Finds a usable key by its hash and records that it was used.
+    #[instrument(skip(self, key_hash))]
+    pub async fn authenticate(&self, key_hash: &str) -> Result<Option<ApiKey>> {
+        let key = sqlx::query_as::<_, ApiKey>(
+            r#"UPDATE api_keys SET last_used_at = current_timestamp
+            WHERE key_hash = $1 AND revoked_at IS NULL AND (expires_at IS NULL OR expires_at > current_timestamp)
+            RETURNING id, user_id, name, prefix, scopes, expires_at, last_used_at, created_at"#,
+        )
+        .bind(key_hash)
+        .fetch_optional(&*self.pool)
+        .await?;
+        Ok(key)
+    }
+
+    /// Revokes a key of the user, returns whether there was one to revoke.
+    #[instrument(skip(self))]
+    pub async fn revoke(&self, user_id: Uuid, id: Uuid) -> Result<bool> {
+        let revoked = sqlx::query(
+            "UPDATE api_keys SET revoked_at = current_timestamp WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL",
+        )
+        .bind(id)
+        .bind(user_id)
+        .execute(&*self.pool)
+        .await?;
+        Ok(revoked > 0)
+    }
+}
+
+impl FromRequest for ApiKeyRepository {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+    #[instrument(skip(req, payload))]
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let pool_result = Data::<PgPool>::from_request(req, payload).into_inner();
+
+        match pool_result {
+            Ok(pool) => ready(Ok(ApiKeyRepository::new(pool.deref().clone()))),
+            _ => ready(Err(AppError::NOT_AUTHORIZED.default())),
+        }
+    }
+}
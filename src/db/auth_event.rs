@@ -0,0 +1,169 @@
+use crate::{
+    errors::AppError,
+    models::auth_event::{AuthEvent, AuthEventsCursor, LoginAttempt, Throttle},
+    models::page::{encode_cursor, Page, PageQuery, DEFAULT_LIMIT},
+    models::trans::CheckCache,
+};
+use actix_web::{web::Data, FromRequest};
+use chrono::{DateTime, Utc};
+use color_eyre::Result;
+use futures::future::{ready, Ready};
+use sqlx::postgres::PgQueryAs;
+use sqlx::PgPool;
+use std::{ops::Deref, sync::Arc};
+use tracing::instrument;
+use uuid::Uuid;
+
+pub struct AuthEventRepository {
+    pool: Arc<PgPool>,
+}
+
+impl AuthEventRepository {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        Self { pool }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn record(
+        &self,
+        user_id: Option<Uuid>,
+        attempt: &LoginAttempt,
+        event: &str,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO auth_events (user_id, username, event, ip, user_agent) VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(user_id)
+        .bind(attempt.username.clone())
+        .bind(event)
+        .bind(attempt.ip.clone())
+        .bind(attempt.user_agent.clone())
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Events of a user, newest first.
+    #[instrument(skip(self))]
+    pub async fn list(
+        &self,
+        user_id: Uuid,
+        page: &PageQuery,
+        cursor: Option<AuthEventsCursor>,
+    ) -> Result<Page<AuthEvent>> {
+        let limit = page.limit.unwrap_or(DEFAULT_LIMIT);
+        let mut results = sqlx::query_as::<_, AuthEvent>(
+            r#"SELECT id, event, ip, user_agent, created_at FROM auth_events
+            WHERE user_id = $1 AND ($2::BIGINT IS NULL OR id < $2)
+            ORDER BY id DESC LIMIT $3"#,
+        )
+        .bind(user_id)
+        .bind(cursor.map(|cursor| cursor.id))
+        .bind(limit + 1)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        // One extra row is fetched to know whether there is a next page
+        let has_more = results.len() as i64 > limit;
+        results.truncate(limit as usize);
+        let next_cursor = match results.last() {
+            Some(last) if has_more => Some(encode_cursor(&AuthEventsCursor { id: last.id })),
+            _ => None,
+        };
+
+        let total_count = sqlx::query_as::<_, CheckCache>(
+            "SELECT count(*) as results FROM auth_events WHERE user_id = $1",
+        )
+        .bind(user_id)
+        .fetch_one(&*self.pool)
+        .await?
+        .results;
+
+        Ok(Page {
+            results,
+            next_cursor,
+            total_count,
+        })
+    }
+
+    /// Every event of a user, oldest first, for data exports.
+    #[instrument(skip(self))]
+    pub async fn all(&self, user_id: Uuid) -> Result<Vec<AuthEvent>> {
+        let events = sqlx::query_as::<_, AuthEvent>(
+            "SELECT id, event, ip, user_agent, created_at FROM auth_events WHERE user_id = $1 ORDER BY id",
+        )
+        .bind(user_id)
+        .fetch_all(&*self.pool)
+        .await?;
+        Ok(events)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn throttle(&self, key: &str) -> Result<Option<Throttle>> {
+        let throttle = sqlx::query_as::<_, Throttle>(
+            "SELECT failures, locked_until FROM auth_throttles WHERE key = $1",
+        )
+        .bind(key)
+        .fetch_optional(&*self.pool)
+        .await?;
+        Ok(throttle)
+    }
+
+    /// Counts a failed attempt. When the previous failure is older than
+    /// `reset_before` counting starts over.
+    #[instrument(skip(self))]
+    pub async fn record_failure(&self, key: &str, reset_before: DateTime<Utc>) -> Result<Throttle> {
+        let throttle = sqlx::query_as::<_, Throttle>(
+            r#"INSERT INTO auth_throttles (key, failures, last_failure_at) VALUES ($1, 1, current_timestamp)
+            ON CONFLICT (key) DO UPDATE SET
+                failures = CASE
+                    WHEN auth_throttles.last_failure_at < $2 THEN 1
+                    ELSE auth_throttles.failures + 1
+                END,
+                last_failure_at = current_timestamp
+            RETURNING failures, locked_until"#,
+        )
+        .bind(key)
+        .bind(reset_before)
+        .fetch_one(&*self.pool)
+        .await?;
+        Ok(throttle)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn lock(&self, key: &str, locked_until: DateTime<Utc>) -> Result<()> {
+        sqlx::query("UPDATE auth_throttles SET locked_until = $2 WHERE key = $1")
+            .bind(key)
+            .bind(locked_until)
+            .execute(&*self.pool)
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn clear(&self, key: &str) -> Result<()> {
+        sqlx::query("DELETE FROM auth_throttles WHERE key = $1")
+            .bind(key)
+            .execute(&*self.pool)
+            .await?;
+        Ok(())
+    }
+}
+
+impl FromRequest for AuthEventRepository {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+    #[instrument(skip(req, payload))]
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let pool_result = Data::<PgPool>::from_request(req, payload).into_inner();
+
+        match pool_result {
+            Ok(pool) => ready(Ok(AuthEventRepository::new(pool.deref().clone()))),
+            _ => ready(Err(AppError::NOT_AUTHORIZED.default())),
+        }
+    }
+}
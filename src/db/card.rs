@@ -0,0 +1,118 @@
+use crate::{errors::AppError, models::card::Card};
+use actix_web::{web::Data, FromRequest};
+use color_eyre::Result;
+use futures::future::{ready, Ready};
+use sqlx::postgres::PgQueryAs;
+use sqlx::PgPool;
+use std::{ops::Deref, sync::Arc};
+use tracing::instrument;
+use uuid::Uuid;
+
+const CARD_COLUMNS: &str = "account_id, card_network, card_type, currency, display_name, \
+    partial_card_number, name_on_card, valid_from, valid_to";
+
+pub struct CardRepository {
+    pool: Arc<PgPool>,
+}
+
+impl CardRepository {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        Self { pool }
+    }
+
+    /// Stores the cards returned by the provider. Cards it no longer returns
+    /// are kept along with their transactions.
+    #[instrument(skip(self, cards))]
+    pub async fn save(&self, user_id: Uuid, provider: &str, cards: &[Card]) -> Result<()> {
+        for card in cards {
+            sqlx::query(
+                r#"INSERT INTO cards (user_id, provider, account_id, card_network, card_type, currency,
+                    display_name, partial_card_number, name_on_card, valid_from, valid_to)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+                ON CONFLICT (user_id, provider, account_id) DO UPDATE SET
+                    card_network = excluded.card_network,
+                    card_type = excluded.card_type,
+                    currency = excluded.currency,
+                    display_name = excluded.display_name,
+                    partial_card_number = excluded.partial_card_number,
+                    name_on_card = excluded.name_on_card,
+                    valid_from = excluded.valid_from,
+                    valid_to = excluded.valid_to,
+                    updated_at = current_timestamp"#,
+            )
+            .bind(user_id)
+            .bind(provider)
+            .bind(&card.account_id)
+            .bind(&card.card_network)
+            .bind(&card.card_type)
+            .bind(&card.currency)
+            .bind(&card.display_name)
+            .bind(&card.partial_card_number)
+            .bind(&card.name_on_card)
+            .bind(&card.valid_from)
+            .bind(&card.valid_to)
+            .execute(&*self.pool)
+            .await?;
+        }
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn list(&self, user_id: Uuid, provider: &str) -> Result<Vec<Card>> {
+        let cards = sqlx::query_as::<_, Card>(&format!(
+            r#"SELECT {} FROM cards WHERE user_id = $1 AND provider = $2
+            ORDER BY created_at, account_id"#,
+            CARD_COLUMNS
+        ))
+        .bind(user_id)
+        .bind(provider)
+        .fetch_all(&*self.pool)
+        .await?;
+        Ok(cards)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find(&self, user_id: Uuid, provider: &str, card_id: &str) -> Result<Option<Card>> {
+        let card = sqlx::query_as::<_, Card>(&format!(
+            "SELECT {} FROM cards WHERE user_id = $1 AND provider = $2 AND account_id = $3",
+            CARD_COLUMNS
+        ))
+        .bind(user_id)
+        .bind(provider)
+        .bind(card_id)
+        .fetch_optional(&*self.pool)
+        .await?;
+        Ok(card)
+    }
+
+    /// Every card of the user whatever the provider, for data exports.
+    #[instrument(skip(self))]
+    pub async fn all(&self, user_id: Uuid) -> Result<Vec<Card>> {
+        let cards = sqlx::query_as::<_, Card>(&format!(
+            "SELECT {} FROM cards WHERE user_id = $1 ORDER BY provider, created_at, account_id",
+            CARD_COLUMNS
+        ))
+        .bind(user_id)
+        .fetch_all(&*self.pool)
+        .await?;
+        Ok(cards)
+    }
+}
+
+impl FromRequest for CardRepository {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+    #[instrument(skip(req, payload))]
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let pool_result = Data::<PgPool>::from_request(req, payload).into_inner();
+
+        match pool_result {
+            Ok(pool) => ready(Ok(CardRepository::new(pool.deref().clone()))),
+            _ => ready(Err(AppError::NOT_AUTHORIZED.default())),
+        }
+    }
+}
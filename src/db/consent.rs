@@ -0,0 +1,200 @@
+use crate::{
+    errors::AppError,
+    models::consent::{Consent, CONSENT_ACTIVE, CONSENT_EXPIRED, CONSENT_REVOKED},
+};
+use actix_web::{web::Data, FromRequest};
+use chrono::{DateTime, Utc};
+use color_eyre::Result;
+use futures::future::{ready, Ready};
+use sqlx::postgres::PgQueryAs;
+use sqlx::PgPool;
+use std::{ops::Deref, sync::Arc};
+use tracing::instrument;
+use uuid::Uuid;
+
+const CONSENT_COLUMNS: &str =
+    "id, provider, scopes, status, granted_at, expires_at, revoked_at, updated_at";
+
+pub struct ConsentRepository {
+    pool: Arc<PgPool>,
+}
+
+impl ConsentRepository {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        Self { pool }
+    }
+
+    /// Records a new consent, the active one for the same provider is
+    /// replaced and marked as revoked.
+    #[instrument(skip(self))]
+    pub async fn grant(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        scopes: Option<&str>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Consent> {
+        self.expire_lapsed(user_id).await?;
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            r#"UPDATE consents
+            SET status = $1, revoked_at = current_timestamp, updated_at = current_timestamp
+            WHERE user_id = $2 AND provider = $3 AND status = $4"#,
+        )
+        .bind(CONSENT_REVOKED)
+        .bind(user_id)
+        .bind(provider)
+        .bind(CONSENT_ACTIVE)
+        .execute(&mut tx)
+        .await?;
+
+        let consent = sqlx::query_as::<_, Consent>(&format!(
+            r#"INSERT INTO consents (user_id, provider, scopes, status, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING {}"#,
+            CONSENT_COLUMNS
+        ))
+        .bind(user_id)
+        .bind(provider)
+        .bind(scopes)
+        .bind(CONSENT_ACTIVE)
+        .bind(expires_at)
+        .fetch_one(&mut tx)
+        .await?;
+        tx.commit().await?;
+        Ok(consent)
+    }
+
+    /// Marks the active consents of the user past their expiry as expired,
+    /// before they are changed so lapsed consents aren't recorded as revoked.
+    #[instrument(skip(self))]
+    async fn expire_lapsed(&self, user_id: Uuid) -> Result<()> {
+        sqlx::query(
+            r#"UPDATE consents SET status = $1, updated_at = current_timestamp
+            WHERE user_id = $2 AND status = $3 AND expires_at <= current_timestamp"#,
+        )
+        .bind(CONSENT_EXPIRED)
+        .bind(user_id)
+        .bind(CONSENT_ACTIVE)
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Marks the active consent for a provider as expired, when the provider
+    /// stopped honoring it before the expected expiry. Returns whether there
+    /// was one.
+    #[instrument(skip(self))]
+    pub async fn expire(&self, user_id: Uuid, provider: &str) -> Result<bool> {
+        let expired = sqlx::query(
+            r#"UPDATE consents SET status = $1, expires_at = least(expires_at, current_timestamp),
+                updated_at = current_timestamp
+            WHERE user_id = $2 AND provider = $3 AND status = $4"#,
+        )
+        .bind(CONSENT_EXPIRED)
+        .bind(user_id)
+        .bind(provider)
+        .bind(CONSENT_ACTIVE)
+        .execute(&*self.pool)
+        .await?;
+        Ok(expired > 0)
+    }
+
+    /// Every consent of the user, newest first, with lapsed ones reported as
+    /// expired.
+    #[instrument(skip(self))]
+    pub async fn list(&self, user_id: Uuid) -> Result<Vec<Consent>> {
+        let consents = sqlx::query_as::<_, Consent>(&format!(
+            "SELECT {} FROM consents WHERE user_id = $1 ORDER BY granted_at DESC",
+            CONSENT_COLUMNS
+        ))
+        .bind(user_id)
+        .fetch_all(&*self.pool)
+        .await?;
+        let now = Utc::now();
+        Ok(consents
+            .into_iter()
+            .map(|consent| consent.at(now))
+            .collect())
+    }
+
+    /// The last consent granted for a provider, whatever its status.
+    #[instrument(skip(self))]
+    pub async fn latest(&self, user_id: Uuid, provider: &str) -> Result<Option<Consent>> {
+        let consent = sqlx::query_as::<_, Consent>(&format!(
+            r#"SELECT {} FROM consents WHERE user_id = $1 AND provider = $2
+            ORDER BY granted_at DESC LIMIT 1"#,
+            CONSENT_COLUMNS
+        ))
+        .bind(user_id)
+        .bind(provider)
+        .fetch_optional(&*self.pool)
+        .await?;
+        Ok(consent.map(|consent| consent.at(Utc::now())))
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find(&self, user_id: Uuid, id: Uuid) -> Result<Option<Consent>> {
+        let consent = sqlx::query_as::<_, Consent>(&format!(
+            "SELECT {} FROM consents WHERE id = $1 AND user_id = $2",
+            CONSENT_COLUMNS
+        ))
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(&*self.pool)
+        .await?;
+        Ok(consent.map(|consent| consent.at(Utc::now())))
+    }
+
+    #[instrument(skip(self))]
+    pub async fn revoke(&self, user_id: Uuid, id: Uuid) -> Result<()> {
+        self.expire_lapsed(user_id).await?;
+        sqlx::query(
+            r#"UPDATE consents
+            SET status = $1, revoked_at = current_timestamp, updated_at = current_timestamp
+            WHERE id = $2 AND user_id = $3 AND status = $4"#,
+        )
+        .bind(CONSENT_REVOKED)
+        .bind(id)
+        .bind(user_id)
+        .bind(CONSENT_ACTIVE)
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Revokes every active consent of the user, when the account is deleted.
+    #[instrument(skip(self))]
+    pub async fn revoke_all(&self, user_id: Uuid) -> Result<()> {
+        self.expire_lapsed(user_id).await?;
+        sqlx::query(
+            r#"UPDATE consents
+            SET status = $1, revoked_at = current_timestamp, updated_at = current_timestamp
+            WHERE user_id = $2 AND status = $3"#,
+        )
+        .bind(CONSENT_REVOKED)
+        .bind(user_id)
+        .bind(CONSENT_ACTIVE)
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+impl FromRequest for ConsentRepository {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+    #[instrument(skip(req, payload))]
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let pool_result = Data::<PgPool>::from_request(req, payload).into_inner();
+
+        match pool_result {
+            Ok(pool) => ready(Ok(ConsentRepository::new(pool.deref().clone()))),
+            _ => ready(Err(AppError::NOT_AUTHORIZED.default())),
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use crate::{
+    errors::AppError,
+    models::export::{
+        DataExport, EXPORT_EXPIRED, EXPORT_FAILED, EXPORT_PENDING, EXPORT_READY, EXPORT_RUNNING,
+    },
+};
+use actix_web::{web::Data, FromRequest};
+use chrono::{DateTime, Utc};
+use color_eyre::Result;
+use futures::future::{ready, Ready};
+use sqlx::postgres::PgQueryAs;
+use sqlx::PgPool;
+use std::{ops::Deref, sync::Arc};
+use tracing::instrument;
+use uuid::Uuid;
+
+const EXPORT_COLUMNS: &str =
+    "id, user_id, status, error, created_at, started_at, completed_at, expires_at";
+
+#[derive(sqlx::FromRow)]
+struct Archive {
+    archive: Vec<u8>,
+}
+
+pub struct ExportRepository {
+    pool: Arc<PgPool>,
+}
+
+impl ExportRepository {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        Self { pool }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn create(&self, user_id: Uuid) -> Result<DataExport> {
+        let export = sqlx::query_as::<_, DataExport>(&format!(
+            "INSERT INTO data_exports (user_id) VALUES ($1) RETURNING {}",
+            EXPORT_COLUMNS
+        ))
+        .bind(user_id)
+        .fetch_one(&*self.pool)
+        .await?;
+        Ok(export)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn latest(&self, user_id: Uuid) -> Result<Option<DataExport>> {
+        let export = sqlx::query_as::<_, DataExport>(&format!(
+            "SELECT {} FROM data_exports WHERE user_id = $1 ORDER BY created_at DESC LIMIT 1",
+            EXPORT_COLUMNS
+        ))
+        .bind(user_id)
+        .fetch_optional(&*self.pool)
+        .await?;
+        Ok(export)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find(&self, user_id: Uuid, id: Uuid) -> Result<Option<DataExport>> {
+        let export = sqlx::query_as::<_, DataExport>(&format!(
+            "SELECT {} FROM data_exports WHERE id = $1 AND user_id = $2",
+            EXPORT_COLUMNS
+        ))
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(&*self.pool)
+        .await?;
+        Ok(export)
+    }
+
+    /// Picks the oldest pending export and marks it running. Exports left
+    /// running for longer than `stale_before` by a worker that died are picked
+    /// again.
+    #[instrument(skip(self))]
+    pub async fn claim_next(&self, stale_before: DateTime<Utc>) -> Result<Option<DataExport>> {
+        let export = sqlx::query_as::<_, DataExport>(&format!(
+            r#"UPDATE data_exports SET status = $1, started_at = current_timestamp
+            WHERE id = (
+                SELECT id FROM data_exports
+                WHERE status = $2 OR (status = $1 AND started_at < $3)
+                ORDER BY created_at
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING {}"#,
+            EXPORT_COLUMNS
+        ))
+        .bind(EXPORT_RUNNING)
+        .bind(EXPORT_PENDING)
+        .bind(stale_before)
+        .fetch_optional(&*self.pool)
+        .await?;
+        Ok(export)
+    }
+
+    #[instrument(skip(self, archive))]
+    pub async fn complete(
+        &self,
+        id: Uuid,
+        archive: Vec<u8>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"UPDATE data_exports SET status = $1, archive = $2, completed_at = current_timestamp,
+                expires_at = $3
+            WHERE id = $4"#,
+        )
+        .bind(EXPORT_READY)
+        .bind(archive)
+        .bind(expires_at)
+        .bind(id)
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn fail(&self, id: Uuid, error: String) -> Result<()> {
+        sqlx::query(
+            "UPDATE data_exports SET status = $1, error = $2, completed_at = current_timestamp WHERE id = $3",
+        )
+        .bind(EXPORT_FAILED)
+        .bind(error)
+        .bind(id)
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// The archive of a ready export, `None` once it has expired.
+    #[instrument(skip(self))]
+    pub async fn archive(&self, id: Uuid) -> Result<Option<Vec<u8>>> {
+        let archive = sqlx::query_as::<_, Archive>(
+            r#"SELECT archive FROM data_exports
+            WHERE id = $1 AND status = $2 AND expires_at > current_timestamp"#,
+        )
+        .bind(id)
+        .bind(EXPORT_READY)
+        .fetch_optional(&*self.pool)
+        .await?;
+        Ok(archive.map(|archive| archive.archive))
+    }
+
+    /// Drops the archives past their expiration and returns how many.
+    #[instrument(skip(self))]
+    pub async fn expire(&self) -> Result<u64> {
+        let expired = sqlx::query(
+            r#"UPDATE data_exports SET status = $1, archive = NULL
+            WHERE status = $2 AND expires_at <= current_timestamp"#,
+        )
+        .bind(EXPORT_EXPIRED)
+        .bind(EXPORT_READY)
+        .execute(&*self.pool)
+        .await?;
+        Ok(expired)
+    }
+}
+
+impl FromRequest for ExportRepository {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+    #[instrument(skip(req, payload))]
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let pool_result = Data::<PgPool>::from_request(req, payload).into_inner();
+
+        match pool_result {
+            Ok(pool) => ready(Ok(ExportRepository::new(pool.deref().clone()))),
+            _ => ready(Err(AppError::NOT_AUTHORIZED.default())),
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use crate::{
+    config::crypto::CryptoService, errors::AppError, models::mfa::UserMfa,
+    models::trans::CheckCache,
+};
+use actix_web::{web::Data, FromRequest};
+use color_eyre::Result;
+use futures::future::{ready, Ready};
+use sqlx::postgres::PgQueryAs;
+use sqlx::PgPool;
+use std::{ops::Deref, sync::Arc};
+use tracing::instrument;
+use uuid::Uuid;
+
+pub struct MfaRepository {
+    pool: Arc<PgPool>,
+}
+
+impl MfaRepository {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        Self { pool }
+    }
+
+    /// The TOTP settings of the user, with the secret decrypted.
+    #[instrument(skip(self, crypto))]
+    pub async fn find(&self, user_id: Uuid, crypto: &CryptoService) -> Result<Option<UserMfa>> {
+        let mfa = sqlx::query_as::<_, UserMfa>(
+            "SELECT user_id, secret, enabled_at FROM user_mfa WHERE user_id = $1",
+        )
+        .bind(user_id)
+        .fetch_optional(&*self.pool)
+        .await?;
+        mfa.map(|mfa| decrypt_secret(mfa, crypto)).transpose()
+    }
+
+    /// Whether the user confirmed 2FA, without reading the secret.
+    #[instrument(skip(self))]
+    pub async fn enabled(&self, user_id: Uuid) -> Result<bool> {
+        let enabled = sqlx::query_as::<_, CheckCache>(
+            "SELECT count(*) as results FROM user_mfa WHERE user_id = $1 AND enabled_at IS NOT NULL",
+        )
+        .bind(user_id)
+        .fetch_one(&*self.pool)
+        .await?
+        .results;
+        Ok(enabled > 0)
+    }
+
+    /// Stores a new secret waiting for confirmation, encrypted. Returns
+    /// `None` when 2FA is already enabled, in which case the secret is left
+    /// untouched.
+    #[instrument(skip(self, secret, crypto))]
+    pub async fn save_pending(
+        &self,
+        user_id: Uuid,
+        secret: &str,
+        crypto: &CryptoService,
+    ) -> Result<Option<UserMfa>> {
+        let secret = crypto.encrypt(secret)?;
+        let mfa = sqlx::query_as::<_, UserMfa>(
+            r#"INSERT INTO user_mfa (user_id, secret) VALUES ($1, $2)
+            ON CONFLICT (user_id) DO UPDATE
+            SET secret = EXCLUDED.secret, last_used_step = NULL, created_at = current_timestamp
+            WHERE user_mfa.enabled_at IS NULL
+            RETURNING user_id, secret, enabled_at"#,
+        )
+        .bind(user_id)
+        .bind(secret)
+        .fetch_optional(&*self.pool)
+        .await?;
+        mfa.map(|mfa| decrypt_secret(mfa, crypto)).transpose()
+    }
+
+    /// Enables 2FA and replaces the recovery codes.
+    #[instrument(skip(self, code_hashes))]
+    pub async fn enable(&self, user_id: Uuid, code_hashes: Vec<String>) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("UPDATE user_mfa SET enabled_at = current_timestamp WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut tx)
+            .await?;
+
+        sqlx::query("DELETE FROM mfa_recovery_codes WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut tx)
+            .await?;
+
+        for code_hash in code_hashes {
+            sqlx::query("INSERT INTO mfa_recovery_codes (user_id, code_hash) VALUES ($1, $2)")
+                .bind(user_id)
+                .bind(code_hash)
+                .execute(&mut tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Records the time step of an accepted code, `false` if that step or a
+    /// later one was already used so a code can't be replayed.
+    #[instrument(skip(self))]
+    pub async fn use_step(&self, user_id: Uuid, step: i64) -> Result<bool> {
+        let updated = sqlx::query(
+            "UPDATE user_mfa SET last_used_step = $2 WHERE user_id = $1 AND (last_used_step IS NULL OR last_used_step < $2)",
+        )
+        .bind(user_id)
+        .bind(step)
+        .execute(&*self.pool)
+        .await?;
+        Ok(updated > 0)
+    }
+
+    #[instrument(skip(self, code_hash))]
+    pub async fn use_recovery_code(&self, user_id: Uuid, code_hash: String) -> Result<bool> {
+        let updated = sqlx::query(
+            "UPDATE mfa_recovery_codes SET used_at = current_timestamp WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL",
+        )
+        .bind(user_id)
+        .bind(code_hash)
+        .execute(&*self.pool)
+        .await?;
+        Ok(updated > 0)
+    }
+
+    /// Re-encrypts every stored TOTP secret under the current encryption key
+    /// and returns how many rows were rewritten.
+    #[instrument(skip(self, crypto))]
+    pub async fn rotate_encryption(&self, crypto: &CryptoService) -> Result<u64> {
+        let rows = sqlx::query_as::<_, UserMfa>("SELECT user_id, secret, enabled_at FROM user_mfa")
+            .fetch_all(&*self.pool)
+            .await?;
+
+        let mut rotated = 0;
+        for row in rows {
+            if !crypto.needs_rotation(&row.secret) {
+                continue;
+            }
+
+            // Only rewrite the row if the secret was not replaced in the meantime.
+            rotated +=
+                sqlx::query("UPDATE user_mfa SET secret = $1 WHERE user_id = $2 AND secret = $3")
+                    .bind(crypto.reencrypt(&row.secret)?)
+                    .bind(row.user_id)
+                    .bind(&row.secret)
+                    .execute(&*self.pool)
+                    .await?;
+        }
+        Ok(rotated)
+    }
+}
+
+fn decrypt_secret(mut mfa: UserMfa, crypto: &CryptoService) -> Result<UserMfa> {
+    mfa.secret = crypto.decrypt(&mfa.secret)?;
+    Ok(mfa)
+}
+
+impl FromRequest for MfaRepository {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+    #[instrument(skip(req, payload))]
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let pool_result = Data::<PgPool>::from_request(req, payload).into_inner();
+
+        match pool_result {
+            Ok(pool) => ready(Ok(MfaRepository::new(pool.deref().clone()))),
+            _ => ready(Err(AppError::NOT_AUTHORIZED.default())),
+        }
+    }
+}
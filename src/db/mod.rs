@@ -1,4 +1,16 @@
+pub mod account;
+pub mod api_key;
+pub mod auth_event;
+pub mod card;
+pub mod consent;
+pub mod export;
+pub mod mfa;
+pub mod payment;
+pub mod provider;
+pub mod sync;
+pub mod token;
+pub mod trans;
 pub mod user;
-//pub mod trans;
+pub mod user_token;
 
 pub const UNIQUE_VIOLATION_CODE: &str = "23505";
@@ -0,0 +1,407 @@
+use super::trans::like_pattern;
+use crate::{
+    errors::AppError,
+    models::money::Money,
+    models::page::{encode_cursor, Page, DEFAULT_LIMIT},
+    models::payment::{
+        DirectDebit, DirectDebitRecord, PaymentsCursor, PaymentsQuery, StandingOrder,
+        StandingOrderRecord,
+    },
+    models::trans::CheckCache,
+};
+use actix_web::{web::Data, FromRequest};
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Utc};
+use color_eyre::Result;
+use eyre::WrapErr;
+use futures::future::{ready, Ready};
+use sqlx::postgres::PgQueryAs;
+use sqlx::PgPool;
+use std::{ops::Deref, sync::Arc};
+use tracing::instrument;
+use uuid::Uuid;
+
+const DIRECT_DEBIT_COLUMNS: &str = "id, account_id, direct_debit_id, name, status, mandated_at, \
+    previous_payment_at, previous_payment_amount, currency";
+
+const STANDING_ORDER_COLUMNS: &str = "id, account_id, frequency, status, reference, payee, \
+    currency, next_payment_at, next_payment_amount, first_payment_at, first_payment_amount, \
+    final_payment_at, final_payment_amount";
+
+/// Conditions for every `PaymentsQuery` filter on direct debits, an unset
+/// filter binds NULL and matches everything.
+const DIRECT_DEBIT_FILTERS: &str = r#"user_id = $1 AND provider = $2
+    AND ($3::varchar IS NULL OR account_id = $3)
+    AND ($4::timestamptz IS NULL OR previous_payment_at >= $4)
+    AND ($5::timestamptz IS NULL OR previous_payment_at < $5)
+    AND ($6::varchar IS NULL OR lower(status) = lower($6))
+    AND ($7::varchar IS NULL OR name ILIKE $7 ESCAPE '\')"#;
+
+/// Same as `DIRECT_DEBIT_FILTERS` for standing orders.
+const STANDING_ORDER_FILTERS: &str = r#"user_id = $1 AND provider = $2
+    AND ($3::varchar IS NULL OR account_id = $3)
+    AND ($4::timestamptz IS NULL OR next_payment_at >= $4)
+    AND ($5::timestamptz IS NULL OR next_payment_at < $5)
+    AND ($6::varchar IS NULL OR lower(status) = lower($6))
+    AND ($7::varchar IS NULL OR payee ILIKE $7 ESCAPE '\' OR reference ILIKE $7 ESCAPE '\')"#;
+
+#[derive(sqlx::FromRow)]
+struct DirectDebitRow {
+    id: i64,
+    account_id: String,
+    direct_debit_id: String,
+    name: String,
+    status: Option<String>,
+    mandated_at: Option<DateTime<Utc>>,
+    previous_payment_at: Option<DateTime<Utc>>,
+    previous_payment_amount: Option<BigDecimal>,
+    currency: Option<String>,
+}
+
+impl DirectDebitRow {
+    fn into_record(self) -> Result<DirectDebitRecord> {
+        let previous_payment_amount = match (self.previous_payment_amount, &self.currency) {
+            (Some(amount), Some(currency)) => Some(Money::new(amount, currency)?),
+            _ => None,
+        };
+        Ok(DirectDebitRecord {
+            id: self.id,
+            account_id: self.account_id,
+            direct_debit_id: self.direct_debit_id,
+            name: self.name,
+            status: self.status,
+            mandated_at: self.mandated_at,
+            previous_payment_at: self.previous_payment_at,
+            previous_payment_amount,
+        })
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct StandingOrderRow {
+    id: i64,
+    account_id: String,
+    frequency: Option<String>,
+    status: Option<String>,
+    reference: Option<String>,
+    payee: Option<String>,
+    currency: String,
+    next_payment_at: Option<DateTime<Utc>>,
+    next_payment_amount: Option<BigDecimal>,
+    first_payment_at: Option<DateTime<Utc>>,
+    first_payment_amount: Option<BigDecimal>,
+    final_payment_at: Option<DateTime<Utc>>,
+    final_payment_amount: Option<BigDecimal>,
+}
+
+impl StandingOrderRow {
+    fn into_record(self) -> Result<StandingOrderRecord> {
+        let currency = self.currency;
+        let money = |amount: Option<BigDecimal>| -> Result<Option<Money>> {
+            Ok(amount
+                .map(|amount| Money::new(amount, &currency))
+                .transpose()?)
+        };
+        Ok(StandingOrderRecord {
+            id: self.id,
+            account_id: self.account_id,
+            frequency: self.frequency,
+            status: self.status,
+            reference: self.reference,
+            payee: self.payee,
+            next_payment_at: self.next_payment_at,
+            next_payment_amount: money(self.next_payment_amount)?,
+            first_payment_at: self.first_payment_at,
+            first_payment_amount: money(self.first_payment_amount)?,
+            final_payment_at: self.final_payment_at,
+            final_payment_amount: money(self.final_payment_amount)?,
+        })
+    }
+}
+
+fn parse_timestamp(value: &Option<String>) -> Result<Option<DateTime<Utc>>> {
+    value
+        .as_deref()
+        .map(|value| {
+            DateTime::parse_from_rfc3339(value)
+                .map(|timestamp| timestamp.with_timezone(&Utc))
+                .wrap_err_with(|| format!("parsing payment timestamp {}", value))
+        })
+        .transpose()
+}
+
+fn next_cursor(last_id: Option<i64>, has_more: bool) -> Option<String> {
+    match last_id {
+        Some(id) if has_more => Some(encode_cursor(&PaymentsCursor { id })),
+        _ => None,
+    }
+}
+
+pub struct PaymentRepository {
+    pool: Arc<PgPool>,
+}
+
+impl PaymentRepository {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        Self { pool }
+    }
+
+    /// Replaces the direct debits of an account with the ones the provider
+    /// returned.
+    #[instrument(skip(self, direct_debits))]
+    pub async fn replace_direct_debits(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        account_id: &str,
+        direct_debits: &[DirectDebit],
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "DELETE FROM direct_debits WHERE user_id = $1 AND provider = $2 AND account_id = $3",
+        )
+        .bind(user_id)
+        .bind(provider)
+        .bind(account_id)
+        .execute(&mut tx)
+        .await?;
+
+        for direct_debit in direct_debits {
+            sqlx::query(
+                r#"INSERT INTO direct_debits (user_id, provider, account_id, direct_debit_id, name, status,
+                    mandated_at, previous_payment_at, previous_payment_amount, currency, raw)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)"#,
+            )
+            .bind(user_id)
+            .bind(provider)
+            .bind(account_id)
+            .bind(&direct_debit.direct_debit_id)
+            .bind(&direct_debit.name)
+            .bind(&direct_debit.status)
+            .bind(parse_timestamp(&direct_debit.timestamp)?)
+            .bind(parse_timestamp(&direct_debit.previous_payment_timestamp)?)
+            .bind(&direct_debit.previous_payment_amount)
+            .bind(direct_debit.currency.as_deref().map(str::to_uppercase))
+            .bind(serde_json::to_value(direct_debit)?)
+            .execute(&mut tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Replaces the standing orders of an account with the ones the provider
+    /// returned.
+    #[instrument(skip(self, standing_orders))]
+    pub async fn replace_standing_orders(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        account_id: &str,
+        standing_orders: &[StandingOrder],
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "DELETE FROM standing_orders WHERE user_id = $1 AND provider = $2 AND account_id = $3",
+        )
+        .bind(user_id)
+        .bind(provider)
+        .bind(account_id)
+        .execute(&mut tx)
+        .await?;
+
+        for order in standing_orders {
+            sqlx::query(
+                r#"INSERT INTO standing_orders (user_id, provider, account_id, frequency, status,
+                    reference, payee, currency, next_payment_at, next_payment_amount,
+                    first_payment_at, first_payment_amount, final_payment_at, final_payment_amount, raw)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)"#,
+            )
+            .bind(user_id)
+            .bind(provider)
+            .bind(account_id)
+            .bind(&order.frequency)
+            .bind(&order.status)
+            .bind(&order.reference)
+            .bind(&order.payee)
+            .bind(order.currency.to_uppercase())
+            .bind(parse_timestamp(&order.next_payment_date)?)
+            .bind(&order.next_payment_amount)
+            .bind(parse_timestamp(&order.first_payment_date)?)
+            .bind(&order.first_payment_amount)
+            .bind(parse_timestamp(&order.final_payment_date)?)
+            .bind(&order.final_payment_amount)
+            .bind(serde_json::to_value(order)?)
+            .execute(&mut tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// A page of the direct debits matching the query, in the order they were
+    /// stored.
+    #[instrument(skip(self))]
+    pub async fn direct_debits(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        query: &PaymentsQuery,
+        limit: Option<i64>,
+        cursor: Option<PaymentsCursor>,
+    ) -> Result<Page<DirectDebitRecord>> {
+        let limit = limit.unwrap_or(DEFAULT_LIMIT);
+        let mut rows = sqlx::query_as::<_, DirectDebitRow>(&format!(
+            r#"SELECT {} FROM direct_debits WHERE {}
+            AND ($8::bigint IS NULL OR id > $8)
+            ORDER BY id LIMIT $9"#,
+            DIRECT_DEBIT_COLUMNS, DIRECT_DEBIT_FILTERS
+        ))
+        .bind(user_id)
+        .bind(provider)
+        .bind(query.account_id.clone())
+        .bind(query.from)
+        .bind(query.to)
+        .bind(query.status.clone())
+        .bind(query.q.as_deref().map(like_pattern))
+        .bind(cursor.map(|cursor| cursor.id))
+        .bind(limit + 1)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        // One extra row is fetched to know whether there is a next page
+        let has_more = rows.len() as i64 > limit;
+        rows.truncate(limit as usize);
+        let results = rows
+            .into_iter()
+            .map(DirectDebitRow::into_record)
+            .collect::<Result<Vec<_>>>()?;
+
+        let total_count = sqlx::query_as::<_, CheckCache>(&format!(
+            "SELECT count(*) as results FROM direct_debits WHERE {}",
+            DIRECT_DEBIT_FILTERS
+        ))
+        .bind(user_id)
+        .bind(provider)
+        .bind(query.account_id.clone())
+        .bind(query.from)
+        .bind(query.to)
+        .bind(query.status.clone())
+        .bind(query.q.as_deref().map(like_pattern))
+        .fetch_one(&*self.pool)
+        .await?
+        .results;
+
+        Ok(Page {
+            next_cursor: next_cursor(results.last().map(|last| last.id), has_more),
+            results,
+            total_count,
+        })
+    }
+
+    /// A page of the standing orders matching the query, in the order they
+    /// were stored.
+    #[instrument(skip(self))]
+    pub async fn standing_orders(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        query: &PaymentsQuery,
+        limit: Option<i64>,
+        cursor: Option<PaymentsCursor>,
+    ) -> Result<Page<StandingOrderRecord>> {
+        let limit = limit.unwrap_or(DEFAULT_LIMIT);
+        let mut rows = sqlx::query_as::<_, StandingOrderRow>(&format!(
+            r#"SELECT {} FROM standing_orders WHERE {}
+            AND ($8::bigint IS NULL OR id > $8)
+            ORDER BY id LIMIT $9"#,
+            STANDING_ORDER_COLUMNS, STANDING_ORDER_FILTERS
+        ))
+        .bind(user_id)
+        .bind(provider)
+        .bind(query.account_id.clone())
+        .bind(query.from)
+        .bind(query.to)
+        .bind(query.status.clone())
+        .bind(query.q.as_deref().map(like_pattern))
+        .bind(cursor.map(|cursor| cursor.id))
+        .bind(limit + 1)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        let has_more = rows.len() as i64 > limit;
+        rows.truncate(limit as usize);
+        let results = rows
+            .into_iter()
+            .map(StandingOrderRow::into_record)
+            .collect::<Result<Vec<_>>>()?;
+
+        let total_count = sqlx::query_as::<_, CheckCache>(&format!(
+            "SELECT count(*) as results FROM standing_orders WHERE {}",
+            STANDING_ORDER_FILTERS
+        ))
+        .bind(user_id)
+        .bind(provider)
+        .bind(query.account_id.clone())
+        .bind(query.from)
+        .bind(query.to)
+        .bind(query.status.clone())
+        .bind(query.q.as_deref().map(like_pattern))
+        .fetch_one(&*self.pool)
+        .await?
+        .results;
+
+        Ok(Page {
+            next_cursor: next_cursor(results.last().map(|last| last.id), has_more),
+            results,
+            total_count,
+        })
+    }
+
+    /// Every direct debit of the user whatever the provider, for data exports.
+    #[instrument(skip(self))]
+    pub async fn all_direct_debits(&self, user_id: Uuid) -> Result<Vec<DirectDebitRecord>> {
+        let rows = sqlx::query_as::<_, DirectDebitRow>(&format!(
+            "SELECT {} FROM direct_debits WHERE user_id = $1 ORDER BY id",
+            DIRECT_DEBIT_COLUMNS
+        ))
+        .bind(user_id)
+        .fetch_all(&*self.pool)
+        .await?;
+        rows.into_iter().map(DirectDebitRow::into_record).collect()
+    }
+
+    /// Every standing order of the user whatever the provider, for data
+    /// exports.
+    #[instrument(skip(self))]
+    pub async fn all_standing_orders(&self, user_id: Uuid) -> Result<Vec<StandingOrderRecord>> {
+        let rows = sqlx::query_as::<_, StandingOrderRow>(&format!(
+            "SELECT {} FROM standing_orders WHERE user_id = $1 ORDER BY id",
+            STANDING_ORDER_COLUMNS
+        ))
+        .bind(user_id)
+        .fetch_all(&*self.pool)
+        .await?;
+        rows.into_iter()
+            .map(StandingOrderRow::into_record)
+            .collect()
+    }
+}
+
+impl FromRequest for PaymentRepository {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+    #[instrument(skip(req, payload))]
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let pool_result = Data::<PgPool>::from_request(req, payload).into_inner();
+
+        match pool_result {
+            Ok(pool) => ready(Ok(PaymentRepository::new(pool.deref().clone()))),
+            _ => ready(Err(AppError::NOT_AUTHORIZED.default())),
+        }
+    }
+}
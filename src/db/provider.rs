@@ -0,0 +1,209 @@
+use crate::{
+    config::crypto::{CryptoService, ENCRYPTED_PREFIX},
+    errors::AppError,
+    models::export::LinkedProvider,
+    models::provider::{ProviderCredentials, Token},
+};
+use actix_web::{web::Data, FromRequest};
+use chrono::{Duration, Utc};
+use color_eyre::Result;
+use futures::future::{ready, Ready};
+use sqlx::postgres::PgQueryAs;
+use sqlx::PgPool;
+use std::{ops::Deref, sync::Arc};
+use tracing::instrument;
+use uuid::Uuid;
+
+pub struct ProviderRepository {
+    pool: Arc<PgPool>,
+}
+
+impl ProviderRepository {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        Self { pool }
+    }
+
+    /// Stores the tokens returned by the provider, encrypted. A refresh
+    /// response without a new refresh token keeps the one already stored.
+    #[instrument(skip(self, token, crypto))]
+    pub async fn save_credentials(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        token: &Token,
+        crypto: &CryptoService,
+    ) -> Result<ProviderCredentials> {
+        let expires_at = Utc::now() + Duration::seconds(token.expires_in);
+        let access_token = crypto.encrypt(&token.access_token)?;
+        let refresh_token = match &token.refresh_token {
+            Some(refresh_token) => Some(crypto.encrypt(refresh_token)?),
+            None => None,
+        };
+        let credentials = sqlx::query_as::<_, ProviderCredentials>(
+            r#"INSERT INTO provider_credentials (user_id, provider, access_token, refresh_token, scopes, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (user_id, provider) DO UPDATE SET
+                access_token = excluded.access_token,
+                refresh_token = coalesce(excluded.refresh_token, provider_credentials.refresh_token),
+                scopes = coalesce(excluded.scopes, provider_credentials.scopes),
+                expires_at = excluded.expires_at,
+                updated_at = current_timestamp
+            RETURNING id, access_token, refresh_token, expires_at"#,
+        )
+        .bind(user_id)
+        .bind(provider)
+        .bind(access_token)
+        .bind(refresh_token)
+        .bind(&token.scope)
+        .bind(expires_at.naive_utc())
+        .fetch_one(&*self.pool)
+        .await?;
+        decrypt_credentials(credentials, crypto)
+    }
+
+    #[instrument(skip(self, crypto))]
+    pub async fn find_credentials(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        crypto: &CryptoService,
+    ) -> Result<Option<ProviderCredentials>> {
+        let maybe_credentials = sqlx::query_as::<_, ProviderCredentials>(
+            r#"SELECT id, access_token, refresh_token, expires_at FROM provider_credentials
+            WHERE user_id = $1 AND provider = $2"#,
+        )
+        .bind(user_id)
+        .bind(provider)
+        .fetch_optional(&*self.pool)
+        .await?;
+        maybe_credentials
+            .map(|credentials| decrypt_credentials(credentials, crypto))
+            .transpose()
+    }
+
+    /// The providers a user linked, without their tokens.
+    #[instrument(skip(self))]
+    pub async fn linked_providers(&self, user_id: Uuid) -> Result<Vec<LinkedProvider>> {
+        let providers = sqlx::query_as::<_, LinkedProvider>(
+            r#"SELECT provider, scopes, expires_at, created_at, updated_at
+            FROM provider_credentials WHERE user_id = $1 ORDER BY created_at"#,
+        )
+        .bind(user_id)
+        .fetch_all(&*self.pool)
+        .await?;
+        Ok(providers)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn delete_credentials(&self, user_id: Uuid, provider: &str) -> Result<()> {
+        sqlx::query("DELETE FROM provider_credentials WHERE user_id = $1 AND provider = $2")
+            .bind(user_id)
+            .bind(provider)
+            .execute(&*self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Re-encrypts every stored token under the current encryption key and
+    /// returns how many rows were rewritten.
+    #[instrument(skip(self, crypto))]
+    pub async fn rotate_encryption(&self, crypto: &CryptoService) -> Result<u64> {
+        let rows = sqlx::query_as::<_, ProviderCredentials>(
+            "SELECT id, access_token, refresh_token, expires_at FROM provider_credentials",
+        )
+        .fetch_all(&*self.pool)
+        .await?;
+
+        let mut rotated = 0;
+        for row in rows {
+            let stale_refresh = row
+                .refresh_token
+                .as_deref()
+                .map(|token| crypto.needs_rotation(token))
+                .unwrap_or(false);
+            if !crypto.needs_rotation(&row.access_token) && !stale_refresh {
+                continue;
+            }
+
+            let access_token = crypto.reencrypt(&row.access_token)?;
+            let refresh_token = match &row.refresh_token {
+                Some(token) => Some(crypto.reencrypt(token)?),
+                None => None,
+            };
+
+            // Only rewrite the row if it was not refreshed in the meantime.
+            rotated += sqlx::query(
+                "UPDATE provider_credentials SET access_token = $1, refresh_token = $2 WHERE id = $3 AND access_token = $4",
+            )
+            .bind(access_token)
+            .bind(refresh_token)
+            .bind(row.id)
+            .bind(&row.access_token)
+            .execute(&*self.pool)
+            .await?;
+        }
+        Ok(rotated)
+    }
+
+    /// Encrypts the tokens copied in plaintext from `users` by the migration
+    /// to `provider_credentials`, and returns how many rows were rewritten.
+    #[instrument(skip(self, crypto))]
+    pub async fn encrypt_legacy(&self, crypto: &CryptoService) -> Result<u64> {
+        let rows = sqlx::query_as::<_, ProviderCredentials>(
+            r#"SELECT id, access_token, refresh_token, expires_at FROM provider_credentials
+            WHERE access_token NOT LIKE $1 OR refresh_token NOT LIKE $1"#,
+        )
+        .bind(format!("{}:%", ENCRYPTED_PREFIX))
+        .fetch_all(&*self.pool)
+        .await?;
+
+        let mut encrypted = 0;
+        for row in rows {
+            let refresh_token = match &row.refresh_token {
+                Some(token) => Some(crypto.encrypt_legacy(token)?),
+                None => None,
+            };
+
+            encrypted += sqlx::query(
+                "UPDATE provider_credentials SET access_token = $1, refresh_token = $2 WHERE id = $3 AND access_token = $4",
+            )
+            .bind(crypto.encrypt_legacy(&row.access_token)?)
+            .bind(refresh_token)
+            .bind(row.id)
+            .bind(&row.access_token)
+            .execute(&*self.pool)
+            .await?;
+        }
+        Ok(encrypted)
+    }
+}
+
+fn decrypt_credentials(
+    mut credentials: ProviderCredentials,
+    crypto: &CryptoService,
+) -> Result<ProviderCredentials> {
+    credentials.access_token = crypto.decrypt(&credentials.access_token)?;
+    credentials.refresh_token = match &credentials.refresh_token {
+        Some(refresh_token) => Some(crypto.decrypt(refresh_token)?),
+        None => None,
+    };
+    Ok(credentials)
+}
+
+impl FromRequest for ProviderRepository {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+    #[instrument(skip(req, payload))]
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let pool_result = Data::<PgPool>::from_request(req, payload).into_inner();
+
+        match pool_result {
+            Ok(pool) => ready(Ok(ProviderRepository::new(pool.deref().clone()))),
+            _ => ready(Err(AppError::NOT_AUTHORIZED.default())),
+        }
+    }
+}
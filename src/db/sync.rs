@@ -0,0 +1,213 @@
+use crate::{errors::AppError, models::sync::SyncStatus};
+use actix_web::{web::Data, FromRequest};
+use chrono::{DateTime, Utc};
+use color_eyre::Result;
+use futures::future::{ready, Ready};
+use sqlx::postgres::PgQueryAs;
+use sqlx::{pool::PoolConnection, PgConnection, PgPool, Transaction};
+use std::{ops::Deref, sync::Arc};
+use tracing::instrument;
+use uuid::Uuid;
+
+const STATUS_COLUMNS: &str =
+    "last_started_at, last_success_at, last_failure_at, last_error, next_sync_at";
+
+#[derive(sqlx::FromRow)]
+struct DueUser {
+    user_id: Uuid,
+}
+
+/// First key of the advisory locks taken on users being synced, the second
+/// one is derived from the user id.
+const SYNC_LOCK_CLASS: i32 = 1;
+
+#[derive(sqlx::FromRow)]
+struct Locked {
+    locked: bool,
+}
+
+/// Held while a user is synced. Dropping it also releases the lock, but
+/// closes its connection instead of returning it to the pool.
+pub struct SyncLock {
+    tx: Transaction<PoolConnection<PgConnection>>,
+}
+
+impl SyncLock {
+    pub async fn release(self) -> Result<()> {
+        self.tx.rollback().await?;
+        Ok(())
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct Watermark {
+    synced_until: DateTime<Utc>,
+}
+
+pub struct SyncRepository {
+    pool: Arc<PgPool>,
+}
+
+impl SyncRepository {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        Self { pool }
+    }
+
+    /// Users linked to the provider whose next sync is due.
+    #[instrument(skip(self))]
+    pub async fn due_users(&self, provider: &str, limit: i64) -> Result<Vec<Uuid>> {
+        let users = sqlx::query_as::<_, DueUser>(
+            r#"SELECT pc.user_id FROM provider_credentials pc
+            LEFT JOIN sync_status s ON s.user_id = pc.user_id
+            WHERE pc.provider = $1
+            AND (s.next_sync_at IS NULL OR s.next_sync_at <= current_timestamp)
+            ORDER BY s.next_sync_at NULLS FIRST
+            LIMIT $2"#,
+        )
+        .bind(provider)
+        .bind(limit)
+        .fetch_all(&*self.pool)
+        .await?;
+        Ok(users.into_iter().map(|user| user.user_id).collect())
+    }
+
+    /// Takes the sync lock of the user, `None` when another sync of the user
+    /// is running.
+    #[instrument(skip(self))]
+    pub async fn lock(&self, user_id: Uuid) -> Result<Option<SyncLock>> {
+        let mut tx = self.pool.begin().await?;
+        let locked = sqlx::query_as::<_, Locked>(
+            "SELECT pg_try_advisory_xact_lock($1, hashtext($2::text)) as locked",
+        )
+        .bind(SYNC_LOCK_CLASS)
+        .bind(user_id)
+        .fetch_one(&mut tx)
+        .await?
+        .locked;
+        Ok(if locked { Some(SyncLock { tx }) } else { None })
+    }
+
+    #[instrument(skip(self))]
+    pub async fn watermark(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        account_id: &str,
+    ) -> Result<Option<DateTime<Utc>>> {
+        let watermark = sqlx::query_as::<_, Watermark>(
+            "SELECT synced_until FROM sync_accounts WHERE user_id = $1 AND provider = $2 AND account_id = $3",
+        )
+        .bind(user_id)
+        .bind(provider)
+        .bind(account_id)
+        .fetch_optional(&*self.pool)
+        .await?;
+        Ok(watermark.map(|watermark| watermark.synced_until))
+    }
+
+    #[instrument(skip(self))]
+    pub async fn save_watermark(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        account_id: &str,
+        synced_until: DateTime<Utc>,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"INSERT INTO sync_accounts (user_id, provider, account_id, synced_until)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (user_id, provider, account_id) DO UPDATE SET
+                synced_until = excluded.synced_until,
+                updated_at = current_timestamp"#,
+        )
+        .bind(user_id)
+        .bind(provider)
+        .bind(account_id)
+        .bind(synced_until)
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn start(&self, user_id: Uuid) -> Result<()> {
+        sqlx::query(
+            r#"INSERT INTO sync_status (user_id, last_started_at) VALUES ($1, current_timestamp)
+            ON CONFLICT (user_id) DO UPDATE SET last_started_at = excluded.last_started_at"#,
+        )
+        .bind(user_id)
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn succeed(&self, user_id: Uuid, next_sync_at: DateTime<Utc>) -> Result<SyncStatus> {
+        let status = sqlx::query_as::<_, SyncStatus>(&format!(
+            r#"UPDATE sync_status SET
+                last_success_at = current_timestamp,
+                last_error = NULL,
+                next_sync_at = $2
+            WHERE user_id = $1
+            RETURNING {}"#,
+            STATUS_COLUMNS
+        ))
+        .bind(user_id)
+        .bind(next_sync_at)
+        .fetch_one(&*self.pool)
+        .await?;
+        Ok(status)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn fail(
+        &self,
+        user_id: Uuid,
+        error: String,
+        next_sync_at: DateTime<Utc>,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"UPDATE sync_status SET
+                last_failure_at = current_timestamp,
+                last_error = $2,
+                next_sync_at = $3
+            WHERE user_id = $1"#,
+        )
+        .bind(user_id)
+        .bind(error)
+        .bind(next_sync_at)
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn status(&self, user_id: Uuid) -> Result<Option<SyncStatus>> {
+        let status = sqlx::query_as::<_, SyncStatus>(&format!(
+            "SELECT {} FROM sync_status WHERE user_id = $1",
+            STATUS_COLUMNS
+        ))
+        .bind(user_id)
+        .fetch_optional(&*self.pool)
+        .await?;
+        Ok(status)
+    }
+}
+
+impl FromRequest for SyncRepository {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+    #[instrument(skip(req, payload))]
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let pool_result = Data::<PgPool>::from_request(req, payload).into_inner();
+
+        match pool_result {
+            Ok(pool) => ready(Ok(SyncRepository::new(pool.deref().clone()))),
+            _ => ready(Err(AppError::NOT_AUTHORIZED.default())),
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use crate::{errors::AppError, models::token::RefreshToken};
+use actix_web::{web::Data, FromRequest};
+use chrono::NaiveDateTime;
+use color_eyre::Result;
+use futures::future::{ready, Ready};
+use sqlx::postgres::PgQueryAs;
+use sqlx::PgPool;
+use std::{ops::Deref, sync::Arc};
+use tracing::instrument;
+use uuid::Uuid;
+
+#[derive(sqlx::FromRow)]
+struct Revoked {
+    revoked: bool,
+}
+
+pub struct TokenRepository {
+    pool: Arc<PgPool>,
+}
+
+impl TokenRepository {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        Self { pool }
+    }
+
+    #[instrument(skip(self, token_hash))]
+    pub async fn create_refresh_token(
+        &self,
+        user_id: Uuid,
+        family_id: Uuid,
+        token_hash: String,
+        expires_at: NaiveDateTime,
+    ) -> Result<RefreshToken> {
+        let token = sqlx::query_as::<_, RefreshToken>(
+            r#"INSERT INTO refresh_tokens (user_id, family_id, token_hash, expires_at)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, user_id, family_id, expires_at, revoked_at"#,
+        )
+        .bind(user_id)
+        .bind(family_id)
+        .bind(token_hash)
+        .bind(expires_at)
+        .fetch_one(&*self.pool)
+        .await?;
+        Ok(token)
+    }
+
+    #[instrument(skip(self, token_hash))]
+    pub async fn find_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshToken>> {
+        let token = sqlx::query_as::<_, RefreshToken>(
+            r#"SELECT id, user_id, family_id, expires_at, revoked_at FROM refresh_tokens
+            WHERE token_hash = $1"#,
+        )
+        .bind(token_hash)
+        .fetch_optional(&*self.pool)
+        .await?;
+        Ok(token)
+    }
+
+    /// Marks a refresh token as used, `false` if it already was so two
+    /// concurrent refreshes can't both succeed.
+    #[instrument(skip(self))]
+    pub async fn use_refresh_token(&self, id: Uuid) -> Result<bool> {
+        let updated = sqlx::query(
+            "UPDATE refresh_tokens SET used_at = current_timestamp WHERE id = $1 AND used_at IS NULL AND revoked_at IS NULL",
+        )
+        .bind(id)
+        .execute(&*self.pool)
+        .await?;
+        Ok(updated > 0)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn revoke_family(&self, family_id: Uuid) -> Result<()> {
+        sqlx::query(
+            "UPDATE refresh_tokens SET revoked_at = current_timestamp WHERE family_id = $1 AND revoked_at IS NULL",
+        )
+        .bind(family_id)
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn revoke_user_refresh_tokens(&self, user_id: Uuid) -> Result<()> {
+        sqlx::query(
+            "UPDATE refresh_tokens SET revoked_at = current_timestamp WHERE user_id = $1 AND revoked_at IS NULL",
+        )
+        .bind(user_id)
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Adds an access token to the revocation list until it expires on its
+    /// own, and drops the entries that no longer need to be kept.
+    #[instrument(skip(self))]
+    pub async fn revoke_access_token(
+        &self,
+        jti: Uuid,
+        user_id: Uuid,
+        expires_at: NaiveDateTime,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO revoked_tokens (jti, user_id, expires_at) VALUES ($1, $2, $3) ON CONFLICT (jti) DO NOTHING",
+        )
+        .bind(jti)
+        .bind(user_id)
+        .bind(expires_at)
+        .execute(&*self.pool)
+        .await?;
+
+        sqlx::query("DELETE FROM revoked_tokens WHERE expires_at < current_timestamp")
+            .execute(&*self.pool)
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn is_revoked(&self, jti: Uuid) -> Result<bool> {
+        let revoked = sqlx::query_as::<_, Revoked>(
+            "SELECT exists(SELECT 1 FROM revoked_tokens WHERE jti = $1) as revoked",
+        )
+        .bind(jti)
+        .fetch_one(&*self.pool)
+        .await?;
+        Ok(revoked.revoked)
+    }
+}
+
+impl FromRequest for TokenRepository {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+    #[instrument(skip(req, payload))]
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let pool_result = Data::<PgPool>::from_request(req, payload).into_inner();
+
+        match pool_result {
+            Ok(pool) => ready(Ok(TokenRepository::new(pool.deref().clone()))),
+            _ => ready(Err(AppError::NOT_AUTHORIZED.default())),
+        }
+    }
+}
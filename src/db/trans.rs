@@ -0,0 +1,376 @@
+use crate::{
+    errors::AppError,
+    models::export::ExportedTransaction,
+    models::money::Money,
+    models::page::{encode_cursor, Page, DEFAULT_LIMIT},
+    models::trans::{
+        CategoryTotal, CheckCache, Transaction, TransactionsAccount, TransactionsCursor,
+        TransactionsPage, TransactionsQuery, TransactionsSort,
+    },
+};
+use actix_web::{web::Data, FromRequest};
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Utc};
+use color_eyre::Result;
+use eyre::WrapErr;
+use futures::future::{ready, Ready};
+use sqlx::postgres::PgQueryAs;
+use sqlx::PgPool;
+use std::{ops::Deref, sync::Arc};
+use tracing::instrument;
+use uuid::Uuid;
+
+const TRANSACTIONS: &str = "transactions";
+/// Card transactions have the same columns, `account_id` holds the card id.
+const CARD_TRANSACTIONS: &str = "card_transactions";
+
+const TRANSACTION_COLUMNS: &str = "id, account_id, provider_transaction_id, booked_at, \
+    amount, currency, transaction_type, transaction_category, description";
+
+#[derive(sqlx::FromRow)]
+struct TransactionRow {
+    id: i64,
+    account_id: String,
+    provider_transaction_id: String,
+    booked_at: DateTime<Utc>,
+    amount: BigDecimal,
+    currency: String,
+    transaction_type: Option<String>,
+    transaction_category: Option<String>,
+    description: Option<String>,
+}
+
+impl TransactionRow {
+    fn into_transaction(self) -> Result<Transaction> {
+        Ok(Transaction {
+            id: self.id,
+            account_id: self.account_id,
+            provider_transaction_id: self.provider_transaction_id,
+            booked_at: self.booked_at,
+            amount: Money::new(self.amount, &self.currency)?,
+            transaction_type: self.transaction_type,
+            transaction_category: self.transaction_category,
+            description: self.description,
+        })
+    }
+}
+
+/// Conditions for every `TransactionsQuery` filter, an unset filter binds NULL
+/// and matches everything.
+const QUERY_FILTERS: &str = r#"user_id = $1
+    AND ($2::varchar IS NULL OR account_id = $2)
+    AND ($3::timestamptz IS NULL OR booked_at >= $3)
+    AND ($4::timestamptz IS NULL OR booked_at < $4)
+    AND ($5::varchar IS NULL OR transaction_type = $5)
+    AND ($6::varchar IS NULL OR transaction_category = $6)
+    AND ($7::numeric IS NULL OR amount >= $7)
+    AND ($8::numeric IS NULL OR amount <= $8)
+    AND ($9::varchar IS NULL OR description ILIKE $9 ESCAPE '\')"#;
+
+/// Turns a search text into an ILIKE pattern matching it anywhere.
+pub(super) fn like_pattern(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+fn into_transactions(rows: Vec<TransactionRow>) -> Result<Vec<Transaction>> {
+    rows.into_iter()
+        .map(TransactionRow::into_transaction)
+        .collect()
+}
+
+#[derive(sqlx::FromRow)]
+struct CategoryTotalRow {
+    transaction_category: Option<String>,
+    total_amount: BigDecimal,
+    currency: String,
+}
+
+pub struct TransactionRepository {
+    pool: Arc<PgPool>,
+}
+
+impl TransactionRepository {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        Self { pool }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn check_cache(&self, user_id: Uuid) -> Result<Option<CheckCache>> {
+        let maybe_cached = sqlx::query_as::<_, CheckCache>(
+            r#"select count(*) as results from transactions where user_id=$1"#,
+        )
+        .bind(user_id)
+        .fetch_optional(&*self.pool)
+        .await?;
+        Ok(maybe_cached)
+    }
+
+    /// Inserts a provider transaction, or updates it if the provider sent the
+    /// same `transaction_id` for the account before.
+    #[instrument(skip(self, transaction))]
+    pub async fn save(
+        &self,
+        user_id: Uuid,
+        account_id: &str,
+        transaction: &TransactionsAccount,
+    ) -> Result<()> {
+        self.save_into(TRANSACTIONS, user_id, account_id, transaction)
+            .await
+    }
+
+    /// Same as `save`, for a transaction of a card.
+    #[instrument(skip(self, transaction))]
+    pub async fn save_card(
+        &self,
+        user_id: Uuid,
+        card_id: &str,
+        transaction: &TransactionsAccount,
+    ) -> Result<()> {
+        self.save_into(CARD_TRANSACTIONS, user_id, card_id, transaction)
+            .await
+    }
+
+    async fn save_into(
+        &self,
+        table: &str,
+        user_id: Uuid,
+        account_id: &str,
+        transaction: &TransactionsAccount,
+    ) -> Result<()> {
+        let booked_at = DateTime::parse_from_rfc3339(&transaction.timestamp)
+            .wrap_err_with(|| format!("parsing transaction timestamp {}", transaction.timestamp))?
+            .with_timezone(&Utc);
+        let amount = transaction.money()?;
+        let raw = serde_json::to_value(transaction)?;
+
+        sqlx::query(&format!(
+            r#"INSERT INTO {}
+                (user_id, account_id, provider_transaction_id, booked_at, amount, currency,
+                 transaction_type, transaction_category, description, raw)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            ON CONFLICT (user_id, account_id, provider_transaction_id) DO UPDATE SET
+                booked_at = excluded.booked_at,
+                amount = excluded.amount,
+                currency = excluded.currency,
+                transaction_type = excluded.transaction_type,
+                transaction_category = excluded.transaction_category,
+                description = excluded.description,
+                raw = excluded.raw,
+                updated_at = current_timestamp"#,
+            table
+        ))
+        .bind(user_id)
+        .bind(account_id)
+        .bind(&transaction.transaction_id)
+        .bind(booked_at)
+        .bind(amount.amount())
+        .bind(amount.currency())
+        .bind(&transaction.transaction_type)
+        .bind(&transaction.transaction_category)
+        .bind(&transaction.description)
+        .bind(raw)
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Every transaction of a user with the data the provider sent, for data
+    /// exports.
+    #[instrument(skip(self))]
+    pub async fn export(&self, user_id: Uuid) -> Result<Vec<ExportedTransaction>> {
+        self.export_from(TRANSACTIONS, user_id).await
+    }
+
+    /// Every card transaction of a user, for data exports.
+    #[instrument(skip(self))]
+    pub async fn export_cards(&self, user_id: Uuid) -> Result<Vec<ExportedTransaction>> {
+        self.export_from(CARD_TRANSACTIONS, user_id).await
+    }
+
+    async fn export_from(&self, table: &str, user_id: Uuid) -> Result<Vec<ExportedTransaction>> {
+        let transactions = sqlx::query_as::<_, ExportedTransaction>(&format!(
+            r#"SELECT account_id, provider_transaction_id, booked_at, amount, currency,
+                transaction_type, transaction_category, description, raw, created_at
+            FROM {} WHERE user_id = $1 ORDER BY booked_at, id"#,
+            table
+        ))
+        .bind(user_id)
+        .fetch_all(&*self.pool)
+        .await?;
+        Ok(transactions)
+    }
+
+    /// A page of the transactions matching the query, in the requested order.
+    ///
+    /// Pages are keyset based: the cursor holds the sort column and id of the
+    /// last transaction returned, so pages stay consistent while new
+    /// transactions are synced.
+    #[instrument(skip(self))]
+    pub async fn list(
+        &self,
+        user_id: Uuid,
+        query: &TransactionsQuery,
+        page: &TransactionsPage,
+        cursor: Option<TransactionsCursor>,
+    ) -> Result<Page<Transaction>> {
+        self.list_from(TRANSACTIONS, user_id, query, page, cursor)
+            .await
+    }
+
+    /// Same as `list`, for card transactions. The `account_id` filter matches
+    /// the card id.
+    #[instrument(skip(self))]
+    pub async fn list_card(
+        &self,
+        user_id: Uuid,
+        query: &TransactionsQuery,
+        page: &TransactionsPage,
+        cursor: Option<TransactionsCursor>,
+    ) -> Result<Page<Transaction>> {
+        self.list_from(CARD_TRANSACTIONS, user_id, query, page, cursor)
+            .await
+    }
+
+    async fn list_from(
+        &self,
+        table: &str,
+        user_id: Uuid,
+        query: &TransactionsQuery,
+        page: &TransactionsPage,
+        cursor: Option<TransactionsCursor>,
+    ) -> Result<Page<Transaction>> {
+        let limit = page.limit.unwrap_or(DEFAULT_LIMIT);
+        let (booked_at, amount, id) = match cursor {
+            Some(cursor) => (cursor.booked_at, cursor.amount, Some(cursor.id)),
+            None => (None, None, None),
+        };
+
+        let (column, direction, comparison) = match page.sort {
+            TransactionsSort::BookedAtDesc => ("booked_at", "DESC", "<"),
+            TransactionsSort::BookedAtAsc => ("booked_at", "ASC", ">"),
+            TransactionsSort::AmountDesc => ("amount", "DESC", "<"),
+            TransactionsSort::AmountAsc => ("amount", "ASC", ">"),
+        };
+
+        let mut rows = sqlx::query_as::<_, TransactionRow>(&format!(
+            r#"SELECT {columns} FROM {table} WHERE {filters}
+            AND ($10::timestamptz IS NULL OR (booked_at, id) {cmp} ($10, $12))
+            AND ($11::numeric IS NULL OR (amount, id) {cmp} ($11, $12))
+            ORDER BY {column} {direction}, id {direction}
+            LIMIT $13"#,
+            columns = TRANSACTION_COLUMNS,
+            table = table,
+            filters = QUERY_FILTERS,
+            cmp = comparison,
+            column = column,
+            direction = direction,
+        ))
+        .bind(user_id)
+        .bind(query.account_id.clone())
+        .bind(query.from)
+        .bind(query.to)
+        .bind(query.transaction_type.clone())
+        .bind(query.category.clone())
+        .bind(query.min_amount.clone())
+        .bind(query.max_amount.clone())
+        .bind(query.q.as_deref().map(like_pattern))
+        .bind(booked_at)
+        .bind(amount)
+        .bind(id)
+        .bind(limit + 1)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        // One extra row is fetched to know whether there is a next page
+        let has_more = rows.len() as i64 > limit;
+        rows.truncate(limit as usize);
+        let results = into_transactions(rows)?;
+        let next_cursor = match results.last() {
+            Some(last) if has_more => {
+                Some(encode_cursor(&TransactionsCursor::after(last, page.sort)))
+            }
+            _ => None,
+        };
+
+        let total_count = sqlx::query_as::<_, CheckCache>(&format!(
+            "SELECT count(*) as results FROM {} WHERE {}",
+            table, QUERY_FILTERS
+        ))
+        .bind(user_id)
+        .bind(query.account_id.clone())
+        .bind(query.from)
+        .bind(query.to)
+        .bind(query.transaction_type.clone())
+        .bind(query.category.clone())
+        .bind(query.min_amount.clone())
+        .bind(query.max_amount.clone())
+        .bind(query.q.as_deref().map(like_pattern))
+        .fetch_one(&*self.pool)
+        .await?
+        .results;
+
+        Ok(Page {
+            results,
+            next_cursor,
+            total_count,
+        })
+    }
+
+    /// Totals per category and currency of the transactions matching the
+    /// query.
+    #[instrument(skip(self))]
+    pub async fn totals(
+        &self,
+        user_id: Uuid,
+        query: &TransactionsQuery,
+    ) -> Result<Vec<CategoryTotal>> {
+        let rows = sqlx::query_as::<_, CategoryTotalRow>(&format!(
+            r#"SELECT transaction_category, sum(amount) as total_amount, currency
+            FROM transactions
+            WHERE {}
+            GROUP BY transaction_category, currency"#,
+            QUERY_FILTERS
+        ))
+        .bind(user_id)
+        .bind(query.account_id.clone())
+        .bind(query.from)
+        .bind(query.to)
+        .bind(query.transaction_type.clone())
+        .bind(query.category.clone())
+        .bind(query.min_amount.clone())
+        .bind(query.max_amount.clone())
+        .bind(query.q.as_deref().map(like_pattern))
+        .fetch_all(&*self.pool)
+        .await?;
+        rows.into_iter()
+            .map(|row| -> Result<CategoryTotal> {
+                Ok(CategoryTotal {
+                    transaction_category: row.transaction_category,
+                    total: Money::new(row.total_amount, &row.currency)?,
+                })
+            })
+            .collect()
+    }
+}
+
+impl FromRequest for TransactionRepository {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+    #[instrument(skip(req, payload))]
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let pool_result = Data::<PgPool>::from_request(req, payload).into_inner();
+
+        match pool_result {
+            Ok(pool) => ready(Ok(TransactionRepository::new(pool.deref().clone()))),
+            _ => ready(Err(AppError::NOT_AUTHORIZED.default())),
+        }
+    }
+}
@@ -1,19 +1,26 @@
 use crate::{
-    config::crypto::CryptoService,
+    config::crypto::{CryptoService, ENCRYPTED_PREFIX},
     errors::AppError,
-    models::trans::{CheckCache, Transactions},
-    models::user::{NewUser, User},
+    models::{
+        role::Role,
+        user::{NewUser, UpdateProfile, User},
+    },
 };
 use actix_web::{web::Data, FromRequest};
+use chrono::NaiveDateTime;
 use color_eyre::Result;
 use futures::future::{ready, Ready};
-use serde_json::Value;
 use sqlx::postgres::PgQueryAs;
 use sqlx::PgPool;
 use std::{ops::Deref, sync::Arc};
 use tracing::instrument;
 use uuid::Uuid;
 
+#[derive(sqlx::FromRow)]
+struct DeletedUser {
+    id: Uuid,
+}
+
 pub struct UserRepository {
     pool: Arc<PgPool>,
 }
@@ -49,159 +56,264 @@ impl UserRepository {
     }
 
     #[instrument(skip(self))]
-    pub async fn find_by_id(&self, id: Uuid) -> Result<Option<User>> {
-        let maybe_user = sqlx::query_as::<_, User>("select * from users where id = $1")
-            .bind(id)
+    pub async fn find_by_email(&self, email: &str) -> Result<Option<User>> {
+        let maybe_user = sqlx::query_as::<_, User>("select * from users where email = $1")
+            .bind(email)
             .fetch_optional(&*self.pool)
             .await?;
-
         Ok(maybe_user)
     }
 
-    pub async fn update_code(&self, code: String) -> Result<Option<User>> {
-        let user = sqlx::query_as::<_, User>("update users set code = $1 returning *")
-            .bind(code)
+    #[instrument(skip(self))]
+    pub async fn find_by_id(&self, id: Uuid) -> Result<Option<User>> {
+        let maybe_user = sqlx::query_as::<_, User>("select * from users where id = $1")
+            .bind(id)
             .fetch_optional(&*self.pool)
             .await?;
-        Ok(user)
-    }
 
-    pub async fn update_token(&self, access_token: String) -> Result<Option<User>> {
-        let user = sqlx::query_as::<_, User>("update users set access_token = $1 returning *")
-            .bind(access_token)
-            .fetch_optional(&*self.pool)
-            .await?;
-        Ok(user)
+        Ok(maybe_user)
     }
 
     #[instrument(skip(self))]
-    pub async fn get_token(&self, id: Uuid) -> Result<Option<User>> {
-        let maybe_token = sqlx::query_as::<_, User>("select * from users where id = $1")
-            .bind(id)
-            .fetch_optional(&*self.pool)
+    pub async fn create_state(
+        &self,
+        nonce: Uuid,
+        user_id: Uuid,
+        expires_at: NaiveDateTime,
+    ) -> Result<()> {
+        sqlx::query("INSERT INTO oauth_states (nonce, user_id, expires_at) VALUES ($1, $2, $3)")
+            .bind(nonce)
+            .bind(user_id)
+            .bind(expires_at)
+            .execute(&*self.pool)
             .await?;
-
-        Ok(maybe_token)
+        Ok(())
     }
 
+    /// Deletes the pending state and returns whether it was still valid, so a
+    /// state can only be used once.
     #[instrument(skip(self))]
-    pub async fn check_cache(&self, user_id: Uuid) -> Result<Option<CheckCache>> {
-        let maybe_cached = sqlx::query_as::<_, CheckCache>(
-            r#"select count(*) as results from transactions where user_id=$1"#,
+    pub async fn consume_state(&self, nonce: Uuid, user_id: Uuid) -> Result<bool> {
+        let deleted = sqlx::query(
+            "DELETE FROM oauth_states WHERE nonce = $1 AND user_id = $2 AND expires_at > current_timestamp",
         )
+        .bind(nonce)
         .bind(user_id)
-        .fetch_optional(&*self.pool)
+        .execute(&*self.pool)
         .await?;
-        Ok(maybe_cached)
+        Ok(deleted > 0)
     }
 
-    #[instrument(skip(self))]
-    pub async fn get_cache(&self, user_id: Uuid) -> Result<Transactions> {
-        let maybe_cached = sqlx::query_as::<_, Transactions>
-        (r#"SELECT cast(json_agg(results) as text) as results FROM transactions where user_id=$1"#)
-            .bind(user_id)
-            .fetch_one(&*self.pool)
-            .await?;
-        Ok(maybe_cached)
+    #[instrument(skip(self, code, crypto))]
+    pub async fn update_code(
+        &self,
+        id: Uuid,
+        code: String,
+        crypto: &CryptoService,
+    ) -> Result<Option<User>> {
+        let code = crypto.encrypt(&code)?;
+        let user =
+            sqlx::query_as::<_, User>("update users set code = $1 where id = $2 returning *")
+                .bind(code)
+                .bind(id)
+                .fetch_optional(&*self.pool)
+                .await?;
+        Ok(user)
     }
 
-    #[instrument(skip(self))]
-    pub async fn save_trans(&self, user_id: Uuid, json_trans: String) -> Result<()> {
-        let serialized: Value = serde_json::from_str(&json_trans).unwrap();
-        sqlx::query_as::<_, Transactions>(
-            r#"INSERT INTO transactions (user_id, results) VALUES($1, to_json($2))"#,
+    #[instrument(skip(self, password, hashing))]
+    pub async fn update_password(
+        &self,
+        id: Uuid,
+        password: String,
+        hashing: &CryptoService,
+    ) -> Result<()> {
+        let password_hash = hashing.hash_password(password).await?;
+        sqlx::query(
+            "update users set password_hash = $1, updated_at = current_timestamp where id = $2",
         )
-        .bind(user_id)
-        .bind(serialized)
-        .fetch_optional(&*self.pool)
+        .bind(password_hash)
+        .bind(id)
+        .execute(&*self.pool)
         .await?;
         Ok(())
     }
 
-    #[instrument(skip(self))]
-    pub async fn daily_transactions(&self, user_id: Uuid) -> Result<Option<Transactions>> {
-        let maybe_daily = sqlx::query_as::<_, Transactions>
-        (r#"SELECT cast(json_agg(results) as TEXT) as results FROM transactions WHERE user_id=$1 AND (results->>'timestamp')::timestamp with time zone > current_timestamp - interval '1 day'"#)
-            .bind(user_id)
-            .fetch_optional(&*self.pool)
+    /// Replaces the hash of an unchanged password, after the hashing settings
+    /// changed. The user isn't considered updated.
+    #[instrument(skip(self, password, hashing))]
+    pub async fn rehash_password(
+        &self,
+        id: Uuid,
+        password: String,
+        hashing: &CryptoService,
+    ) -> Result<()> {
+        let password_hash = hashing.hash_password(password).await?;
+        sqlx::query("update users set password_hash = $1 where id = $2")
+            .bind(password_hash)
+            .bind(id)
+            .execute(&*self.pool)
             .await?;
-        Ok(maybe_daily)
+        Ok(())
     }
 
+    /// Marks the email as verified, unless it changed since the token was
+    /// sent.
     #[instrument(skip(self))]
-    pub async fn weekly_transactions(&self, user_id: Uuid) -> Result<Option<Transactions>> {
-        let maybe_weekly = sqlx::query_as::<_, Transactions>
-        (r#"SELECT cast(json_agg(results) as TEXT) as results FROM transactions WHERE user_id=$1 AND (results->>'timestamp')::timestamp with time zone > current_timestamp - interval '1 week'"#)
-            .bind(user_id)
-            .fetch_optional(&*self.pool)
-            .await?;
-        Ok(maybe_weekly)
+    pub async fn verify_email(&self, id: Uuid, email: &str) -> Result<bool> {
+        let updated = sqlx::query(
+            "update users set email_verified_at = current_timestamp, updated_at = current_timestamp where id = $1 and email = $2",
+        )
+        .bind(id)
+        .bind(email)
+        .execute(&*self.pool)
+        .await?;
+        Ok(updated > 0)
     }
-    
+
+    /// Applies the changes to the profile. A changed email is no longer
+    /// verified.
     #[instrument(skip(self))]
-    pub async fn monthly_transactions(&self, user_id: Uuid) -> Result<Option<Transactions>> {
-        let maybe_monthly = sqlx::query_as::<_, Transactions>
-        (r#"SELECT cast(json_agg(results) as TEXT) as results FROM transactions WHERE user_id=$1 AND (results->>'timestamp')::timestamp with time zone > current_timestamp - interval '1 month'"#)
-            .bind(user_id)
-            .fetch_optional(&*self.pool)
-            .await?;
-        Ok(maybe_monthly)
+    pub async fn update_profile(&self, id: Uuid, update: &UpdateProfile) -> Result<Option<User>> {
+        let user = sqlx::query_as::<_, User>(
+            r#"update users set
+                username = coalesce($2, username),
+                full_name = coalesce($3, full_name),
+                email = coalesce($4::VARCHAR, email),
+                email_verified_at = case when $4::VARCHAR is distinct from email and $4::VARCHAR is not null
+                    then null else email_verified_at end,
+                updated_at = current_timestamp
+            where id = $1 returning *"#,
+        )
+        .bind(id)
+        .bind(&update.username)
+        .bind(&update.full_name)
+        .bind(&update.email)
+        .fetch_optional(&*self.pool)
+        .await?;
+        Ok(user)
     }
 
+    /// Deactivates a user who deleted their account, their data is purged
+    /// later by `purge_deleted`.
     #[instrument(skip(self))]
-    pub async fn credit(&self, user_id: Uuid) -> Result<Option<Transactions>> {
-        let maybe_credit = sqlx::query_as::<_, Transactions>
-        (r#"SELECT cast(json_agg(results) as text) as results FROM transactions WHERE user_id=$1 AND results ->> 'transaction_type' = 'CREDIT'"#)
-            .bind(user_id)
-            .fetch_optional(&*self.pool)
-            .await?;
-        Ok(maybe_credit)
+    pub async fn delete(&self, id: Uuid) -> Result<()> {
+        sqlx::query(
+            "update users set active = false, deleted_at = current_timestamp, updated_at = current_timestamp where id = $1",
+        )
+        .bind(id)
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
     }
 
+    /// Deletes the data of up to `limit` users deleted before `deleted_before`,
+    /// and returns how many were purged.
     #[instrument(skip(self))]
-    pub async fn debit(&self, user_id: Uuid) -> Result<Option<Transactions>> {
-        let maybe_debit = sqlx::query_as::<_, Transactions>
-        (r#"SELECT cast(json_agg(results) as text) as results FROM transactions WHERE user_id=$1 AND results ->> 'transaction_type' = 'DEBIT'"#)
-            .bind(user_id)
-            .fetch_optional(&*self.pool)
-            .await?;
-        Ok(maybe_debit)
+    pub async fn purge_deleted(&self, deleted_before: NaiveDateTime, limit: i64) -> Result<u64> {
+        let users = sqlx::query_as::<_, DeletedUser>(
+            r#"select id from users
+            where deleted_at < $1 and purged_at is null
+            order by deleted_at limit $2"#,
+        )
+        .bind(deleted_before)
+        .bind(limit)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        let mut purged = 0;
+        for user in users {
+            let mut tx = self.pool.begin().await?;
+            for table in &[
+                "transactions",
+                "legacy_transactions",
+                "card_transactions",
+                "cards",
+                "direct_debits",
+                "standing_orders",
+                "balance_snapshots",
+                "accounts",
+                "consents",
+                "sync_accounts",
+                "sync_status",
+                "data_exports",
+            ] {
+                sqlx::query(&format!("delete from {} where user_id = $1", table))
+                    .bind(user.id)
+                    .execute(&mut tx)
+                    .await?;
+            }
+            purged += sqlx::query("update users set purged_at = current_timestamp where id = $1")
+                .bind(user.id)
+                .execute(&mut tx)
+                .await?;
+            tx.commit().await?;
+        }
+        Ok(purged)
     }
-    
+
     #[instrument(skip(self))]
-    pub async fn total_week_transactions(&self, user_id: Uuid) -> Result<Option<Transactions>> {
-        let maybe_total = sqlx::query_as::<_, Transactions>
-        (r#"SELECT cast(json_agg(row_to_json(results)) as text) as results
-            FROM (
-                SELECT results->>'transaction_category' as transaction_category,
-                sum(cast(results->>'amount' as numeric)) as total_amount  from "transactions"
-                WHERE user_id=$1
-                AND (results->>'timestamp')::timestamp with time zone > current_timestamp - interval '1 week'  
-                GROUP BY results->>'transaction_category'
-            ) results"#)
-            .bind(user_id)
-            .fetch_optional(&*self.pool)
+    pub async fn update_role(&self, id: Uuid, role: Role) -> Result<Option<User>> {
+        let user = sqlx::query_as::<_, User>(
+            "update users set role = $1, updated_at = current_timestamp where id = $2 returning *",
+        )
+        .bind(role.as_str())
+        .bind(id)
+        .fetch_optional(&*self.pool)
+        .await?;
+        Ok(user)
+    }
+
+    /// Re-encrypts every stored authorization code under the current
+    /// encryption key and returns how many rows were rewritten.
+    #[instrument(skip(self, crypto))]
+    pub async fn rotate_encryption(&self, crypto: &CryptoService) -> Result<u64> {
+        let users = sqlx::query_as::<_, User>("select * from users where code is not null")
+            .fetch_all(&*self.pool)
             .await?;
-        Ok(maybe_total)
+
+        let mut rotated = 0;
+        for user in users {
+            let code = match user.code {
+                Some(code) if crypto.needs_rotation(&code) => code,
+                _ => continue,
+            };
+
+            rotated += sqlx::query("update users set code = $1 where id = $2 and code = $3")
+                .bind(crypto.reencrypt(&code)?)
+                .bind(user.id)
+                .bind(&code)
+                .execute(&*self.pool)
+                .await?;
+        }
+        Ok(rotated)
     }
-    
-    #[instrument(skip(self))]
-    pub async fn total_month_transactions(&self, user_id: Uuid) -> Result<Option<Transactions>> {
-        let maybe_total = sqlx::query_as::<_, Transactions>
-        (r#"SELECT cast(json_agg(row_to_json(results)) as text) as results
-            FROM (
-                SELECT results->>'transaction_category' as transaction_category,
-                sum(cast(results->>'amount' as numeric)) as total_amount  from "transactions"
-                WHERE user_id=$1
-                AND (results->>'timestamp')::timestamp with time zone > current_timestamp - interval '1 month'  
-                GROUP BY results->>'transaction_category'
-            ) results"#)
-            .bind(user_id)
-            .fetch_optional(&*self.pool)
+
+    /// Encrypts the authorization codes stored in plaintext before encryption
+    /// was introduced, and returns how many rows were rewritten.
+    #[instrument(skip(self, crypto))]
+    pub async fn encrypt_legacy(&self, crypto: &CryptoService) -> Result<u64> {
+        let users = sqlx::query_as::<_, User>("select * from users where code not like $1")
+            .bind(format!("{}:%", ENCRYPTED_PREFIX))
+            .fetch_all(&*self.pool)
             .await?;
-        Ok(maybe_total)
+
+        let mut encrypted = 0;
+        for user in users {
+            let code = match user.code {
+                Some(code) => code,
+                None => continue,
+            };
+
+            encrypted += sqlx::query("update users set code = $1 where id = $2 and code = $3")
+                .bind(crypto.encrypt_legacy(&code)?)
+                .bind(user.id)
+                .bind(&code)
+                .execute(&*self.pool)
+                .await?;
+        }
+        Ok(encrypted)
     }
-    
 }
 
 impl FromRequest for UserRepository {
@@ -0,0 +1,106 @@
+use crate::{errors::AppError, models::token::UserToken};
+use actix_web::{web::Data, FromRequest};
+use chrono::{DateTime, Utc};
+use color_eyre::Result;
+use futures::future::{ready, Ready};
+use sqlx::postgres::PgQueryAs;
+use sqlx::PgPool;
+use std::{ops::Deref, sync::Arc};
+use tracing::instrument;
+use uuid::Uuid;
+
+pub struct UserTokenRepository {
+    pool: Arc<PgPool>,
+}
+
+impl UserTokenRepository {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        Self { pool }
+    }
+
+    /// Stores a new token, the ones previously issued for the same purpose
+    /// stop working.
+    #[instrument(skip(self, token_hash))]
+    pub async fn create(
+        &self,
+        user_id: Uuid,
+        purpose: &str,
+        token_hash: String,
+        email: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<()> {
+        self.invalidate(user_id, purpose).await?;
+        sqlx::query(
+            r#"INSERT INTO user_tokens (user_id, purpose, token_hash, email, expires_at)
+            VALUES ($1, $2, $3, $4, $5)"#,
+        )
+        .bind(user_id)
+        .bind(purpose)
+        .bind(token_hash)
+        .bind(email)
+        .bind(expires_at)
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Finds a token that can still be used, without consuming it.
+    #[instrument(skip(self, token_hash))]
+    pub async fn find(&self, purpose: &str, token_hash: &str) -> Result<Option<UserToken>> {
+        let token = sqlx::query_as::<_, UserToken>(
+            r#"SELECT user_id, email FROM user_tokens
+            WHERE purpose = $1 AND token_hash = $2 AND used_at IS NULL AND expires_at > current_timestamp"#,
+        )
+        .bind(purpose)
+        .bind(token_hash)
+        .fetch_optional(&*self.pool)
+        .await?;
+        Ok(token)
+    }
+
+    /// Marks a token as used and returns it, `None` if it doesn't exist, has
+    /// expired or was already used.
+    #[instrument(skip(self, token_hash))]
+    pub async fn consume(&self, purpose: &str, token_hash: &str) -> Result<Option<UserToken>> {
+        let token = sqlx::query_as::<_, UserToken>(
+            r#"UPDATE user_tokens SET used_at = current_timestamp
+            WHERE purpose = $1 AND token_hash = $2 AND used_at IS NULL AND expires_at > current_timestamp
+            RETURNING user_id, email"#,
+        )
+        .bind(purpose)
+        .bind(token_hash)
+        .fetch_optional(&*self.pool)
+        .await?;
+        Ok(token)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn invalidate(&self, user_id: Uuid, purpose: &str) -> Result<()> {
+        sqlx::query(
+            "UPDATE user_tokens SET used_at = current_timestamp WHERE user_id = $1 AND purpose = $2 AND used_at IS NULL",
+        )
+        .bind(user_id)
+        .bind(purpose)
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+impl FromRequest for UserTokenRepository {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+    #[instrument(skip(req, payload))]
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let pool_result = Data::<PgPool>::from_request(req, payload).into_inner();
+
+        match pool_result {
+            Ok(pool) => ready(Ok(UserTokenRepository::new(pool.deref().clone()))),
+            _ => ready(Err(AppError::NOT_AUTHORIZED.default())),
+        }
+    }
+}
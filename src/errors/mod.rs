@@ -2,15 +2,17 @@ use actix_web::error::ResponseError;
 use actix_web::http::StatusCode;
 use actix_web::HttpResponse;
 use color_eyre::Report;
-use serde::export::Formatter;
 use serde::{Serialize, Serializer};
 use std::convert::From;
+use std::fmt::Formatter;
 use tracing::error;
 
 #[derive(Debug, Serialize)]
 pub struct AppError {
     message: String,
     code: AppErrorCode,
+    #[serde(skip)]
+    status: Option<StatusCode>,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -21,6 +23,7 @@ impl AppErrorCode {
         AppError {
             message: _message,
             code: self,
+            status: None,
         }
     }
 
@@ -30,11 +33,31 @@ impl AppErrorCode {
             AppError::INVALID_CREDENTIALS => "Invalid username or password.",
             AppError::NOT_AUTHORIZED => "Not authorized.",
             AppError::NOT_FOUND => "Item not found.",
+            AppError::INVALID_STATE => "Invalid or expired authorization state.",
+            AppError::RELINK_REQUIRED => {
+                "Bank account link expired. Please link your account again."
+            }
+            AppError::INVALID_MFA_CODE => "Invalid two-factor authentication code.",
+            AppError::ACCOUNT_LOCKED => {
+                "Account temporarily locked after too many failed attempts."
+            }
+            AppError::ACCOUNT_INACTIVE => "Account is inactive.",
+            AppError::TOO_MANY_ATTEMPTS => "Too many failed attempts. Please try again later.",
+            AppError::CONSENT_EXPIRED => {
+                "Bank consent expired. Please grant access to your bank account again."
+            }
+            AppError::UPSTREAM_TIMEOUT => {
+                "The bank did not respond in time. Please try again later."
+            }
+            AppError::UPSTREAM_UNAVAILABLE => "The bank is unavailable. Please try again later.",
+            AppError::UPSTREAM_REJECTED => "The bank rejected the request.",
+            AppError::UPSTREAM_BAD_PAYLOAD => "The bank sent an invalid response.",
             _ => "An unexpected error has occurred.",
         };
         AppError {
             message: message.to_string(),
             code: self,
+            status: None,
         }
     }
 }
@@ -46,11 +69,29 @@ impl From<AppErrorCode> for AppError {
 }
 
 impl AppError {
+    /// Overrides the status the code maps to, e.g. to answer a
+    /// `NOT_AUTHORIZED` with 403 once the caller is authenticated.
+    pub fn status(mut self, status: StatusCode) -> Self {
+        self.status = Some(status);
+        self
+    }
+
     pub const INTERNAL_ERROR: AppErrorCode = AppErrorCode(1001);
     pub const INVALID_INPUT: AppErrorCode = AppErrorCode(2001);
     pub const INVALID_CREDENTIALS: AppErrorCode = AppErrorCode(3001);
     pub const NOT_AUTHORIZED: AppErrorCode = AppErrorCode(3002);
+    pub const INVALID_STATE: AppErrorCode = AppErrorCode(3003);
+    pub const RELINK_REQUIRED: AppErrorCode = AppErrorCode(3004);
+    pub const INVALID_MFA_CODE: AppErrorCode = AppErrorCode(3005);
+    pub const ACCOUNT_LOCKED: AppErrorCode = AppErrorCode(3006);
+    pub const ACCOUNT_INACTIVE: AppErrorCode = AppErrorCode(3007);
+    pub const TOO_MANY_ATTEMPTS: AppErrorCode = AppErrorCode(3008);
+    pub const CONSENT_EXPIRED: AppErrorCode = AppErrorCode(3009);
     pub const NOT_FOUND: AppErrorCode = AppErrorCode(4001);
+    pub const UPSTREAM_TIMEOUT: AppErrorCode = AppErrorCode(5001);
+    pub const UPSTREAM_UNAVAILABLE: AppErrorCode = AppErrorCode(5002);
+    pub const UPSTREAM_REJECTED: AppErrorCode = AppErrorCode(5003);
+    pub const UPSTREAM_BAD_PAYLOAD: AppErrorCode = AppErrorCode(5004);
 }
 
 impl Serialize for AppErrorCode {
@@ -71,11 +112,25 @@ impl From<Report> for AppError {
 
 impl ResponseError for AppError {
     fn status_code(&self) -> StatusCode {
+        if let Some(status) = self.status {
+            return status;
+        }
         match self.code {
             AppError::INVALID_INPUT => StatusCode::BAD_REQUEST,
             AppError::NOT_FOUND => StatusCode::NOT_FOUND,
             AppError::INVALID_CREDENTIALS => StatusCode::UNAUTHORIZED,
             AppError::NOT_AUTHORIZED => StatusCode::UNAUTHORIZED,
+            AppError::INVALID_STATE => StatusCode::BAD_REQUEST,
+            AppError::RELINK_REQUIRED => StatusCode::FORBIDDEN,
+            AppError::INVALID_MFA_CODE => StatusCode::UNAUTHORIZED,
+            AppError::ACCOUNT_LOCKED => StatusCode::LOCKED,
+            AppError::ACCOUNT_INACTIVE => StatusCode::FORBIDDEN,
+            AppError::TOO_MANY_ATTEMPTS => StatusCode::TOO_MANY_REQUESTS,
+            AppError::CONSENT_EXPIRED => StatusCode::FORBIDDEN,
+            AppError::UPSTREAM_TIMEOUT => StatusCode::GATEWAY_TIMEOUT,
+            AppError::UPSTREAM_UNAVAILABLE => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::UPSTREAM_REJECTED => StatusCode::BAD_GATEWAY,
+            AppError::UPSTREAM_BAD_PAYLOAD => StatusCode::BAD_GATEWAY,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
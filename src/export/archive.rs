@@ -0,0 +1,484 @@
+use crate::models::{
+    export::{ExportData, ExportedTransaction},
+    money::Money,
+};
+use color_eyre::Result;
+use eyre::eyre;
+use std::fmt::Display;
+use std::io::{Cursor, Seek, Write};
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+fn optional<T: Display>(value: &Option<T>) -> String {
+    value.as_ref().map(ToString::to_string).unwrap_or_default()
+}
+
+fn amount(money: &Option<Money>) -> String {
+    optional(&money.as_ref().map(Money::rounded))
+}
+
+const TRANSACTION_HEADER: &[&str] = &[
+    "account_id",
+    "transaction_id",
+    "booked_at",
+    "amount",
+    "currency",
+    "transaction_type",
+    "transaction_category",
+    "description",
+];
+
+fn transaction_rows(transactions: &[ExportedTransaction]) -> Vec<Vec<String>> {
+    transactions
+        .iter()
+        .map(|transaction| {
+            vec![
+                transaction.account_id.clone(),
+                transaction.provider_transaction_id.clone(),
+                transaction.booked_at.to_rfc3339(),
+                transaction.amount.to_string(),
+                transaction.currency.clone(),
+                optional(&transaction.transaction_type),
+                optional(&transaction.transaction_category),
+                optional(&transaction.description),
+            ]
+        })
+        .collect()
+}
+
+fn write_csv<W: Write + Seek>(
+    zip: &mut ZipWriter<W>,
+    options: FileOptions,
+    name: &str,
+    header: &[&str],
+    rows: Vec<Vec<String>>,
+) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(header)?;
+    for row in rows {
+        writer.write_record(&row)?;
+    }
+    let bytes = writer
+        .into_inner()
+        .map_err(|err| eyre!("Writing {}: {}", name, err))?;
+
+    zip.start_file(name, options)?;
+    zip.write_all(&bytes)?;
+    Ok(())
+}
+
+/// Builds the zip archive of a data export: everything in `export.json`, and
+/// a CSV file per table for spreadsheets.
+pub fn build(data: &ExportData) -> Result<Vec<u8>> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    zip.start_file("export.json", options)?;
+    zip.write_all(&serde_json::to_vec_pretty(data)?)?;
+
+    let profile = &data.profile;
+    write_csv(
+        &mut zip,
+        options,
+        "profile.csv",
+        &[
+            "id",
+            "username",
+            "email",
+            "full_name",
+            "role",
+            "email_verified_at",
+            "created_at",
+            "updated_at",
+        ],
+        vec![vec![
+            profile.id.to_string(),
+            optional(&profile.username),
+            profile.email.clone(),
+            optional(&profile.full_name),
+            profile.role.clone(),
+            optional(&profile.email_verified_at),
+            profile.created_at.to_string(),
+            profile.updated_at.to_string(),
+        ]],
+    )?;
+
+    write_csv(
+        &mut zip,
+        options,
+        "providers.csv",
+        &[
+            "provider",
+            "scopes",
+            "created_at",
+            "updated_at",
+            "expires_at",
+        ],
+        data.providers
+            .iter()
+            .map(|provider| {
+                vec![
+                    provider.provider.clone(),
+                    optional(&provider.scopes),
+                    provider.created_at.to_string(),
+                    provider.updated_at.to_string(),
+                    provider.expires_at.to_string(),
+                ]
+            })
+            .collect(),
+    )?;
+
+    write_csv(
+        &mut zip,
+        options,
+        "consents.csv",
+        &[
+            "provider",
+            "scopes",
+            "status",
+            "granted_at",
+            "expires_at",
+            "revoked_at",
+        ],
+        data.consents
+            .iter()
+            .map(|consent| {
+                vec![
+                    consent.provider.clone(),
+                    optional(&consent.scopes),
+                    consent.status.clone(),
+                    consent.granted_at.to_rfc3339(),
+                    consent.expires_at.to_rfc3339(),
+                    optional(&consent.revoked_at.map(|at| at.to_rfc3339())),
+                ]
+            })
+            .collect(),
+    )?;
+
+    write_csv(
+        &mut zip,
+        options,
+        "api_keys.csv",
+        &[
+            "name",
+            "prefix",
+            "scopes",
+            "created_at",
+            "last_used_at",
+            "expires_at",
+        ],
+        data.api_keys
+            .iter()
+            .map(|key| {
+                vec![
+                    key.name.clone(),
+                    key.prefix.clone(),
+                    key.scopes
+                        .iter()
+                        .map(|scope| scope.as_str())
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                    key.created_at.to_rfc3339(),
+                    optional(&key.last_used_at),
+                    optional(&key.expires_at),
+                ]
+            })
+            .collect(),
+    )?;
+
+    write_csv(
+        &mut zip,
+        options,
+        "auth_events.csv",
+        &["created_at", "event", "ip", "user_agent"],
+        data.auth_events
+            .iter()
+            .map(|event| {
+                vec![
+                    event.created_at.to_rfc3339(),
+                    event.event.clone(),
+                    optional(&event.ip),
+                    optional(&event.user_agent),
+                ]
+            })
+            .collect(),
+    )?;
+
+    write_csv(
+        &mut zip,
+        options,
+        "transactions.csv",
+        TRANSACTION_HEADER,
+        transaction_rows(&data.transactions),
+    )?;
+
+    write_csv(
+        &mut zip,
+        options,
+        "accounts.csv",
+        &["account_id", "account_type", "display_name", "currency"],
+        data.accounts
+            .iter()
+            .map(|account| {
+                vec![
+                    account.account_id.clone(),
+                    optional(&account.account_type),
+                    optional(&account.display_name),
+                    optional(&account.currency),
+                ]
+            })
+            .collect(),
+    )?;
+
+    write_csv(
+        &mut zip,
+        options,
+        "balances.csv",
+        &[
+            "provider",
+            "account_id",
+            "date",
+            "currency",
+            "current",
+            "available",
+            "overdraft",
+            "fetched_at",
+        ],
+        data.balances
+            .iter()
+            .map(|balance| {
+                vec![
+                    balance.provider.clone(),
+                    balance.account_id.clone(),
+                    balance.snapshot_date.to_string(),
+                    balance.currency.clone(),
+                    balance.current.to_string(),
+                    optional(&balance.available),
+                    optional(&balance.overdraft),
+                    balance.fetched_at.to_rfc3339(),
+                ]
+            })
+            .collect(),
+    )?;
+
+    write_csv(
+        &mut zip,
+        options,
+        "cards.csv",
+        &[
+            "account_id",
+            "card_network",
+            "card_type",
+            "currency",
+            "display_name",
+            "partial_card_number",
+            "name_on_card",
+            "valid_from",
+            "valid_to",
+        ],
+        data.cards
+            .iter()
+            .map(|card| {
+                vec![
+                    card.account_id.clone(),
+                    optional(&card.card_network),
+                    optional(&card.card_type),
+                    optional(&card.currency),
+                    optional(&card.display_name),
+                    optional(&card.partial_card_number),
+                    optional(&card.name_on_card),
+                    optional(&card.valid_from),
+                    optional(&card.valid_to),
+                ]
+            })
+            .collect(),
+    )?;
+
+    write_csv(
+        &mut zip,
+        options,
+        "card_transactions.csv",
+        TRANSACTION_HEADER,
+        transaction_rows(&data.card_transactions),
+    )?;
+
+    write_csv(
+        &mut zip,
+        options,
+        "direct_debits.csv",
+        &[
+            "account_id",
+            "direct_debit_id",
+            "name",
+            "status",
+            "timestamp",
+            "previous_payment_timestamp",
+            "previous_payment_amount",
+            "currency",
+        ],
+        data.direct_debits
+            .iter()
+            .map(|direct_debit| {
+                vec![
+                    direct_debit.account_id.clone(),
+                    direct_debit.direct_debit_id.clone(),
+                    direct_debit.name.clone(),
+                    optional(&direct_debit.status),
+                    optional(&direct_debit.mandated_at.map(|at| at.to_rfc3339())),
+                    optional(&direct_debit.previous_payment_at.map(|at| at.to_rfc3339())),
+                    amount(&direct_debit.previous_payment_amount),
+                    optional(
+                        &direct_debit
+                            .previous_payment_amount
+                            .as_ref()
+                            .map(Money::currency),
+                    ),
+                ]
+            })
+            .collect(),
+    )?;
+
+    write_csv(
+        &mut zip,
+        options,
+        "standing_orders.csv",
+        &[
+            "account_id",
+            "frequency",
+            "status",
+            "reference",
+            "payee",
+            "next_payment_date",
+            "next_payment_amount",
+            "first_payment_date",
+            "first_payment_amount",
+            "final_payment_date",
+            "final_payment_amount",
+        ],
+        data.standing_orders
+            .iter()
+            .map(|order| {
+                vec![
+                    order.account_id.clone(),
+                    optional(&order.frequency),
+                    optional(&order.status),
+                    optional(&order.reference),
+                    optional(&order.payee),
+                    optional(&order.next_payment_at.map(|at| at.to_rfc3339())),
+                    amount(&order.next_payment_amount),
+                    optional(&order.first_payment_at.map(|at| at.to_rfc3339())),
+                    amount(&order.first_payment_amount),
+                    optional(&order.final_payment_at.map(|at| at.to_rfc3339())),
+                    amount(&order.final_payment_amount),
+                ]
+            })
+            .collect(),
+    )?;
+
+    Ok(zip.finish()?.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::user::User;
+    use bigdecimal::BigDecimal;
+    use chrono::{TimeZone, Utc};
+    use std::io::Read;
+    use std::str::FromStr;
+    use uuid::Uuid;
+    use zip::ZipArchive;
+
+    fn export_data() -> ExportData {
+        let created_at = Utc.with_ymd_and_hms(2020, 12, 4, 9, 0, 0).unwrap();
+        ExportData {
+            exported_at: created_at,
+            profile: User {
+                id: Uuid::nil(),
+                username: Some("jdoe".to_string()),
+                email: "jane.doe@example.com".to_string(),
+                password_hash: "hash".to_string(),
+                full_name: None,
+                active: true,
+                code: None,
+                role: "customer".to_string(),
+                email_verified_at: None,
+                created_at: created_at.naive_utc(),
+                updated_at: created_at.naive_utc(),
+            },
+            providers: Vec::new(),
+            consents: Vec::new(),
+            api_keys: Vec::new(),
+            auth_events: Vec::new(),
+            transactions: vec![ExportedTransaction {
+                account_id: "mock-current-account".to_string(),
+                provider_transaction_id: "tx-1".to_string(),
+                booked_at: created_at,
+                amount: BigDecimal::from_str("-12.50").unwrap(),
+                currency: "GBP".to_string(),
+                transaction_type: Some("DEBIT".to_string()),
+                transaction_category: None,
+                description: Some("Coffee, beans".to_string()),
+                raw: serde_json::json!({}),
+                created_at: created_at.naive_utc(),
+            }],
+            accounts: Vec::new(),
+            balances: Vec::new(),
+            cards: Vec::new(),
+            card_transactions: Vec::new(),
+            direct_debits: Vec::new(),
+            standing_orders: Vec::new(),
+        }
+    }
+
+    fn read_entry(archive: &mut ZipArchive<Cursor<Vec<u8>>>, name: &str) -> String {
+        let mut content = String::new();
+        archive
+            .by_name(name)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    }
+
+    #[test]
+    fn builds_json_and_csv_entries() {
+        let bytes = build(&export_data()).unwrap();
+        let mut archive = ZipArchive::new(Cursor::new(bytes)).unwrap();
+
+        let names: Vec<String> = (0..archive.len())
+            .map(|index| archive.by_index(index).unwrap().name().to_string())
+            .collect();
+        assert_eq!(names[0], "export.json");
+        for name in &[
+            "profile.csv",
+            "providers.csv",
+            "consents.csv",
+            "api_keys.csv",
+            "auth_events.csv",
+            "transactions.csv",
+            "accounts.csv",
+            "balances.csv",
+            "cards.csv",
+            "card_transactions.csv",
+            "direct_debits.csv",
+            "standing_orders.csv",
+        ] {
+            assert!(names.iter().any(|entry| entry == name), "{}", name);
+        }
+
+        let transactions = read_entry(&mut archive, "transactions.csv");
+        let lines: Vec<&str> = transactions.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "account_id,transaction_id,booked_at,amount,currency,transaction_type,transaction_category,description",
+                "mock-current-account,tx-1,2020-12-04T09:00:00+00:00,-12.50,GBP,DEBIT,,\"Coffee, beans\"",
+            ]
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_str(&read_entry(&mut archive, "export.json")).unwrap();
+        assert_eq!(json["profile"]["email"], "jane.doe@example.com");
+        assert!(json["profile"].get("password_hash").is_none());
+    }
+}
@@ -0,0 +1,132 @@
+pub mod archive;
+
+use crate::{
+    db::{
+        account::AccountRepository, api_key::ApiKeyRepository, auth_event::AuthEventRepository,
+        card::CardRepository, consent::ConsentRepository, export::ExportRepository,
+        payment::PaymentRepository, provider::ProviderRepository, trans::TransactionRepository,
+        user::UserRepository,
+    },
+    models::export::{DataExport, ExportData},
+};
+use chrono::{Duration, Utc};
+use color_eyre::Result;
+use eyre::eyre;
+use sqlx::PgPool;
+use std::sync::Arc;
+use tracing::{debug, error, info, instrument};
+use uuid::Uuid;
+
+/// How often the worker looks for pending exports.
+const POLL_SECONDS: u64 = 10;
+/// A running export not finished after this long is considered abandoned by a
+/// worker that stopped, and is started again.
+const STALE_MINUTES: i64 = 30;
+
+/// Builds the data exports users request, in the background.
+#[derive(Clone)]
+pub struct Exporter {
+    pool: Arc<PgPool>,
+    /// How long an archive can be downloaded once ready.
+    ttl: Duration,
+    public_url: Arc<String>,
+}
+
+impl Exporter {
+    pub fn new(pool: PgPool, ttl: Duration, public_url: String) -> Self {
+        Exporter {
+            pool: Arc::new(pool),
+            ttl,
+            public_url: Arc::new(public_url),
+        }
+    }
+
+    /// Link to download an export with a token from
+    /// `CryptoService::generate_export_token`.
+    pub fn download_url(&self, export_id: Uuid, token: &str) -> String {
+        format!(
+            "{}/exports/{}/download?token={}",
+            self.public_url, export_id, token
+        )
+    }
+
+    /// Runs the exports forever, to be spawned on the actix runtime.
+    pub async fn run(self) {
+        info!("Starting data exports");
+        loop {
+            if let Err(err) = self.export_pending().await {
+                error!("Data exports failed. {:?}", err);
+            }
+            tokio::time::delay_for(std::time::Duration::from_secs(POLL_SECONDS)).await;
+        }
+    }
+
+    async fn export_pending(&self) -> Result<()> {
+        let exports = ExportRepository::new(self.pool.clone());
+
+        let expired = exports.expire().await?;
+        if expired > 0 {
+            debug!("Dropped {} expired export archives", expired);
+        }
+
+        let stale_before = Utc::now() - Duration::minutes(STALE_MINUTES);
+        while let Some(export) = exports.claim_next(stale_before).await? {
+            match self.build(&export).await {
+                Ok(archive) => {
+                    exports
+                        .complete(export.id, archive, Utc::now() + self.ttl)
+                        .await?
+                }
+                Err(err) => {
+                    error!("Data export {} failed. {:?}", export.id, err);
+                    exports
+                        .fail(export.id, "The export could not be generated.".to_string())
+                        .await?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Collects everything stored about the user and archives it.
+    #[instrument(skip(self))]
+    async fn build(&self, export: &DataExport) -> Result<Vec<u8>> {
+        let user_id = export.user_id;
+        let profile = UserRepository::new(self.pool.clone())
+            .find_by_id(user_id)
+            .await?
+            .ok_or_else(|| eyre!("User {} not found", user_id))?;
+
+        let accounts = AccountRepository::new(self.pool.clone());
+        let payments = PaymentRepository::new(self.pool.clone());
+        let transactions = TransactionRepository::new(self.pool.clone());
+        let data = ExportData {
+            exported_at: Utc::now(),
+            profile,
+            providers: ProviderRepository::new(self.pool.clone())
+                .linked_providers(user_id)
+                .await?,
+            consents: ConsentRepository::new(self.pool.clone())
+                .list(user_id)
+                .await?,
+            api_keys: ApiKeyRepository::new(self.pool.clone())
+                .list(user_id)
+                .await?
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            auth_events: AuthEventRepository::new(self.pool.clone())
+                .all(user_id)
+                .await?,
+            transactions: transactions.export(user_id).await?,
+            accounts: accounts.all(user_id).await?,
+            balances: accounts.snapshots(user_id).await?,
+            cards: CardRepository::new(self.pool.clone()).all(user_id).await?,
+            card_transactions: transactions.export_cards(user_id).await?,
+            direct_debits: payments.all_direct_debits(user_id).await?,
+            standing_orders: payments.all_standing_orders(user_id).await?,
+        };
+
+        archive::build(&data)
+    }
+}
@@ -0,0 +1,135 @@
+use super::{auth::AuthenticatedUser, provider_error, AppResponse};
+use crate::{
+    config::crypto::CryptoService,
+    db::{account::AccountRepository, consent::ConsentRepository, provider::ProviderRepository},
+    errors::AppError,
+    models::account::{AccountsResults, BalanceHistory, BalanceHistoryQuery, DEFAULT_HISTORY_DAYS},
+    providers::{session::ProviderSession, BankingProvider},
+};
+use actix_web::{
+    web::{self, Data},
+    HttpResponse,
+};
+use chrono::{Duration, Utc};
+use tracing::{debug, instrument};
+use validator::Validate;
+
+/// Accounts of the user as last synced. They are fetched from the provider,
+/// and stored, only when none were synced yet.
+#[instrument(skip(
+    user,
+    repository,
+    provider_repository,
+    consents,
+    crypto_service,
+    provider
+))]
+pub async fn accounts(
+    user: AuthenticatedUser,
+    repository: AccountRepository,
+    provider_repository: ProviderRepository,
+    consents: ConsentRepository,
+    crypto_service: Data<CryptoService>,
+    provider: Data<dyn BankingProvider>,
+) -> AppResponse {
+    let stored = repository.list(user.id, provider.name()).await?;
+    if !stored.is_empty() {
+        return Ok(HttpResponse::Ok().json(AccountsResults { results: stored }));
+    }
+
+    let mut session = ProviderSession::open(
+        &**provider,
+        &provider_repository,
+        &consents,
+        &crypto_service,
+        user.id,
+    )
+    .await
+    .map_err(provider_error)?;
+    let accounts = session.accounts().await.map_err(provider_error)?;
+    repository.save(user.id, provider.name(), &accounts).await?;
+
+    Ok(HttpResponse::Ok().json(AccountsResults { results: accounts }))
+}
+
+/// Current balance of an account, recorded as today's snapshot.
+#[instrument(skip(
+    user,
+    repository,
+    provider_repository,
+    consents,
+    crypto_service,
+    provider
+))]
+pub async fn balance(
+    user: AuthenticatedUser,
+    id: web::Path<String>,
+    repository: AccountRepository,
+    provider_repository: ProviderRepository,
+    consents: ConsentRepository,
+    crypto_service: Data<CryptoService>,
+    provider: Data<dyn BankingProvider>,
+) -> AppResponse {
+    let mut session = ProviderSession::open(
+        &**provider,
+        &provider_repository,
+        &consents,
+        &crypto_service,
+        user.id,
+    )
+    .await
+    .map_err(provider_error)?;
+
+    if repository
+        .find(user.id, provider.name(), &id)
+        .await?
+        .is_none()
+    {
+        // The account may have been opened since the accounts were stored
+        let accounts = session.accounts().await.map_err(provider_error)?;
+        repository.save(user.id, provider.name(), &accounts).await?;
+        if !accounts.iter().any(|account| account.account_id == *id) {
+            return Err(AppError::NOT_FOUND.into());
+        }
+    }
+
+    let balance = session.balance(&id).await.map_err(provider_error)?;
+    let balance = repository
+        .save_balance(user.id, provider.name(), &id, &balance)
+        .await?;
+    Ok(HttpResponse::Ok().json(balance))
+}
+
+/// Daily balances of an account from the stored snapshots, the provider is
+/// not called.
+#[instrument(skip(user, repository, provider))]
+pub async fn balance_history(
+    user: AuthenticatedUser,
+    id: web::Path<String>,
+    repository: AccountRepository,
+    provider: Data<dyn BankingProvider>,
+    web::Query(query): web::Query<BalanceHistoryQuery>,
+) -> AppResponse {
+    query.validate().map_err(|errors| {
+        debug!("Invalid balance history query. {:?}", errors);
+        AppError::INVALID_INPUT
+            .message("Invalid query. \"from\" must be before \"to\".".to_string())
+    })?;
+    repository
+        .find(user.id, provider.name(), &id)
+        .await?
+        .ok_or(AppError::NOT_FOUND)?;
+
+    let to = query.to.unwrap_or_else(|| Utc::now().date_naive());
+    let from = query
+        .from
+        .unwrap_or_else(|| to - Duration::days(DEFAULT_HISTORY_DAYS));
+    let results = repository
+        .balance_history(user.id, provider.name(), &id, from, to)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(BalanceHistory {
+        account_id: id.into_inner(),
+        results,
+    }))
+}
@@ -0,0 +1,113 @@
+use super::{auth::AuthenticatedUser, AppResponse};
+use crate::{
+    config::crypto::CryptoService,
+    db::api_key::ApiKeyRepository,
+    errors::AppError,
+    models::api_key::{ApiKeyInfo, CreatedApiKey, NewApiKey, API_KEY_PREFIX, API_KEY_SCOPES},
+    models::role::Permission,
+};
+use actix_web::{
+    web::{self, Data, Json},
+    HttpResponse,
+};
+use chrono::{Duration, Utc};
+use tracing::{debug, instrument};
+use uuid::Uuid;
+use validator::Validate;
+
+const MAX_API_KEYS: i64 = 20;
+/// Characters of the key kept to recognise it, prefix included.
+const DISPLAY_PREFIX_LENGTH: usize = 12;
+
+/// Creates an API key. The key is only returned in this response.
+#[instrument(skip(user, request, api_keys, hashing))]
+pub async fn create_api_key(
+    user: AuthenticatedUser,
+    request: Json<NewApiKey>,
+    api_keys: ApiKeyRepository,
+    hashing: Data<CryptoService>,
+) -> AppResponse {
+    request.validate().map_err(|errors| {
+        debug!("Invalid api key. {:?}", errors);
+        AppError::INVALID_INPUT.message(
+            "Invalid api key. The name must be 1 to 100 characters and the expiration 1 to 365 days."
+                .to_string(),
+        )
+    })?;
+
+    let scopes = request
+        .scopes
+        .clone()
+        .unwrap_or_else(|| vec![Permission::ReadTransactions]);
+    if scopes.is_empty() {
+        return Err(AppError::INVALID_INPUT.message("At least one scope is required.".to_string()));
+    }
+    if let Some(scope) = scopes
+        .iter()
+        .find(|scope| !API_KEY_SCOPES.contains(*scope) || !user.can(**scope))
+    {
+        return Err(AppError::INVALID_INPUT.message(format!(
+            "Scope {} cannot be granted to an api key.",
+            scope.as_str()
+        )));
+    }
+
+    if api_keys.count_active(user.id).await? >= MAX_API_KEYS {
+        return Err(AppError::INVALID_INPUT.message(format!(
+            "Too many api keys. Revoke one first, at most {} can be active.",
+            MAX_API_KEYS
+        )));
+    }
+
+    let (secret, _) = hashing.generate_token();
+    let key = format!("{}{}", API_KEY_PREFIX, secret);
+    let prefix: String = key.chars().take(DISPLAY_PREFIX_LENGTH).collect();
+    let scopes = scopes
+        .iter()
+        .map(|scope| scope.as_str())
+        .collect::<Vec<_>>()
+        .join(",");
+    let expires_at = request
+        .expires_in_days
+        .map(|days| Utc::now() + Duration::days(days));
+
+    let created = api_keys
+        .create(
+            user.id,
+            request.name.trim(),
+            &prefix,
+            hashing.hash_token(&key),
+            &scopes,
+            expires_at,
+        )
+        .await?;
+
+    Ok(HttpResponse::Created().json(CreatedApiKey {
+        info: created.into(),
+        key,
+    }))
+}
+
+/// The API keys of the user that haven't been revoked.
+#[instrument(skip(user, api_keys))]
+pub async fn api_keys(user: AuthenticatedUser, api_keys: ApiKeyRepository) -> AppResponse {
+    let keys: Vec<ApiKeyInfo> = api_keys
+        .list(user.id)
+        .await?
+        .into_iter()
+        .map(ApiKeyInfo::from)
+        .collect();
+    Ok(HttpResponse::Ok().json(keys))
+}
+
+#[instrument(skip(user, api_keys))]
+pub async fn revoke_api_key(
+    user: AuthenticatedUser,
+    api_keys: ApiKeyRepository,
+    id: web::Path<Uuid>,
+) -> AppResponse {
+    if !api_keys.revoke(user.id, *id).await? {
+        return Err(AppError::NOT_FOUND.into());
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -1,21 +1,74 @@
-use super::AppResponse;
+use super::{throttle, AppResponse};
 use crate::{
-    config::crypto::{Auth, CryptoService},
-    db::user::UserRepository,
+    config::{
+        crypto::{Auth, Claims, CryptoService},
+        lockout::LockoutPolicy,
+    },
+    db::{
+        api_key::ApiKeyRepository, auth_event::AuthEventRepository, mfa::MfaRepository,
+        token::TokenRepository, user::UserRepository,
+    },
     errors::AppError,
-    config::params::{Params},
+    models::{
+        api_key::{ApiKey, API_KEY_PREFIX, API_KEY_SCOPES},
+        auth_event::{
+            AuthEventsCursor, ACCOUNT_INACTIVE, LOGIN_FAILED, LOGIN_SUCCEEDED, MFA_REQUIRED,
+        },
+        mfa::MfaRequired,
+        page::{decode_cursor, PageQuery},
+        role::{Permission, Role},
+        token::{LogoutRequest, RefreshRequest, Tokens},
+        user::User,
+    },
+    providers::BankingProvider,
 };
 
-use actix_web::{web::Data, FromRequest, HttpResponse};
+use actix_web::{
+    http::{Method, StatusCode},
+    web::{self, Data, Json},
+    FromRequest, HttpRequest, HttpResponse,
+};
 use actix_web_httpauth::extractors::{basic::BasicAuth, bearer::BearerAuth};
+use chrono::{DateTime, Duration, Utc};
 use futures::future::{ready, BoxFuture};
-use tracing::{debug, instrument};
+use tracing::{debug, error, instrument};
 use uuid::Uuid;
+use validator::Validate;
 use color_eyre::Result;
 
+const STATE_EXPIRATION_MINUTES: i64 = 10;
+const REFRESH_TOKEN_EXPIRATION_DAYS: i64 = 30;
 
 #[derive(Debug)]
-pub struct AuthenticatedUser(pub Uuid);
+pub struct AuthenticatedUser {
+    pub id: Uuid,
+    pub role: Role,
+    pub permissions: Vec<Permission>,
+    /// Set when the request was authenticated with an API key instead of a
+    /// JWT.
+    pub api_key_id: Option<Uuid>,
+}
+
+impl AuthenticatedUser {
+    pub fn can(&self, permission: Permission) -> bool {
+        self.permissions.contains(&permission)
+    }
+}
+
+/// Permission a route requires, registered as resource data in
+/// `handlers::app_config` and checked by the `AuthenticatedUser` extractor.
+#[derive(Debug, Clone, Copy)]
+pub struct RequiredPermission(pub Permission);
+
+pub fn requires(permission: Permission) -> RequiredPermission {
+    RequiredPermission(permission)
+}
+
+/// What a request was authenticated with.
+enum Credential {
+    Jwt(Claims),
+    ApiKey(ApiKey),
+}
 
 impl FromRequest for AuthenticatedUser {
     type Error = AppError;
@@ -26,28 +79,114 @@ impl FromRequest for AuthenticatedUser {
         req: &actix_web::HttpRequest,
         payload: &mut actix_web::dev::Payload,
     ) -> Self::Future {
+        let required = req
+            .app_data::<RequiredPermission>()
+            .map(|required| required.0);
+        let read_only = req.method() == Method::GET;
         let bearer_result = BearerAuth::from_request(req, payload).into_inner();
         let repository_result = UserRepository::from_request(req, payload).into_inner();
+        let tokens_result = TokenRepository::from_request(req, payload).into_inner();
+        let api_keys_result = ApiKeyRepository::from_request(req, payload).into_inner();
         let crypto_service_result = Data::<CryptoService>::from_request(req, payload).into_inner();
 
-        match (bearer_result, repository_result, crypto_service_result) {
-            (Ok(bearer), Ok(repository), Ok(crypto_service)) => {
+        match (
+            bearer_result,
+            repository_result,
+            tokens_result,
+            api_keys_result,
+            crypto_service_result,
+        ) {
+            (Ok(bearer), Ok(repository), Ok(tokens), Ok(api_keys), Ok(crypto_service)) => {
                 let future = async move {
-                    let user_id: Uuid = crypto_service
-                        .check_jwt(bearer.token().to_string())
-                        .await
-                        .map(|data| data.claims.sub)
-                        .map_err(|err| {
-                            debug!("Cannot check jwt. {:?}", err);
-                            AppError::NOT_AUTHORIZED
-                        })?;
-
-                    repository.find_by_id(user_id).await?.ok_or_else(|| {
+                    let token = bearer.token().to_string();
+                    let credential = if token.starts_with(API_KEY_PREFIX) {
+                        let key = api_keys
+                            .authenticate(&crypto_service.hash_token(&token))
+                            .await?
+                            .ok_or_else(|| {
+                                debug!("Unknown, expired or revoked api key");
+                                AppError::NOT_AUTHORIZED
+                            })?;
+                        Credential::ApiKey(key)
+                    } else {
+                        let claims = crypto_service
+                            .check_jwt(token)
+                            .await
+                            .map(|data| data.claims)
+                            .map_err(|err| {
+                                debug!("Cannot check jwt. {:?}", err);
+                                AppError::NOT_AUTHORIZED
+                            })?;
+
+                        if tokens.is_revoked(claims.jti).await? {
+                            debug!("Token {} has been revoked", claims.jti);
+                            return Err(AppError::NOT_AUTHORIZED.into());
+                        }
+                        Credential::Jwt(claims)
+                    };
+                    let user_id = match &credential {
+                        Credential::Jwt(claims) => claims.sub,
+                        Credential::ApiKey(key) => key.user_id,
+                    };
+
+                    let found = repository.find_by_id(user_id).await?.ok_or_else(|| {
                         debug!("User {} not found", user_id);
                         AppError::NOT_AUTHORIZED
                     })?;
 
-                    Ok(AuthenticatedUser(user_id))
+                    if !found.active {
+                        debug!("User {} is inactive", user_id);
+                        return Err(AppError::ACCOUNT_INACTIVE.into());
+                    }
+
+                    let user = match credential {
+                        Credential::Jwt(claims) => AuthenticatedUser {
+                            id: user_id,
+                            role: claims.role,
+                            permissions: claims.perms,
+                            api_key_id: None,
+                        },
+                        // Keys get the current permissions of the user, limited
+                        // to their scopes
+                        Credential::ApiKey(key) => {
+                            let role = found.role();
+                            let scopes = key.scopes();
+                            AuthenticatedUser {
+                                id: user_id,
+                                role,
+                                permissions: role
+                                    .permissions()
+                                    .into_iter()
+                                    .filter(|permission| {
+                                        scopes.contains(permission)
+                                            && API_KEY_SCOPES.contains(permission)
+                                    })
+                                    .collect(),
+                                api_key_id: Some(key.id),
+                            }
+                        }
+                    };
+
+                    match required {
+                        Some(permission) if !user.can(permission) => {
+                            debug!(
+                                "User {} with role {} is missing permission {:?}",
+                                user_id, user.role, permission
+                            );
+                            Err(AppError::NOT_AUTHORIZED
+                                .default()
+                                .status(StatusCode::FORBIDDEN))
+                        }
+                        // API keys are only good for reading the routes their
+                        // scopes cover
+                        _ if user.api_key_id.is_some() && (required.is_none() || !read_only) => {
+                            debug!("Api key used outside of its scopes");
+                            Err(AppError::NOT_AUTHORIZED
+                                .default()
+                                .status(StatusCode::FORBIDDEN))
+                        }
+                        _ => Ok(user),
+                    }
                 };
                 Box::pin(future)
             }
@@ -59,12 +198,62 @@ impl FromRequest for AuthenticatedUser {
     }
 }
 
-#[instrument(skip(basic, repository, hashing))]
+/// Issues a new refresh token in the given family and stores its hash.
+async fn issue_refresh_token(
+    tokens: &TokenRepository,
+    hashing: &CryptoService,
+    user_id: Uuid,
+    family_id: Uuid,
+) -> Result<String> {
+    let (refresh_token, token_hash) = hashing.generate_token();
+    let expires_at = Utc::now() + Duration::days(REFRESH_TOKEN_EXPIRATION_DAYS);
+    tokens
+        .create_refresh_token(user_id, family_id, token_hash, expires_at.naive_utc())
+        .await?;
+    Ok(refresh_token)
+}
+
+/// Issues the access and refresh tokens of a fully authenticated user, along
+/// with the link to grant access to their bank.
+pub(super) async fn sign_in(
+    user: &User,
+    repository: &UserRepository,
+    tokens: &TokenRepository,
+    hashing: &CryptoService,
+    provider: &dyn BankingProvider,
+) -> AppResponse {
+    let token = hashing.generate_jwt(user.id, user.role()).await?;
+    let refresh_token = issue_refresh_token(tokens, hashing, user.id, Uuid::new_v4()).await?;
+
+    let nonce = Uuid::new_v4();
+    let expires_at = Utc::now() + Duration::minutes(STATE_EXPIRATION_MINUTES);
+    repository
+        .create_state(nonce, user.id, expires_at.naive_utc())
+        .await?;
+    let state = hashing
+        .generate_state(user.id, nonce, expires_at.timestamp())
+        .await?;
+    let url = provider.auth_url(&state);
+
+    Ok(HttpResponse::Ok().json(Auth {
+        token,
+        refresh_token,
+        url,
+    }))
+}
+
+#[instrument(skip(req, basic, repository, tokens, mfa, events, policy, hashing, provider))]
+#[allow(clippy::too_many_arguments)]
 pub async fn auth(
+    req: HttpRequest,
     basic: BasicAuth,
     repository: UserRepository,
+    tokens: TokenRepository,
+    mfa: MfaRepository,
+    events: AuthEventRepository,
+    policy: Data<LockoutPolicy>,
     hashing: Data<CryptoService>,
-    params: Data<Params>,
+    provider: Data<dyn BankingProvider>,
 ) -> AppResponse {
     let username = basic.user_id();
     let password = basic
@@ -74,45 +263,189 @@ pub async fn auth(
             AppError::INVALID_CREDENTIALS
         })?;
 
-    let user = repository
-        .find_by_username(username)
-        .await?
-        .ok_or_else(|| {
+    let attempt = throttle::login_attempt(&req, &policy, username);
+    let user = repository.find_by_username(username).await?;
+    let user_id = user.as_ref().map(|user| user.id);
+
+    throttle::check(&events, &policy, &attempt, user_id).await?;
+
+    let user = match user {
+        Some(user) => user,
+        None => {
             debug!("User doesn't exist.");
-            AppError::INVALID_CREDENTIALS
-        })?;
+            throttle::record_failure(&events, &policy, &attempt, None, LOGIN_FAILED).await?;
+            return Err(AppError::INVALID_CREDENTIALS.into());
+        }
+    };
 
     let valid = hashing
         .check_password(password, &user.password_hash)
         .await?;
 
     if valid {
-        let token = hashing.generate_jwt(user.id).await?;
-        let url = encoded_url(params.clone());
+        if !user.active {
+            debug!("User {} is inactive.", user.id);
+            events
+                .record(Some(user.id), &attempt, ACCOUNT_INACTIVE)
+                .await?;
+            return Err(AppError::ACCOUNT_INACTIVE.into());
+        }
 
+        if hashing.needs_rehash(&user.password_hash) {
+            debug!("Upgrading the password hash of user {}", user.id);
+            if let Err(err) = repository
+                .rehash_password(user.id, password.to_string(), &hashing)
+                .await
+            {
+                error!("Cannot rehash password. {:?}", err);
+            }
+        }
 
-        Ok(HttpResponse::Ok().json(Auth { token, url }))
+        if mfa.enabled(user.id).await? {
+            // Failures are only forgotten once the second factor is verified
+            events.record(Some(user.id), &attempt, MFA_REQUIRED).await?;
+            let mfa_token = hashing.generate_mfa_token(user.id).await?;
+            return Ok(HttpResponse::Ok().json(MfaRequired {
+                mfa_required: true,
+                mfa_token,
+            }));
+        }
 
+        throttle::record_success(&events, &attempt, user.id, LOGIN_SUCCEEDED).await?;
+        sign_in(&user, &repository, &tokens, &hashing, provider.as_ref()).await
     } else {
         debug!("Invalid password.");
+        throttle::record_failure(&events, &policy, &attempt, Some(user.id), LOGIN_FAILED).await?;
         Err(AppError::INVALID_CREDENTIALS.into())
     }
 }
 
-fn encoded_url(params: Data<Params>)  -> String {
-
-    let auth_uri = format!("{}", params.auth_uri);
-    let response_type = "response_type=code".to_string();
-    let client_id = format!("client_id={}", params.client_id);
-    let scope = "scope=info%20accounts%20balance%20cards%20transactions%20direct_debits%20standing_orders%20offline_access".to_string();
-    let redirect_uri = format!("redirect_uri={}", params.redirect_uri);
-    let providers = "providers=uk-ob-all%20uk-oauth-all%20uk-cs-mock".to_string();
-
-    format!("{}/?{}&{}&{}&{}&{}", 
-            auth_uri,
-            response_type,
-            client_id,
-            scope,
-            redirect_uri,
-            providers)
+/// Exchanges a refresh token for a new access token, rotating the refresh
+/// token. Presenting a token that was already rotated revokes its whole family.
+#[instrument(skip(request, repository, tokens, hashing))]
+pub async fn refresh(
+    request: Json<RefreshRequest>,
+    repository: UserRepository,
+    tokens: TokenRepository,
+    hashing: Data<CryptoService>,
+) -> AppResponse {
+    let token_hash = hashing.hash_token(&request.refresh_token);
+    let stored = tokens
+        .find_refresh_token(&token_hash)
+        .await?
+        .ok_or_else(|| {
+            debug!("Refresh token not found.");
+            AppError::NOT_AUTHORIZED
+        })?;
+
+    if stored.revoked_at.is_some() {
+        debug!("Refresh token family {} is revoked.", stored.family_id);
+        return Err(AppError::NOT_AUTHORIZED.into());
+    }
+
+    if stored.expires_at < Utc::now().naive_utc() {
+        debug!("Refresh token expired.");
+        return Err(AppError::NOT_AUTHORIZED.into());
+    }
+
+    if !tokens.use_refresh_token(stored.id).await? {
+        debug!(
+            "Refresh token reused, revoking family {}.",
+            stored.family_id
+        );
+        tokens.revoke_family(stored.family_id).await?;
+        return Err(AppError::NOT_AUTHORIZED.into());
+    }
+
+    let user = repository
+        .find_by_id(stored.user_id)
+        .await?
+        .ok_or_else(|| {
+            debug!("User {} not found", stored.user_id);
+            AppError::NOT_AUTHORIZED
+        })?;
+
+    if !user.active {
+        debug!("User {} is inactive", user.id);
+        return Err(AppError::ACCOUNT_INACTIVE.into());
+    }
+
+    let token = hashing.generate_jwt(user.id, user.role()).await?;
+    let refresh_token =
+        issue_refresh_token(&tokens, &hashing, stored.user_id, stored.family_id).await?;
+
+    Ok(HttpResponse::Ok().json(Tokens {
+        token,
+        refresh_token,
+    }))
+}
+
+/// Revokes the presented access token and, when given, the refresh token
+/// family it was issued with.
+#[instrument(skip(bearer, request, tokens, hashing))]
+pub async fn logout(
+    bearer: BearerAuth,
+    request: Option<Json<LogoutRequest>>,
+    tokens: TokenRepository,
+    hashing: Data<CryptoService>,
+) -> AppResponse {
+    let claims = hashing
+        .check_jwt(bearer.token().to_string())
+        .await
+        .map(|data| data.claims)
+        .map_err(|err| {
+            debug!("Cannot check jwt. {:?}", err);
+            AppError::NOT_AUTHORIZED
+        })?;
+
+    let expires_at = DateTime::from_timestamp(claims.exp, 0)
+        .ok_or(AppError::NOT_AUTHORIZED)?
+        .naive_utc();
+    tokens
+        .revoke_access_token(claims.jti, claims.sub, expires_at)
+        .await?;
+
+    let request = request
+        .map(|request| request.into_inner())
+        .unwrap_or_default();
+    if let Some(refresh_token) = request.refresh_token {
+        let token_hash = hashing.hash_token(&refresh_token);
+        if let Some(stored) = tokens.find_refresh_token(&token_hash).await? {
+            if stored.user_id == claims.sub {
+                tokens.revoke_family(stored.family_id).await?;
+            }
+        }
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Publishes the public keys tokens are signed with.
+pub async fn jwks(hashing: Data<CryptoService>) -> HttpResponse {
+    HttpResponse::Ok()
+        .header("Cache-Control", "public, max-age=300")
+        .json(hashing.jwks())
+}
+
+/// The login history of the user, newest first.
+#[instrument(skip(events))]
+pub async fn auth_events(
+    user: AuthenticatedUser,
+    events: AuthEventRepository,
+    web::Query(page): web::Query<PageQuery>,
+) -> AppResponse {
+    page.validate().map_err(|errors| {
+        debug!("Invalid auth events page. {:?}", errors);
+        AppError::INVALID_INPUT.message("Invalid limit. Must be between 1 and 500.".to_string())
+    })?;
+    let cursor = match &page.cursor {
+        Some(cursor) => Some(
+            decode_cursor::<AuthEventsCursor>(cursor)
+                .ok_or_else(|| AppError::INVALID_INPUT.message("Invalid cursor.".to_string()))?,
+        ),
+        None => None,
+    };
+
+    let events = events.list(user.id, &page, cursor).await?;
+    Ok(HttpResponse::Ok().json(events))
 }
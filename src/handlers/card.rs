@@ -0,0 +1,52 @@
+use super::{
+    auth::AuthenticatedUser,
+    trans::{validate_page, validate_query},
+    AppResponse,
+};
+use crate::{
+    db::{card::CardRepository, trans::TransactionRepository},
+    errors::AppError,
+    models::card::CardsResults,
+    models::trans::{TransactionsPage, TransactionsQuery},
+    providers::BankingProvider,
+};
+use actix_web::{
+    web::{self, Data},
+    HttpResponse,
+};
+use tracing::instrument;
+
+/// Cards of the user, as stored by the last sync.
+#[instrument(skip(user, repository, provider))]
+pub async fn cards(
+    user: AuthenticatedUser,
+    repository: CardRepository,
+    provider: Data<dyn BankingProvider>,
+) -> AppResponse {
+    let cards = repository.list(user.id, provider.name()).await?;
+    Ok(HttpResponse::Ok().json(CardsResults { results: cards }))
+}
+
+/// Transactions of a card, with the filters and pagination of the
+/// transactions listings.
+#[instrument(skip(user, cards, repository, provider))]
+pub async fn card_transactions(
+    user: AuthenticatedUser,
+    id: web::Path<String>,
+    cards: CardRepository,
+    repository: TransactionRepository,
+    provider: Data<dyn BankingProvider>,
+    web::Query(mut query): web::Query<TransactionsQuery>,
+    web::Query(page): web::Query<TransactionsPage>,
+) -> AppResponse {
+    cards
+        .find(user.id, provider.name(), &id)
+        .await?
+        .ok_or(AppError::NOT_FOUND)?;
+
+    query.account_id = Some(id.into_inner());
+    validate_query(&query)?;
+    let cursor = validate_page(&page)?;
+    let transactions = repository.list_card(user.id, &query, &page, cursor).await?;
+    Ok(HttpResponse::Ok().json(transactions))
+}
@@ -0,0 +1,71 @@
+use super::{auth::AuthenticatedUser, provider_error, AppResponse};
+use crate::{
+    config::crypto::CryptoService,
+    db::{consent::ConsentRepository, provider::ProviderRepository},
+    errors::AppError,
+    models::consent::ConsentsResults,
+    providers::{session::ProviderSession, BankingProvider, ProviderError},
+};
+use actix_web::{
+    web::{self, Data},
+    HttpResponse,
+};
+use tracing::{debug, instrument};
+use uuid::Uuid;
+
+/// Consents the user granted, newest first, with lapsed ones marked expired.
+#[instrument(skip(user, consents))]
+pub async fn consents(user: AuthenticatedUser, consents: ConsentRepository) -> AppResponse {
+    let results = consents.list(user.id).await?;
+    Ok(HttpResponse::Ok().json(ConsentsResults { results }))
+}
+
+/// Revokes a consent at the provider and deletes the tokens it gave us. A
+/// consent the provider no longer honors is only revoked on our side.
+#[instrument(skip(user, consents, provider_repository, crypto_service, provider))]
+pub async fn revoke_consent(
+    user: AuthenticatedUser,
+    id: web::Path<Uuid>,
+    consents: ConsentRepository,
+    provider_repository: ProviderRepository,
+    crypto_service: Data<CryptoService>,
+    provider: Data<dyn BankingProvider>,
+) -> AppResponse {
+    let consent = consents
+        .find(user.id, *id)
+        .await?
+        .ok_or(AppError::NOT_FOUND)?;
+
+    // The stored tokens belong to the last consent granted for the provider
+    let latest = consents.latest(user.id, &consent.provider).await?;
+    let holds_tokens = latest.map(|latest| latest.id) == Some(consent.id);
+    if holds_tokens && consent.provider == provider.name() {
+        let session = ProviderSession::open(
+            &**provider,
+            &provider_repository,
+            &consents,
+            &crypto_service,
+            user.id,
+        )
+        .await;
+        let revoked = match session {
+            Ok(mut session) => session.revoke().await,
+            Err(err) => Err(err),
+        };
+        match revoked {
+            Ok(()) => {}
+            Err(err @ ProviderError::Unauthorized)
+            | Err(err @ ProviderError::RelinkRequired)
+            | Err(err @ ProviderError::ConsentExpired) => {
+                debug!("Provider consent {} already unusable. {}", consent.id, err);
+            }
+            Err(err) => return Err(provider_error(err)),
+        }
+        provider_repository
+            .delete_credentials(user.id, provider.name())
+            .await?;
+    }
+
+    consents.revoke(user.id, consent.id).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,133 @@
+use super::{auth::AuthenticatedUser, AppResponse, AppResult};
+use crate::{
+    config::crypto::CryptoService,
+    db::export::ExportRepository,
+    errors::AppError,
+    export::Exporter,
+    models::export::{
+        DataExport, DownloadExport, ExportStatus, EXPORT_PENDING, EXPORT_READY, EXPORT_RUNNING,
+    },
+};
+use actix_web::{
+    web::{self, Data},
+    HttpResponse,
+};
+use chrono::Utc;
+use tracing::{debug, instrument};
+use uuid::Uuid;
+
+fn is_downloadable(export: &DataExport) -> bool {
+    export.status == EXPORT_READY
+        && matches!(export.expires_at, Some(expires_at) if expires_at > Utc::now())
+}
+
+/// The status of an export, with a signed download link once it is ready.
+async fn status_of(
+    export: DataExport,
+    exporter: &Exporter,
+    hashing: &CryptoService,
+) -> AppResult<ExportStatus> {
+    let download_url = match export.expires_at {
+        Some(expires_at) if is_downloadable(&export) => {
+            let token = hashing
+                .generate_export_token(export.id, expires_at.timestamp())
+                .await?;
+            Some(exporter.download_url(export.id, &token))
+        }
+        _ => None,
+    };
+
+    Ok(ExportStatus {
+        id: export.id,
+        status: export.status,
+        error: export.error,
+        created_at: export.created_at,
+        started_at: export.started_at,
+        completed_at: export.completed_at,
+        expires_at: export.expires_at,
+        download_url,
+    })
+}
+
+fn status_response(status: ExportStatus) -> AppResponse {
+    if status.download_url.is_some() {
+        Ok(HttpResponse::Ok().json(status))
+    } else {
+        Ok(HttpResponse::Accepted().json(status))
+    }
+}
+
+/// Starts an export of everything stored about the user, or returns the one
+/// in progress or still downloadable. Answers `202` until it is ready.
+#[instrument(skip(user, exports, exporter, hashing))]
+pub async fn export(
+    user: AuthenticatedUser,
+    exports: ExportRepository,
+    exporter: Data<Exporter>,
+    hashing: Data<CryptoService>,
+) -> AppResponse {
+    let current = exports.latest(user.id).await?.filter(|export| {
+        export.status == EXPORT_PENDING
+            || export.status == EXPORT_RUNNING
+            || is_downloadable(export)
+    });
+
+    let export = match current {
+        Some(export) => export,
+        None => {
+            debug!("Starting a data export for user {}", user.id);
+            exports.create(user.id).await?
+        }
+    };
+
+    status_response(status_of(export, &exporter, &hashing).await?)
+}
+
+#[instrument(skip(user, exports, exporter, hashing))]
+pub async fn export_status(
+    user: AuthenticatedUser,
+    exports: ExportRepository,
+    exporter: Data<Exporter>,
+    hashing: Data<CryptoService>,
+    id: web::Path<Uuid>,
+) -> AppResponse {
+    let export = exports
+        .find(user.id, *id)
+        .await?
+        .ok_or(AppError::NOT_FOUND)?;
+
+    status_response(status_of(export, &exporter, &hashing).await?)
+}
+
+/// Downloads an export archive with the signed link from its status. The link
+/// is enough, so it can be opened in a browser.
+#[instrument(skip(exports, hashing, query))]
+pub async fn download_export(
+    exports: ExportRepository,
+    hashing: Data<CryptoService>,
+    id: web::Path<Uuid>,
+    web::Query(query): web::Query<DownloadExport>,
+) -> AppResponse {
+    let claims = hashing
+        .check_export_token(query.token)
+        .await
+        .map(|data| data.claims)
+        .map_err(|err| {
+            debug!("Cannot check export token. {:?}", err);
+            AppError::NOT_AUTHORIZED
+        })?;
+    if claims.eid != *id {
+        debug!("Export token for {} used for {}", claims.eid, *id);
+        return Err(AppError::NOT_AUTHORIZED.into());
+    }
+
+    let archive = exports.archive(*id).await?.ok_or(AppError::NOT_FOUND)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/zip")
+        .header(
+            "content-disposition",
+            format!("attachment; filename=\"bank-api-export-{}.zip\"", *id),
+        )
+        .body(archive))
+}
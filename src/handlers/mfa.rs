@@ -0,0 +1,153 @@
+use super::{
+    auth::{sign_in, AuthenticatedUser},
+    throttle, AppResponse, AppResult,
+};
+use crate::{
+    config::{crypto::CryptoService, lockout::LockoutPolicy, totp},
+    db::{
+        auth_event::AuthEventRepository, mfa::MfaRepository, token::TokenRepository,
+        user::UserRepository,
+    },
+    errors::AppError,
+    models::auth_event::{LOGIN_SUCCEEDED, MFA_FAILED},
+    models::mfa::{MfaCode, MfaEnrollment, MfaLogin, RecoveryCodes, UserMfa},
+    providers::BankingProvider,
+};
+use actix_web::{
+    web::{Data, Json},
+    HttpRequest, HttpResponse,
+};
+use chrono::Utc;
+use tracing::{debug, instrument};
+use uuid::Uuid;
+
+const ISSUER: &str = "Bank API";
+
+/// Checks a TOTP code, falling back to the recovery codes. Each code is only
+/// accepted once.
+async fn check_code(
+    mfa: &MfaRepository,
+    hashing: &CryptoService,
+    settings: &UserMfa,
+    code: &str,
+) -> AppResult<bool> {
+    if let Some(step) = totp::verify(&settings.secret, code, Utc::now().timestamp()) {
+        return Ok(mfa.use_step(settings.user_id, step).await?);
+    }
+
+    if settings.enabled_at.is_some() {
+        let code_hash = hashing.hash_recovery_code(code);
+        return Ok(mfa.use_recovery_code(settings.user_id, code_hash).await?);
+    }
+    Ok(false)
+}
+
+/// Starts the enrollment with a new secret, 2FA is only enabled once a code
+/// generated from it is confirmed.
+#[instrument(skip(user, repository, mfa, hashing))]
+pub async fn enroll_mfa(
+    user: AuthenticatedUser,
+    repository: UserRepository,
+    mfa: MfaRepository,
+    hashing: Data<CryptoService>,
+) -> AppResponse {
+    let profile = repository
+        .find_by_id(user.id)
+        .await?
+        .ok_or(AppError::NOT_AUTHORIZED)?;
+
+    let secret = totp::generate_secret();
+    mfa.save_pending(user.id, &secret, &hashing)
+        .await?
+        .ok_or_else(|| {
+            AppError::INVALID_INPUT
+                .message("Two-factor authentication is already enabled.".to_string())
+        })?;
+
+    let account = profile.username.unwrap_or(profile.email);
+    let provisioning_uri = totp::provisioning_uri(&secret, ISSUER, &account);
+
+    Ok(HttpResponse::Ok().json(MfaEnrollment {
+        secret,
+        provisioning_uri,
+    }))
+}
+
+/// Enables 2FA once the user proves their authenticator works, and returns
+/// the recovery codes. They are only shown this once.
+#[instrument(skip(user, request, mfa, hashing))]
+pub async fn confirm_mfa(
+    user: AuthenticatedUser,
+    request: Json<MfaCode>,
+    mfa: MfaRepository,
+    hashing: Data<CryptoService>,
+) -> AppResponse {
+    let settings = mfa
+        .find(user.id, &hashing)
+        .await?
+        .filter(|settings| settings.enabled_at.is_none())
+        .ok_or_else(|| {
+            AppError::INVALID_INPUT
+                .message("No two-factor authentication enrollment pending.".to_string())
+        })?;
+
+    if !check_code(&mfa, &hashing, &settings, &request.code).await? {
+        debug!("Invalid code confirming 2FA for user {}", user.id);
+        return Err(AppError::INVALID_MFA_CODE.into());
+    }
+
+    let (recovery_codes, code_hashes) = hashing.generate_recovery_codes().into_iter().unzip();
+    mfa.enable(user.id, code_hashes).await?;
+
+    Ok(HttpResponse::Ok().json(RecoveryCodes { recovery_codes }))
+}
+
+/// Completes a login started at `/auth` by a user with 2FA enabled.
+#[instrument(skip(
+    req, request, repository, tokens, mfa, events, policy, hashing, provider
+))]
+#[allow(clippy::too_many_arguments)]
+pub async fn verify_mfa(
+    req: HttpRequest,
+    request: Json<MfaLogin>,
+    repository: UserRepository,
+    tokens: TokenRepository,
+    mfa: MfaRepository,
+    events: AuthEventRepository,
+    policy: Data<LockoutPolicy>,
+    hashing: Data<CryptoService>,
+    provider: Data<dyn BankingProvider>,
+) -> AppResponse {
+    let user_id: Uuid = hashing
+        .check_mfa_token(request.mfa_token.clone())
+        .await
+        .map(|data| data.claims.uid)
+        .map_err(|err| {
+            debug!("Cannot check mfa token. {:?}", err);
+            AppError::NOT_AUTHORIZED
+        })?;
+
+    let user = repository.find_by_id(user_id).await?.ok_or_else(|| {
+        debug!("User {} not found", user_id);
+        AppError::NOT_AUTHORIZED
+    })?;
+
+    let settings = mfa
+        .find(user_id, &hashing)
+        .await?
+        .filter(|settings| settings.enabled_at.is_some())
+        .ok_or(AppError::NOT_AUTHORIZED)?;
+
+    let username = user.username.clone().unwrap_or_else(|| user.email.clone());
+    let attempt = throttle::login_attempt(&req, &policy, &username);
+    throttle::check(&events, &policy, &attempt, Some(user_id)).await?;
+
+    if !check_code(&mfa, &hashing, &settings, &request.code).await? {
+        debug!("Invalid 2FA code for user {}", user_id);
+        throttle::record_failure(&events, &policy, &attempt, Some(user_id), MFA_FAILED).await?;
+        return Err(AppError::INVALID_MFA_CODE.into());
+    }
+
+    throttle::record_success(&events, &attempt, user_id, LOGIN_SUCCEEDED).await?;
+    sign_in(&user, &repository, &tokens, &hashing, provider.as_ref()).await
+}
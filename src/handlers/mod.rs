@@ -1,12 +1,38 @@
+mod account;
+mod api_key;
 mod auth;
+mod card;
+mod consent;
+mod export;
+mod mfa;
+mod password;
+mod payment;
+mod sync;
+mod throttle;
+mod trans;
 mod user;
 
-use crate::errors::AppError;
-use actix_web::{web, HttpResponse};
-use auth::auth;
-use user::{create_user, me, callback_code, 
-    transactions, weekly_transactions, total_week_transactions, daily_transactions, 
-    monthly_transactions, total_month_transactions, credit, debit};
+use crate::{errors::AppError, models::role::Permission, providers::ProviderError};
+use account::{accounts, balance, balance_history};
+use actix_web::{guard, web, HttpResponse};
+use api_key::{api_keys, create_api_key, revoke_api_key};
+use auth::{auth, auth_events, jwks, logout, refresh, requires};
+use card::{card_transactions, cards};
+use consent::{consents, revoke_consent};
+use export::{download_export, export, export_status};
+use mfa::{confirm_mfa, enroll_mfa, verify_mfa};
+use password::{change_password, forgot_password, reset_password};
+use payment::{direct_debits, standing_orders};
+use sync::{sync, sync_status};
+use tracing::error;
+use trans::{
+    credit, daily_transactions, debit, monthly_transactions, total_month_transactions,
+    total_week_transactions, transactions, weekly_transactions,
+};
+use user::{
+    callback_code, create_user, delete_me, get_user, me, resend_verification, update_me,
+    update_role, verify_email,
+};
 
 type AppResult<T> = Result<T, AppError>;
 type AppResponse = AppResult<HttpResponse>;
@@ -15,27 +41,155 @@ pub fn app_config(config: &mut web::ServiceConfig) {
     let signup = web::resource("/signup").route(web::post().to(create_user));
 
     let auth = web::resource("/auth").route(web::post().to(auth));
+    let refresh = web::resource("/auth/refresh").route(web::post().to(refresh));
+    let logout = web::resource("/auth/logout").route(web::post().to(logout));
+    let mfa_login = web::resource("/auth/mfa").route(web::post().to(verify_mfa));
+    let jwks = web::resource("/.well-known/jwks.json").route(web::get().to(jwks));
 
+    // Same path, the guards pick the resource with the permission of the method
     let me = web::resource("/me")
+        .guard(guard::Get())
+        .app_data(requires(Permission::ReadProfile))
         .route(web::get().to(me));
+    let update_me = web::resource("/me")
+        .guard(guard::Any(guard::Patch()).or(guard::Delete()))
+        .app_data(requires(Permission::ManageProfile))
+        .route(web::patch().to(update_me))
+        .route(web::delete().to(delete_me));
+
+    let forgot_password = web::resource("/password/forgot").route(web::post().to(forgot_password));
+    let reset_password = web::resource("/password/reset").route(web::post().to(reset_password));
+    let change_password = web::resource("/password/change")
+        .app_data(requires(Permission::ManageProfile))
+        .route(web::post().to(change_password));
+    let verify_email = web::resource("/email/verify").route(web::get().to(verify_email));
+    let resend_verification = web::resource("/email/verify/resend")
+        .app_data(requires(Permission::ManageProfile))
+        .route(web::post().to(resend_verification));
+
+    let auth_events = web::resource("/me/auth-events")
+        .app_data(requires(Permission::ReadProfile))
+        .route(web::get().to(auth_events));
+    let export = web::resource("/me/export")
+        .app_data(requires(Permission::ReadProfile))
+        .route(web::get().to(export));
+    let export_status = web::resource("/me/export/{id}")
+        .app_data(requires(Permission::ReadProfile))
+        .route(web::get().to(export_status));
+    let download_export =
+        web::resource("/exports/{id}/download").route(web::get().to(download_export));
+    let mfa_enroll = web::resource("/me/mfa")
+        .app_data(requires(Permission::ManageProfile))
+        .route(web::post().to(enroll_mfa));
+    let mfa_confirm = web::resource("/me/mfa/confirm")
+        .app_data(requires(Permission::ManageProfile))
+        .route(web::post().to(confirm_mfa));
 
     let health_resource = web::resource("/").route(web::get().to(health));
 
     let callback_code = web::resource("/callback").route(web::get().to(callback_code));
 
-    let transactions = web::resource("/v1/transactions").route(web::get().to(transactions));
-    let daily_transactions = web::resource("/v1/transactions/daily").route(web::get().to(daily_transactions));
-    let weekly_transactions = web::resource("/v1/transactions/weekly").route(web::get().to(weekly_transactions));
-    let total_week_transactions = web::resource("/v1/transactions/weekly/total").route(web::get().to(total_week_transactions));
-    let monthly_transactions = web::resource("/v1/transactions/monthly").route(web::get().to(monthly_transactions));
-    let total_month_transactions = web::resource("/v1/transactions/monthly/total").route(web::get().to(total_month_transactions));
-    let credit = web::resource("/v1/transactions/credit").route(web::get().to(credit));
-    let debit = web::resource("/v1/transactions/debit").route(web::get().to(debit));
-    
+    let transactions = web::resource("/v1/transactions")
+        .app_data(requires(Permission::ReadTransactions))
+        .route(web::get().to(transactions));
+    let daily_transactions = web::resource("/v1/transactions/daily")
+        .app_data(requires(Permission::ReadTransactions))
+        .route(web::get().to(daily_transactions));
+    let weekly_transactions = web::resource("/v1/transactions/weekly")
+        .app_data(requires(Permission::ReadTransactions))
+        .route(web::get().to(weekly_transactions));
+    let total_week_transactions = web::resource("/v1/transactions/weekly/total")
+        .app_data(requires(Permission::ReadTransactions))
+        .route(web::get().to(total_week_transactions));
+    let monthly_transactions = web::resource("/v1/transactions/monthly")
+        .app_data(requires(Permission::ReadTransactions))
+        .route(web::get().to(monthly_transactions));
+    let total_month_transactions = web::resource("/v1/transactions/monthly/total")
+        .app_data(requires(Permission::ReadTransactions))
+        .route(web::get().to(total_month_transactions));
+    let credit = web::resource("/v1/transactions/credit")
+        .app_data(requires(Permission::ReadTransactions))
+        .route(web::get().to(credit));
+    let debit = web::resource("/v1/transactions/debit")
+        .app_data(requires(Permission::ReadTransactions))
+        .route(web::get().to(debit));
+
+    let accounts = web::resource("/v1/accounts")
+        .app_data(requires(Permission::ReadTransactions))
+        .route(web::get().to(accounts));
+    let balance = web::resource("/v1/accounts/{id}/balance")
+        .app_data(requires(Permission::ReadTransactions))
+        .route(web::get().to(balance));
+    let balance_history = web::resource("/v1/accounts/{id}/balance/history")
+        .app_data(requires(Permission::ReadTransactions))
+        .route(web::get().to(balance_history));
+
+    let cards = web::resource("/v1/cards")
+        .app_data(requires(Permission::ReadTransactions))
+        .route(web::get().to(cards));
+    let card_transactions = web::resource("/v1/cards/{id}/transactions")
+        .app_data(requires(Permission::ReadTransactions))
+        .route(web::get().to(card_transactions));
+    let direct_debits = web::resource("/v1/direct-debits")
+        .app_data(requires(Permission::ReadTransactions))
+        .route(web::get().to(direct_debits));
+    let standing_orders = web::resource("/v1/standing-orders")
+        .app_data(requires(Permission::ReadTransactions))
+        .route(web::get().to(standing_orders));
+
+    let sync = web::resource("/v1/sync")
+        .app_data(requires(Permission::SyncTransactions))
+        .route(web::post().to(sync));
+    let sync_status = web::resource("/v1/sync/status")
+        .app_data(requires(Permission::ReadTransactions))
+        .route(web::get().to(sync_status));
+
+    let user = web::resource("/v1/users/{id}")
+        .app_data(requires(Permission::ReadUsers))
+        .route(web::get().to(get_user));
+    let user_role = web::resource("/v1/users/{id}/role")
+        .app_data(requires(Permission::ManageUsers))
+        .route(web::put().to(update_role));
+
+    let api_keys = web::resource("/v1/api-keys")
+        .guard(guard::Get())
+        .app_data(requires(Permission::ReadProfile))
+        .route(web::get().to(api_keys));
+    let create_api_key = web::resource("/v1/api-keys")
+        .guard(guard::Post())
+        .app_data(requires(Permission::ManageProfile))
+        .route(web::post().to(create_api_key));
+    let api_key = web::resource("/v1/api-keys/{id}")
+        .app_data(requires(Permission::ManageProfile))
+        .route(web::delete().to(revoke_api_key));
+
+    let consents = web::resource("/v1/consents")
+        .app_data(requires(Permission::ReadTransactions))
+        .route(web::get().to(consents));
+    let consent = web::resource("/v1/consents/{id}")
+        .app_data(requires(Permission::ManageProfile))
+        .route(web::delete().to(revoke_consent));
+
     config
         .service(signup)
         .service(auth)
+        .service(refresh)
+        .service(logout)
+        .service(mfa_login)
+        .service(jwks)
         .service(me)
+        .service(update_me)
+        .service(forgot_password)
+        .service(reset_password)
+        .service(change_password)
+        .service(verify_email)
+        .service(resend_verification)
+        .service(auth_events)
+        .service(export)
+        .service(export_status)
+        .service(download_export)
+        .service(mfa_enroll)
+        .service(mfa_confirm)
         .service(health_resource)
         .service(callback_code)
         .service(transactions)
@@ -45,11 +199,46 @@ pub fn app_config(config: &mut web::ServiceConfig) {
         .service(credit)
         .service(debit)
         .service(total_week_transactions)
-        .service(total_month_transactions);
+        .service(total_month_transactions)
+        .service(accounts)
+        .service(balance)
+        .service(balance_history)
+        .service(cards)
+        .service(card_transactions)
+        .service(direct_debits)
+        .service(standing_orders)
+        .service(sync)
+        .service(sync_status)
+        .service(user)
+        .service(user_role)
+        .service(api_keys)
+        .service(create_api_key)
+        .service(api_key)
+        .service(consents)
+        .service(consent);
 }
 
 pub async fn health() -> HttpResponse {
     HttpResponse::Ok().finish()
 }
 
-
+/// Maps a provider failure to the API error. The provider response is only
+/// logged, clients get a stable error code.
+fn provider_error(err: ProviderError) -> AppError {
+    match err {
+        ProviderError::Unauthorized | ProviderError::RelinkRequired => {
+            AppError::RELINK_REQUIRED.into()
+        }
+        ProviderError::ConsentExpired => AppError::CONSENT_EXPIRED.into(),
+        ProviderError::Other(err) => err.into(),
+        err => {
+            error!("Provider request failed. {}", err);
+            match err {
+                ProviderError::Timeout => AppError::UPSTREAM_TIMEOUT.into(),
+                ProviderError::InvalidPayload(_) => AppError::UPSTREAM_BAD_PAYLOAD.into(),
+                err if err.is_transient() => AppError::UPSTREAM_UNAVAILABLE.into(),
+                _ => AppError::UPSTREAM_REJECTED.into(),
+            }
+        }
+    }
+}
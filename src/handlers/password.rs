@@ -0,0 +1,159 @@
+use super::{auth::AuthenticatedUser, AppResponse, AppResult};
+use crate::{
+    config::{crypto::CryptoService, password::PasswordPolicy},
+    db::{token::TokenRepository, user::UserRepository, user_token::UserTokenRepository},
+    errors::AppError,
+    mailer::MailService,
+    models::token::PASSWORD_RESET,
+    models::user::{ChangePassword, ForgotPassword, ResetPassword},
+};
+use actix_web::{
+    web::{Data, Json},
+    HttpResponse,
+};
+use chrono::{Duration, Utc};
+use tracing::{debug, error, instrument};
+use validator::Validate;
+
+const RESET_TOKEN_EXPIRATION_MINUTES: i64 = 60;
+
+/// Rejects a new password that doesn't follow the policy, listing every rule
+/// it breaks.
+pub(super) fn check_password_policy(
+    policy: &PasswordPolicy,
+    password: &str,
+    username: &str,
+    email: &str,
+) -> AppResult<()> {
+    policy.check(password, username, email).map_err(|problems| {
+        debug!("Password rejected by the policy. {:?}", problems);
+        AppError::INVALID_INPUT.message(format!(
+            "Invalid password. It must {}.",
+            problems.join(", ")
+        ))
+    })
+}
+
+/// Sends a password reset token by email. The response is the same whether
+/// the address belongs to a user or not.
+#[instrument(skip(request, repository, user_tokens, hashing, mail))]
+pub async fn forgot_password(
+    request: Json<ForgotPassword>,
+    repository: UserRepository,
+    user_tokens: UserTokenRepository,
+    hashing: Data<CryptoService>,
+    mail: Data<MailService>,
+) -> AppResponse {
+    request.validate().map_err(|_| {
+        AppError::INVALID_INPUT.message(format!("Invalid email address \"{}\"", request.email))
+    })?;
+
+    match repository.find_by_email(&request.email).await? {
+        Some(user) if user.active => {
+            let (token, token_hash) = hashing.generate_token();
+            let expires_at = Utc::now() + Duration::minutes(RESET_TOKEN_EXPIRATION_MINUTES);
+            user_tokens
+                .create(user.id, PASSWORD_RESET, token_hash, &user.email, expires_at)
+                .await?;
+            if let Err(err) = mail.send_password_reset(&user.email, &token).await {
+                error!("Cannot send password reset email. {:?}", err);
+            }
+        }
+        _ => debug!("No active user with this email."),
+    }
+
+    Ok(HttpResponse::Accepted().finish())
+}
+
+/// Sets a new password with a token sent by `forgot_password`, and signs the
+/// user out of every session. The token is only used up once the new password
+/// is accepted.
+#[instrument(skip(request, repository, user_tokens, tokens, hashing, policy, mail))]
+#[allow(clippy::too_many_arguments)]
+pub async fn reset_password(
+    request: Json<ResetPassword>,
+    repository: UserRepository,
+    user_tokens: UserTokenRepository,
+    tokens: TokenRepository,
+    hashing: Data<CryptoService>,
+    policy: Data<PasswordPolicy>,
+    mail: Data<MailService>,
+) -> AppResponse {
+    let invalid_token = || AppError::INVALID_INPUT.message("Invalid or expired token.".to_string());
+
+    let token_hash = hashing.hash_token(&request.token);
+    let token = user_tokens
+        .find(PASSWORD_RESET, &token_hash)
+        .await?
+        .ok_or_else(invalid_token)?;
+
+    let user = repository
+        .find_by_id(token.user_id)
+        .await?
+        .filter(|user| user.active)
+        .ok_or_else(invalid_token)?;
+
+    let username = user.username.as_deref().unwrap_or_default();
+    check_password_policy(&policy, &request.password, username, &user.email)?;
+
+    user_tokens
+        .consume(PASSWORD_RESET, &token_hash)
+        .await?
+        .ok_or_else(invalid_token)?;
+
+    let request = request.into_inner();
+    repository
+        .update_password(user.id, request.password, &hashing)
+        .await?;
+    tokens.revoke_user_refresh_tokens(user.id).await?;
+
+    if let Err(err) = mail.send_password_changed(&user.email).await {
+        error!("Cannot send password changed email. {:?}", err);
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Changes the password of the authenticated user. Other sessions have to
+/// log in again.
+#[instrument(skip(user, request, repository, user_tokens, tokens, hashing, policy, mail))]
+#[allow(clippy::too_many_arguments)]
+pub async fn change_password(
+    user: AuthenticatedUser,
+    request: Json<ChangePassword>,
+    repository: UserRepository,
+    user_tokens: UserTokenRepository,
+    tokens: TokenRepository,
+    hashing: Data<CryptoService>,
+    policy: Data<PasswordPolicy>,
+    mail: Data<MailService>,
+) -> AppResponse {
+    let profile = repository
+        .find_by_id(user.id)
+        .await?
+        .ok_or(AppError::NOT_AUTHORIZED)?;
+
+    let valid = hashing
+        .check_password(&request.current_password, &profile.password_hash)
+        .await?;
+    if !valid {
+        debug!("Invalid current password.");
+        return Err(AppError::INVALID_CREDENTIALS.into());
+    }
+
+    let username = profile.username.as_deref().unwrap_or_default();
+    check_password_policy(&policy, &request.new_password, username, &profile.email)?;
+
+    let request = request.into_inner();
+    repository
+        .update_password(user.id, request.new_password, &hashing)
+        .await?;
+    user_tokens.invalidate(user.id, PASSWORD_RESET).await?;
+    tokens.revoke_user_refresh_tokens(user.id).await?;
+
+    if let Err(err) = mail.send_password_changed(&profile.email).await {
+        error!("Cannot send password changed email. {:?}", err);
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,71 @@
+use super::{auth::AuthenticatedUser, AppResponse, AppResult};
+use crate::{
+    db::payment::PaymentRepository,
+    errors::AppError,
+    models::page::{decode_cursor, PageQuery},
+    models::payment::{PaymentsCursor, PaymentsQuery},
+    providers::BankingProvider,
+};
+use actix_web::{
+    web::{self, Data},
+    HttpResponse,
+};
+use tracing::{debug, instrument};
+use validator::Validate;
+
+fn validate_query(query: &PaymentsQuery, page: &PageQuery) -> AppResult<Option<PaymentsCursor>> {
+    query.validate().map_err(|errors| {
+        debug!("Invalid payments query. {:?}", errors);
+        let mut fields: Vec<&str> = errors.field_errors().keys().copied().collect();
+        fields.sort_unstable();
+        let message = if fields.is_empty() {
+            "Invalid query. \"from\" must be before \"to\".".to_string()
+        } else {
+            format!("Invalid query parameters: {}.", fields.join(", "))
+        };
+        AppError::INVALID_INPUT.message(message)
+    })?;
+    page.validate().map_err(|errors| {
+        debug!("Invalid payments page. {:?}", errors);
+        AppError::INVALID_INPUT.message("Invalid limit. Must be between 1 and 500.".to_string())
+    })?;
+
+    match &page.cursor {
+        Some(cursor) => decode_cursor::<PaymentsCursor>(cursor)
+            .map(Some)
+            .ok_or_else(|| AppError::INVALID_INPUT.message("Invalid cursor.".to_string())),
+        None => Ok(None),
+    }
+}
+
+/// Direct debit mandates of the user, as stored by the last sync.
+#[instrument(skip(user, repository, provider))]
+pub async fn direct_debits(
+    user: AuthenticatedUser,
+    repository: PaymentRepository,
+    provider: Data<dyn BankingProvider>,
+    web::Query(query): web::Query<PaymentsQuery>,
+    web::Query(page): web::Query<PageQuery>,
+) -> AppResponse {
+    let cursor = validate_query(&query, &page)?;
+    let direct_debits = repository
+        .direct_debits(user.id, provider.name(), &query, page.limit, cursor)
+        .await?;
+    Ok(HttpResponse::Ok().json(direct_debits))
+}
+
+/// Standing orders of the user, as stored by the last sync.
+#[instrument(skip(user, repository, provider))]
+pub async fn standing_orders(
+    user: AuthenticatedUser,
+    repository: PaymentRepository,
+    provider: Data<dyn BankingProvider>,
+    web::Query(query): web::Query<PaymentsQuery>,
+    web::Query(page): web::Query<PageQuery>,
+) -> AppResponse {
+    let cursor = validate_query(&query, &page)?;
+    let standing_orders = repository
+        .standing_orders(user.id, provider.name(), &query, page.limit, cursor)
+        .await?;
+    Ok(HttpResponse::Ok().json(standing_orders))
+}
@@ -0,0 +1,18 @@
+use super::{auth::AuthenticatedUser, provider_error, AppResponse};
+use crate::{db::sync::SyncRepository, sync::Syncer};
+use actix_web::{web::Data, HttpResponse};
+use tracing::instrument;
+
+#[instrument(skip(syncer))]
+pub async fn sync(user: AuthenticatedUser, syncer: Data<Syncer>) -> AppResponse {
+    match syncer.sync_user(user.id).await {
+        Ok(status) => Ok(HttpResponse::Ok().json(status)),
+        Err(err) => Err(provider_error(err)),
+    }
+}
+
+#[instrument(skip(repository))]
+pub async fn sync_status(user: AuthenticatedUser, repository: SyncRepository) -> AppResponse {
+    let status = repository.status(user.id).await?.unwrap_or_default();
+    Ok(HttpResponse::Ok().json(status))
+}
@@ -0,0 +1,111 @@
+use super::AppResult;
+use crate::{
+    config::lockout::LockoutPolicy,
+    db::auth_event::AuthEventRepository,
+    errors::AppError,
+    models::auth_event::{LoginAttempt, LOGIN_BLOCKED},
+};
+use actix_web::HttpRequest;
+use chrono::Utc;
+use tracing::debug;
+use uuid::Uuid;
+
+/// Describes a login attempt. The client IP is the peer of the connection,
+/// or taken from `X-Forwarded-For` when the peer is a trusted proxy.
+pub fn login_attempt(req: &HttpRequest, policy: &LockoutPolicy, username: &str) -> LoginAttempt {
+    let forwarded_for = req
+        .headers()
+        .get_all("x-forwarded-for")
+        .filter_map(|value| value.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(",");
+    let ip = policy
+        .client_ip(req.peer_addr().map(|addr| addr.ip()), Some(&forwarded_for))
+        .map(|ip| ip.to_string());
+    let user_agent = req
+        .headers()
+        .get("user-agent")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    LoginAttempt {
+        username: username.to_string(),
+        ip,
+        user_agent,
+    }
+}
+
+/// Rejects the attempt while the username or the client IP is locked.
+pub async fn check(
+    events: &AuthEventRepository,
+    policy: &LockoutPolicy,
+    attempt: &LoginAttempt,
+    user_id: Option<Uuid>,
+) -> AppResult<()> {
+    let now = Utc::now();
+    let user_key = attempt.user_key();
+    let keys = std::iter::once((user_key.clone(), policy.max_failures))
+        .chain(attempt.ip_key().map(|key| (key, policy.max_ip_failures)));
+
+    for (key, max_failures) in keys {
+        let throttle = match events.throttle(&key).await? {
+            Some(throttle) => throttle,
+            None => continue,
+        };
+        let locked_until = match throttle.locked_until {
+            Some(locked_until) if locked_until > now => locked_until,
+            _ => continue,
+        };
+
+        debug!("Login blocked for {} until {}", key, locked_until);
+        events.record(user_id, attempt, LOGIN_BLOCKED).await?;
+
+        if key == user_key && throttle.failures >= max_failures {
+            return Err(AppError::ACCOUNT_LOCKED.into());
+        }
+        let seconds = (locked_until - now).num_seconds().max(1);
+        return Err(AppError::TOO_MANY_ATTEMPTS.message(format!(
+            "Too many failed attempts. Try again in {} seconds.",
+            seconds
+        )));
+    }
+    Ok(())
+}
+
+/// Records a failed attempt and delays the next ones from the same username
+/// and client IP.
+pub async fn record_failure(
+    events: &AuthEventRepository,
+    policy: &LockoutPolicy,
+    attempt: &LoginAttempt,
+    user_id: Option<Uuid>,
+    event: &str,
+) -> AppResult<()> {
+    let now = Utc::now();
+    let keys = std::iter::once((attempt.user_key(), policy.max_failures))
+        .chain(attempt.ip_key().map(|key| (key, policy.max_ip_failures)));
+
+    for (key, max_failures) in keys {
+        let throttle = events
+            .record_failure(&key, policy.reset_before(now))
+            .await?;
+        let locked_until = policy.locked_until(throttle.failures, max_failures, now);
+        events.lock(&key, locked_until).await?;
+    }
+
+    events.record(user_id, attempt, event).await?;
+    Ok(())
+}
+
+/// Records a successful login and forgets the failures of the username. The
+/// client IP keeps its count, a valid account must not be usable to reset it.
+pub async fn record_success(
+    events: &AuthEventRepository,
+    attempt: &LoginAttempt,
+    user_id: Uuid,
+    event: &str,
+) -> AppResult<()> {
+    events.clear(&attempt.user_key()).await?;
+    events.record(Some(user_id), attempt, event).await?;
+    Ok(())
+}
@@ -0,0 +1,200 @@
+use super::{auth::AuthenticatedUser, provider_error, AppResponse, AppResult};
+use crate::{
+    db::trans::TransactionRepository,
+    errors::AppError,
+    models::page::decode_cursor,
+    models::trans::{CategoryTotal, TransactionsCursor, TransactionsPage, TransactionsQuery},
+    sync::Syncer,
+};
+use actix_web::{
+    web::{self, Data},
+    HttpResponse,
+};
+use chrono::{DateTime, Duration, Months, Utc};
+use tracing::{debug, instrument};
+use validator::Validate;
+
+pub(super) fn validate_query(query: &TransactionsQuery) -> AppResult<()> {
+    query.validate().map_err(|errors| {
+        debug!("Invalid transactions query. {:?}", errors);
+        let mut fields: Vec<&str> = errors.field_errors().keys().copied().collect();
+        fields.sort_unstable();
+        let message = if fields.is_empty() {
+            "Invalid query. \"from\" must be before \"to\" and \"min_amount\" below \"max_amount\"."
+                .to_string()
+        } else {
+            format!("Invalid query parameters: {}.", fields.join(", "))
+        };
+        AppError::INVALID_INPUT.message(message)
+    })
+}
+
+pub(super) fn validate_page(page: &TransactionsPage) -> AppResult<Option<TransactionsCursor>> {
+    page.validate().map_err(|errors| {
+        debug!("Invalid transactions page. {:?}", errors);
+        AppError::INVALID_INPUT.message("Invalid limit. Must be between 1 and 500.".to_string())
+    })?;
+
+    match &page.cursor {
+        Some(cursor) => decode_cursor::<TransactionsCursor>(cursor)
+            .filter(|cursor| cursor.sort == page.sort)
+            .map(Some)
+            .ok_or_else(|| AppError::INVALID_INPUT.message("Invalid cursor.".to_string())),
+        None => Ok(None),
+    }
+}
+
+#[instrument[skip(repository, syncer)]]
+pub async fn transactions(
+    user: AuthenticatedUser,
+    repository: TransactionRepository,
+    syncer: Data<Syncer>,
+    web::Query(query): web::Query<TransactionsQuery>,
+    web::Query(page): web::Query<TransactionsPage>,
+) -> AppResponse {
+    validate_query(&query)?;
+    let cursor = validate_page(&page)?;
+
+    //Check if the transactions already exist in the database
+    let cached = repository
+        .check_cache(user.id)
+        .await?
+        .ok_or(AppError::INTERNAL_ERROR)?;
+    // If not sync them now instead of waiting for the background sync
+    if cached.results == 0 {
+        if let Err(err) = syncer.sync_user(user.id).await {
+            return Err(provider_error(err));
+        }
+    }
+
+    // retrieve data from local database
+    let transactions = repository.list(user.id, &query, &page, cursor).await?;
+    Ok(HttpResponse::Ok().json(transactions))
+}
+
+fn days_ago(days: i64) -> DateTime<Utc> {
+    Utc::now() - Duration::days(days)
+}
+
+/// Start of the calendar month window ending now, e.g. the 18th of last month
+/// on the 18th. Clamped to the end of shorter months.
+fn month_ago() -> DateTime<Utc> {
+    let now = Utc::now();
+    now.checked_sub_months(Months::new(1)).unwrap_or(now)
+}
+
+/// Lists from `start`, or from the query `from` if that is later.
+async fn list_since(
+    user: AuthenticatedUser,
+    repository: TransactionRepository,
+    mut query: TransactionsQuery,
+    page: TransactionsPage,
+    start: DateTime<Utc>,
+) -> AppResponse {
+    query.from = Some(query.from.map_or(start, |from| from.max(start)));
+    validate_query(&query)?;
+    let cursor = validate_page(&page)?;
+    let transactions = repository.list(user.id, &query, &page, cursor).await?;
+    Ok(HttpResponse::Ok().json(transactions))
+}
+
+async fn list_type(
+    user: AuthenticatedUser,
+    repository: TransactionRepository,
+    mut query: TransactionsQuery,
+    page: TransactionsPage,
+    transaction_type: &str,
+) -> AppResponse {
+    query.transaction_type = Some(transaction_type.to_string());
+    validate_query(&query)?;
+    let cursor = validate_page(&page)?;
+    let transactions = repository.list(user.id, &query, &page, cursor).await?;
+    Ok(HttpResponse::Ok().json(transactions))
+}
+
+async fn totals_since(
+    user: AuthenticatedUser,
+    repository: TransactionRepository,
+    mut query: TransactionsQuery,
+    start: DateTime<Utc>,
+) -> AppResponse {
+    query.from = Some(query.from.map_or(start, |from| from.max(start)));
+    validate_query(&query)?;
+    let totals = repository.totals(user.id, &query).await?;
+    let totals = CategoryTotal::by_category(totals).map_err(|err| {
+        debug!("Cannot total transactions. {}", err);
+        AppError::INVALID_INPUT.message(
+            "Transactions are in more than one currency. Filter them by \"account_id\"."
+                .to_string(),
+        )
+    })?;
+    Ok(HttpResponse::Ok().json(totals))
+}
+
+#[instrument[skip(repository)]]
+pub async fn daily_transactions(
+    user: AuthenticatedUser,
+    repository: TransactionRepository,
+    web::Query(query): web::Query<TransactionsQuery>,
+    web::Query(page): web::Query<TransactionsPage>,
+) -> AppResponse {
+    list_since(user, repository, query, page, days_ago(1)).await
+}
+
+#[instrument[skip(repository)]]
+pub async fn weekly_transactions(
+    user: AuthenticatedUser,
+    repository: TransactionRepository,
+    web::Query(query): web::Query<TransactionsQuery>,
+    web::Query(page): web::Query<TransactionsPage>,
+) -> AppResponse {
+    list_since(user, repository, query, page, days_ago(7)).await
+}
+
+#[instrument[skip(repository)]]
+pub async fn monthly_transactions(
+    user: AuthenticatedUser,
+    repository: TransactionRepository,
+    web::Query(query): web::Query<TransactionsQuery>,
+    web::Query(page): web::Query<TransactionsPage>,
+) -> AppResponse {
+    list_since(user, repository, query, page, month_ago()).await
+}
+
+#[instrument[skip(repository)]]
+pub async fn credit(
+    user: AuthenticatedUser,
+    repository: TransactionRepository,
+    web::Query(query): web::Query<TransactionsQuery>,
+    web::Query(page): web::Query<TransactionsPage>,
+) -> AppResponse {
+    list_type(user, repository, query, page, "CREDIT").await
+}
+
+#[instrument[skip(repository)]]
+pub async fn debit(
+    user: AuthenticatedUser,
+    repository: TransactionRepository,
+    web::Query(query): web::Query<TransactionsQuery>,
+    web::Query(page): web::Query<TransactionsPage>,
+) -> AppResponse {
+    list_type(user, repository, query, page, "DEBIT").await
+}
+
+#[instrument[skip(repository)]]
+pub async fn total_week_transactions(
+    user: AuthenticatedUser,
+    repository: TransactionRepository,
+    web::Query(query): web::Query<TransactionsQuery>,
+) -> AppResponse {
+    totals_since(user, repository, query, days_ago(7)).await
+}
+
+#[instrument[skip(repository)]]
+pub async fn total_month_transactions(
+    user: AuthenticatedUser,
+    repository: TransactionRepository,
+    web::Query(query): web::Query<TransactionsQuery>,
+) -> AppResponse {
+    totals_since(user, repository, query, month_ago()).await
+}
@@ -1,32 +1,65 @@
-use super::{auth::AuthenticatedUser, AppResponse};
+use super::{
+    auth::AuthenticatedUser, password::check_password_policy, provider_error, AppResponse,
+};
 use crate::{
-    config::crypto::CryptoService,
-    config::params::Params,
+    config::{consent::ConsentPolicy, crypto::CryptoService, password::PasswordPolicy},
     db,
+    db::consent::ConsentRepository,
+    db::provider::ProviderRepository,
+    db::token::TokenRepository,
     db::user::UserRepository,
+    db::user_token::UserTokenRepository,
     errors::AppError,
-    models::trans::TransactionsResults,
-    models::user::{NewUser, User},
+    mailer::MailService,
+    models::token::EMAIL_VERIFICATION,
+    models::user::{NewUser, UpdateProfile, UpdateRole, User, VerifyEmail},
+    providers::BankingProvider,
 };
 use actix_web::web;
 use actix_web::{
     web::{Data, Json},
     HttpResponse,
 };
+use chrono::{Duration, Utc};
 use color_eyre::Result;
-use reqwest;
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde::Deserialize;
 use sqlx::{error::DatabaseError, postgres::PgError};
 use std::fmt::Debug;
-use tracing::{debug, instrument};
+use tracing::{debug, error, instrument};
+use uuid::Uuid;
 use validator::Validate;
 
-#[instrument(skip(user, repository, crypto_service))]
+const VERIFICATION_TOKEN_EXPIRATION_HOURS: i64 = 24;
+
+/// Emails a token to confirm the user owns their address.
+async fn send_verification(
+    user: &User,
+    user_tokens: &UserTokenRepository,
+    crypto_service: &CryptoService,
+    mail: &MailService,
+) -> Result<()> {
+    let (token, token_hash) = crypto_service.generate_token();
+    let expires_at = Utc::now() + Duration::hours(VERIFICATION_TOKEN_EXPIRATION_HOURS);
+    user_tokens
+        .create(
+            user.id,
+            EMAIL_VERIFICATION,
+            token_hash,
+            &user.email,
+            expires_at,
+        )
+        .await?;
+    mail.send_verification(&user.email, &token).await
+}
+
+#[instrument(skip(user, repository, user_tokens, crypto_service, policy, mail))]
 pub async fn create_user(
     user: Json<NewUser>,
     repository: UserRepository,
+    user_tokens: UserTokenRepository,
     crypto_service: Data<CryptoService>,
+    policy: Data<PasswordPolicy>,
+    mail: Data<MailService>,
 ) -> AppResponse {
     match user.validate() {
         Ok(_) => Ok(()),
@@ -37,8 +70,6 @@ pub async fn create_user(
                 format!("Invalid username. \"{}\" is too short.", user.username)
             } else if error_map.contains_key("email") {
                 format!("Invalid email address \"{}\"", user.email)
-            } else if error_map.contains_key("password") {
-                "Invalid password. Too short".to_string()
             } else {
                 "Invalid input.".to_string()
             };
@@ -47,36 +78,45 @@ pub async fn create_user(
         }
     }?;
 
+    check_password_policy(&policy, &user.password, &user.username, &user.email)?;
+
     let result: Result<User> = repository.create(user.0, crypto_service.as_ref()).await;
 
     match result {
-        Ok(user) => Ok(HttpResponse::Ok().json(user)),
-        Err(error) => {
-            let pg_error: &PgError =
-                error
-                    .root_cause()
-                    .downcast_ref::<PgError>()
-                    .ok_or_else(|| {
-                        debug!("Error creating user. {:?}", error);
-                        AppError::INTERNAL_ERROR
-                    })?;
-
-            let error = match (pg_error.code(), pg_error.column_name()) {
-                (Some(db::UNIQUE_VIOLATION_CODE), Some("email")) => {
-                    AppError::INVALID_INPUT.message("Email address already exists.".to_string())
-                }
-                (Some(db::UNIQUE_VIOLATION_CODE), Some("username")) => {
-                    AppError::INVALID_INPUT.message("Username already exists.".to_string())
-                }
-                (Some(db::UNIQUE_VIOLATION_CODE), None) => {
-                    AppError::INVALID_INPUT.message("Username or email already exists.".to_string())
-                }
-                _ => {
-                    debug!("Error creating user. {:?}", pg_error);
-                    AppError::INTERNAL_ERROR.into()
-                }
-            };
-            Err(error)
+        Ok(user) => {
+            if let Err(err) = send_verification(&user, &user_tokens, &crypto_service, &mail).await {
+                error!("Cannot send verification email. {:?}", err);
+            }
+            Ok(HttpResponse::Ok().json(user))
+        }
+        Err(error) => Err(conflict_error(error)),
+    }
+}
+
+/// Maps a failed insert or update of a user, telling which unique field is
+/// already taken.
+fn conflict_error(error: eyre::Report) -> AppError {
+    let pg_error = match error.root_cause().downcast_ref::<PgError>() {
+        Some(pg_error) => pg_error,
+        None => {
+            debug!("Error saving user. {:?}", error);
+            return AppError::INTERNAL_ERROR.default();
+        }
+    };
+
+    match (pg_error.code(), pg_error.column_name()) {
+        (Some(db::UNIQUE_VIOLATION_CODE), Some("email")) => {
+            AppError::INVALID_INPUT.message("Email address already exists.".to_string())
+        }
+        (Some(db::UNIQUE_VIOLATION_CODE), Some("username")) => {
+            AppError::INVALID_INPUT.message("Username already exists.".to_string())
+        }
+        (Some(db::UNIQUE_VIOLATION_CODE), None) => {
+            AppError::INVALID_INPUT.message("Username or email already exists.".to_string())
+        }
+        _ => {
+            debug!("Error saving user. {:?}", pg_error);
+            AppError::INTERNAL_ERROR.default()
         }
     }
 }
@@ -84,193 +124,248 @@ pub async fn create_user(
 #[instrument[skip(repository)]]
 pub async fn me(user: AuthenticatedUser, repository: UserRepository) -> AppResponse {
     let user = repository
-        .find_by_id(user.0)
+        .find_by_id(user.id)
         .await?
         .ok_or(AppError::INTERNAL_ERROR)?;
 
     Ok(HttpResponse::Ok().json(user))
 }
 
-#[instrument[skip(repository)]]
-pub async fn daily_transactions(user: AuthenticatedUser, repository: UserRepository) -> AppResponse {
-    let daily = repository
-        .daily_transactions(user.0)
+/// Updates the profile of the user. A new email address is sent a
+/// verification link.
+#[instrument(skip(user, update, repository, user_tokens, crypto_service, mail))]
+pub async fn update_me(
+    user: AuthenticatedUser,
+    update: Json<UpdateProfile>,
+    repository: UserRepository,
+    user_tokens: UserTokenRepository,
+    crypto_service: Data<CryptoService>,
+    mail: Data<MailService>,
+) -> AppResponse {
+    update.validate().map_err(|errors| {
+        let error_map = errors.field_errors();
+        let message = if error_map.contains_key("username") {
+            "Invalid username. Too short.".to_string()
+        } else if error_map.contains_key("email") {
+            format!(
+                "Invalid email address \"{}\"",
+                update.email.as_deref().unwrap_or_default()
+            )
+        } else if error_map.contains_key("full_name") {
+            "Invalid full name. Too long.".to_string()
+        } else {
+            "Invalid input.".to_string()
+        };
+        AppError::INVALID_INPUT.message(message)
+    })?;
+
+    let previous = repository
+        .find_by_id(user.id)
         .await?
-        .ok_or(AppError::INTERNAL_ERROR)?;
+        .ok_or(AppError::NOT_AUTHORIZED)?;
 
-    let serialized: Value = serde_json::from_str(&daily.results as &str).unwrap();
-    Ok(HttpResponse::Ok().json(serialized))
-}
+    let updated = repository
+        .update_profile(user.id, &update)
+        .await
+        .map_err(conflict_error)?
+        .ok_or(AppError::NOT_AUTHORIZED)?;
 
-#[instrument[skip(repository)]]
-pub async fn weekly_transactions(user: AuthenticatedUser, repository: UserRepository) -> AppResponse {
-    let weekly = repository
-        .weekly_transactions(user.0)
-        .await?
-        .ok_or(AppError::INTERNAL_ERROR)?;
-    let serialized: Value = serde_json::from_str(&weekly.results as &str).unwrap();
-    Ok(HttpResponse::Ok().json(serialized))
+    if updated.email != previous.email {
+        if let Err(err) = send_verification(&updated, &user_tokens, &crypto_service, &mail).await {
+            error!("Cannot send verification email. {:?}", err);
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(updated))
 }
 
-#[instrument[skip(repository)]]
-pub async fn monthly_transactions(user: AuthenticatedUser, repository: UserRepository) -> AppResponse {
-    let monthly = repository
-        .monthly_transactions(user.0)
-        .await?
-        .ok_or(AppError::INTERNAL_ERROR)?;
-    let serialized: Value = serde_json::from_str(&monthly.results as &str).unwrap();
-    Ok(HttpResponse::Ok().json(serialized))
+/// Deletes the account of the user: it is deactivated, signed out and
+/// unlinked from the bank straight away, and its transactions are purged
+/// after the grace period.
+#[instrument(skip(
+    user,
+    repository,
+    tokens,
+    provider_repository,
+    consents,
+    crypto_service,
+    provider
+))]
+pub async fn delete_me(
+    user: AuthenticatedUser,
+    repository: UserRepository,
+    tokens: TokenRepository,
+    provider_repository: ProviderRepository,
+    consents: ConsentRepository,
+    crypto_service: Data<CryptoService>,
+    provider: Data<dyn BankingProvider>,
+) -> AppResponse {
+    repository.delete(user.id).await?;
+    tokens.revoke_user_refresh_tokens(user.id).await?;
+
+    let credentials = provider_repository
+        .find_credentials(user.id, provider.name(), &crypto_service)
+        .await?;
+    if let Some(credentials) = credentials {
+        // The grant is dropped on our side even if the provider can't be reached
+        if let Err(err) = provider.revoke(&credentials.access_token).await {
+            error!(
+                "Cannot revoke provider grant of user {}. {:?}",
+                user.id, err
+            );
+        }
+        provider_repository
+            .delete_credentials(user.id, provider.name())
+            .await?;
+    }
+    consents.revoke_all(user.id).await?;
+
+    Ok(HttpResponse::NoContent().finish())
 }
 
-#[instrument[skip(repository)]]
-pub async fn credit(user: AuthenticatedUser, repository: UserRepository) -> AppResponse {
-    let credit = repository
-        .credit(user.0)
+/// Confirms the email address with the link sent by email.
+#[instrument(skip(repository, user_tokens, crypto_service, info))]
+pub async fn verify_email(
+    repository: UserRepository,
+    user_tokens: UserTokenRepository,
+    crypto_service: Data<CryptoService>,
+    web::Query(info): web::Query<VerifyEmail>,
+) -> AppResponse {
+    let invalid = || AppError::INVALID_INPUT.message("Invalid or expired token.".to_string());
+
+    let token_hash = crypto_service.hash_token(&info.token);
+    let token = user_tokens
+        .consume(EMAIL_VERIFICATION, &token_hash)
         .await?
-        .ok_or(AppError::INTERNAL_ERROR)?;
-    let serialized: Value = serde_json::from_str(&credit.results as &str).unwrap();
-    Ok(HttpResponse::Ok().json(serialized))
+        .ok_or_else(invalid)?;
+
+    if !repository.verify_email(token.user_id, &token.email).await? {
+        debug!(
+            "Email of user {} changed since the token was sent",
+            token.user_id
+        );
+        return Err(invalid());
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "email": token.email, "verified": true })))
 }
 
-#[instrument[skip(repository)]]
-pub async fn debit(user: AuthenticatedUser, repository: UserRepository) -> AppResponse {
-    let debit = repository
-        .debit(user.0)
+#[instrument(skip(user, repository, user_tokens, crypto_service, mail))]
+pub async fn resend_verification(
+    user: AuthenticatedUser,
+    repository: UserRepository,
+    user_tokens: UserTokenRepository,
+    crypto_service: Data<CryptoService>,
+    mail: Data<MailService>,
+) -> AppResponse {
+    let user = repository
+        .find_by_id(user.id)
         .await?
-        .ok_or(AppError::INTERNAL_ERROR)?;
-    let serialized: Value = serde_json::from_str(&debit.results as &str).unwrap();
-    Ok(HttpResponse::Ok().json(serialized))
+        .ok_or(AppError::NOT_AUTHORIZED)?;
+
+    if user.email_verified_at.is_some() {
+        return Err(AppError::INVALID_INPUT.message("Email address already verified.".to_string()));
+    }
+
+    send_verification(&user, &user_tokens, &crypto_service, &mail).await?;
+    Ok(HttpResponse::Accepted().finish())
 }
 
 #[instrument[skip(repository)]]
-pub async fn total_week_transactions(user: AuthenticatedUser, repository: UserRepository) -> AppResponse {
-    let total_week = repository
-        .total_week_transactions(user.0)
+pub async fn get_user(
+    _user: AuthenticatedUser,
+    repository: UserRepository,
+    id: web::Path<Uuid>,
+) -> AppResponse {
+    let user = repository
+        .find_by_id(*id)
         .await?
-        .ok_or(AppError::INTERNAL_ERROR)?;
-    let serialized: Value = serde_json::from_str(&total_week.results as &str).unwrap();
-    Ok(HttpResponse::Ok().json(serialized))
+        .ok_or(AppError::NOT_FOUND)?;
+
+    Ok(HttpResponse::Ok().json(user))
 }
 
 #[instrument[skip(repository)]]
-pub async fn total_month_transactions(user: AuthenticatedUser, repository: UserRepository) -> AppResponse {
-    let total_month = repository
-        .total_month_transactions(user.0)
-        .await?
-        .ok_or(AppError::INTERNAL_ERROR)?;
-    let serialized: Value = serde_json::from_str(&total_month.results as &str).unwrap();
-    Ok(HttpResponse::Ok().json(serialized))
-}
+pub async fn update_role(
+    user: AuthenticatedUser,
+    repository: UserRepository,
+    id: web::Path<Uuid>,
+    update: Json<UpdateRole>,
+) -> AppResponse {
+    if *id == user.id {
+        return Err(AppError::INVALID_INPUT.message("Cannot change your own role.".to_string()));
+    }
 
+    let user = repository
+        .update_role(*id, update.role)
+        .await?
+        .ok_or(AppError::NOT_FOUND)?;
 
+    Ok(HttpResponse::Ok().json(user))
+}
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize)]
 pub struct AuthRequest {
     code: String,
+    state: String,
 }
 
-#[instrument(skip(repository))]
+#[instrument(skip(
+    repository,
+    provider_repository,
+    consents,
+    consent_policy,
+    crypto_service,
+    provider,
+    info
+))]
 pub async fn callback_code(
-    //user: AuthenticatedUser, #remove auth for the callback •͡˘㇁•͡˘
     repository: UserRepository,
-    params: Data<Params>,
+    provider_repository: ProviderRepository,
+    consents: ConsentRepository,
+    consent_policy: Data<ConsentPolicy>,
+    crypto_service: Data<CryptoService>,
+    provider: Data<dyn BankingProvider>,
     web::Query(info): web::Query<AuthRequest>,
 ) -> AppResponse {
-    repository.update_code(info.code.to_string()).await?;
-
-    let access_token = exchange_token(params, info.code.to_string());
-
-    repository.update_token(access_token.await).await?;
-    Ok(HttpResponse::Ok().json(info))
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-pub struct Token {
-    access_token: String,
-}
-
-#[instrument(skip(params))]
-pub async fn exchange_token(params: Data<Params>, code: String) -> String {
-    let res = reqwest::Client::new()
-        .post(&params.token_uri.to_string())
-        .form(&[
-            ("grant_type", "authorization_code"),
-            ("client_id", &params.client_id.to_string()),
-            ("client_secret", &params.client_secret.to_string()),
-            ("redirect_uri", &params.redirect_uri.to_string()),
-            ("code", &code),
-        ])
-        .send()
+    let state = crypto_service
+        .check_state(info.state.clone())
         .await
-        .expect("Getting token");
+        .map(|data| data.claims)
+        .map_err(|err| {
+            debug!("Cannot check callback state. {:?}", err);
+            AppError::INVALID_STATE
+        })?;
 
-    let body = res.text().await.expect("Reading Body");
-    let token: Token = serde_json::from_str(&body).unwrap();
-    token.access_token.to_string()
-}
+    if !repository.consume_state(state.nonce, state.uid).await? {
+        debug!(
+            "Callback state already used or expired for user {}",
+            state.uid
+        );
+        return Err(AppError::INVALID_STATE.into());
+    }
 
-#[instrument[skip(repository)]]
-pub async fn transactions(
-    user: AuthenticatedUser,
-    repository: UserRepository,
-    params: Data<Params>,
-) -> AppResponse {
-    let user_account: uuid::Uuid = user.0.clone();
-    //Check if the transactions already exist in the database
-    let user = repository
-        .check_cache(user.0)
+    repository
+        .update_code(state.uid, info.code.to_string(), &crypto_service)
         .await?
-        .ok_or(AppError::INTERNAL_ERROR)?;
-    // If not request a new transaction and save locally
-    if user.results as i64 == 0 {
-        let user_token = repository
-            .get_token(user_account)
-            .await?
-            .ok_or(AppError::INTERNAL_ERROR)?;
-        let _host = format!("{}/data/v1/accounts", params.api_uri);
-        /*
-            Todo: request all the accounts from the user and save locally
-        */
-        let host_temp = "https://api.truelayer-sandbox.com/data/v1/accounts/56c7b029e0f8ec5a2334fb0ffc2fface/transactions";
-        let res = reqwest::Client::new()
-            .get(host_temp)
-            .bearer_auth(user_token.access_token.unwrap())
-            .send()
-            .await
-            .expect("Getting Transactions");
-        
-        // Check for 200 status
-        // and save resquest in the database
-        if res.status().is_success() {
-            
-            let body = res.text().await.expect("Reading Body");
-            //let deserialize: TransactionsResults = serde_json::from_str(&body).unwrap();
-            //let serialized: &TransactionsResults = serde_json::to_string(&body).unwrap();
-
-            let resp: TransactionsResults = serde_json::from_str(&body).unwrap();
-            for model in &resp.results {
-                //println!("{:?}", serde_json::to_string(&model));
-                //let form = format!("{:?}", model);
-                repository.save_trans(user_account, serde_json::to_string(&model).unwrap()).await?;
-            }
+        .ok_or(AppError::INVALID_STATE)?;
 
-            let user_cached = repository.get_cache(user_account)
-            .await?;
-            let serialized: Value = serde_json::from_str(&user_cached.results as &str).unwrap();
+    let token = match provider.exchange_code(&info.code).await {
+        Ok(token) => token,
+        Err(err) => return Err(provider_error(err)),
+    };
 
-            Ok(HttpResponse::Ok().json(serialized))
-        } else {
-            // If any error just forward
-            let body = res.text().await.expect("Reading Body");
-            let serialized: Value = serde_json::from_str(&body).unwrap();
-            Ok(HttpResponse::Ok().json(serialized))
-        }
-    } else {
-        // retrieve data from local database
-        let user_cached = repository.get_cache(user_account)
-            .await?;
-        let serialized: Value = serde_json::from_str(&user_cached.results as &str).unwrap();
-        
-        Ok(HttpResponse::Ok().json(serialized))
-    }
+    provider_repository
+        .save_credentials(state.uid, provider.name(), &token, &crypto_service)
+        .await?;
+    consents
+        .grant(
+            state.uid,
+            provider.name(),
+            token.scope.as_deref(),
+            consent_policy.expires_at(Utc::now()),
+        )
+        .await?;
+    // The code and state are single use secrets, they are not echoed back
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "linked": true, "provider": provider.name() })))
 }
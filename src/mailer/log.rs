@@ -0,0 +1,48 @@
+use super::{Email, Mailer, LOG};
+use async_trait::async_trait;
+use color_eyre::Result;
+use std::path::PathBuf;
+use tokio::io::AsyncWriteExt;
+use tracing::{info, instrument};
+
+/// Logs emails instead of sending them, and appends them to a file in
+/// `MAIL_DIR` when set, for local development and tests.
+pub struct LogMailer {
+    dir: Option<PathBuf>,
+}
+
+impl LogMailer {
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        LogMailer { dir }
+    }
+}
+
+#[async_trait]
+impl Mailer for LogMailer {
+    fn name(&self) -> &'static str {
+        LOG
+    }
+
+    #[instrument(skip(self, email))]
+    async fn send(&self, email: Email) -> Result<()> {
+        info!(
+            "Sending email to {}: {}\n{}",
+            email.to, email.subject, email.body
+        );
+
+        if let Some(dir) = &self.dir {
+            tokio::fs::create_dir_all(dir).await?;
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(dir.join(format!("{}.txt", email.to)))
+                .await?;
+            let content = format!(
+                "To: {}\nSubject: {}\n\n{}\n---\n",
+                email.to, email.subject, email.body
+            );
+            file.write_all(content.as_bytes()).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,99 @@
+pub mod log;
+pub mod smtp;
+
+use async_trait::async_trait;
+use color_eyre::Result;
+use std::sync::Arc;
+use tracing::instrument;
+
+pub const LOG: &str = "log";
+pub const SMTP: &str = "smtp";
+
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Delivers the emails the API sends.
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn send(&self, email: Email) -> Result<()>;
+}
+
+/// The emails sent to users, built on top of the configured `Mailer`.
+#[derive(Clone)]
+pub struct MailService {
+    mailer: Arc<dyn Mailer>,
+    public_url: Arc<String>,
+}
+
+impl MailService {
+    pub fn new(mailer: Arc<dyn Mailer>, public_url: String) -> Self {
+        MailService {
+            mailer,
+            public_url: Arc::new(public_url),
+        }
+    }
+
+    #[instrument(skip(self, token))]
+    pub async fn send_verification(&self, to: &str, token: &str) -> Result<()> {
+        let body = format!(
+            "Please confirm your email address by opening the link below.\n\n\
+            {}/email/verify?token={}\n\n\
+            If you didn't create an account, you can ignore this email.\n",
+            self.public_url, token
+        );
+        self.mailer
+            .send(Email {
+                to: to.to_string(),
+                subject: "Confirm your email address".to_string(),
+                body,
+            })
+            .await
+    }
+
+    #[instrument(skip(self, token))]
+    pub async fn send_password_reset(&self, to: &str, token: &str) -> Result<()> {
+        let body = format!(
+            "A password reset was requested for your account. Use the token below\n\
+            to choose a new password at {}/password/reset.\n\n\
+            {}\n\n\
+            If you didn't request it, you can ignore this email.\n",
+            self.public_url, token
+        );
+        self.mailer
+            .send(Email {
+                to: to.to_string(),
+                subject: "Reset your password".to_string(),
+                body,
+            })
+            .await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn send_password_changed(&self, to: &str) -> Result<()> {
+        let body = "The password of your account was just changed. If it wasn't you,\n\
+            reset your password right away.\n"
+            .to_string();
+        self.mailer
+            .send(Email {
+                to: to.to_string(),
+                subject: "Your password was changed".to_string(),
+                body,
+            })
+            .await
+    }
+}
+
+impl std::fmt::Debug for MailService {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MailService")
+            .field("mailer", &self.mailer.name())
+            .field("public_url", &self.public_url)
+            .finish()
+    }
+}
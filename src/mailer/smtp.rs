@@ -0,0 +1,57 @@
+use super::{Email, Mailer, SMTP};
+use actix_web::web::block;
+use async_trait::async_trait;
+use color_eyre::Result;
+use eyre::eyre;
+use lettre::smtp::authentication::Credentials;
+use lettre::{SmtpClient, Transport};
+use lettre_email::EmailBuilder;
+use tracing::instrument;
+
+pub struct SmtpMailer {
+    host: String,
+    username: String,
+    password: String,
+    from: String,
+}
+
+impl SmtpMailer {
+    pub fn new(host: String, username: String, password: String, from: String) -> Self {
+        SmtpMailer {
+            host,
+            username,
+            password,
+            from,
+        }
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    fn name(&self) -> &'static str {
+        SMTP
+    }
+
+    #[instrument(skip(self, email))]
+    async fn send(&self, email: Email) -> Result<()> {
+        let message = EmailBuilder::new()
+            .to(email.to)
+            .from(self.from.clone())
+            .subject(email.subject)
+            .text(email.body)
+            .build()
+            .map_err(|err| eyre!("Building email: {}", err))?;
+
+        let host = self.host.clone();
+        let credentials = Credentials::new(self.username.clone(), self.password.clone());
+        // lettre's transport is blocking
+        block(move || {
+            let mut transport = SmtpClient::new_simple(&host)?
+                .credentials(credentials)
+                .transport();
+            transport.send(message.into()).map(|_| ())
+        })
+        .await
+        .map_err(|err| eyre!("Sending email: {}", err))
+    }
+}
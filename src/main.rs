@@ -1,15 +1,21 @@
 #[macro_use]
 extern crate validator_derive;
 
+mod commands;
 mod config;
 mod db;
 mod errors;
+mod export;
 mod handlers;
+mod mailer;
 mod models;
+mod providers;
+mod purge;
+mod sync;
 
 use crate::config::Config;
 use actix_web::middleware::Logger;
-use actix_web::{App, HttpServer};
+use actix_web::{web::Data, App, HttpServer};
 use color_eyre::Result;
 use handlers::app_config;
 use tracing::{info, instrument};
@@ -23,13 +29,45 @@ async fn main() -> Result<()> {
 
     let pool = config.db_pool().await.expect("Database configuration");
 
-    let hashing = config.hashing();
+    let hashing = config.hashing().expect("Crypto configuration");
 
-    let params = config.params();
+    let lockout = config.lockout().expect("Lockout configuration");
+
+    let password_policy = config.password_policy();
+
+    let mail_service = config.mail_service().expect("Mailer configuration");
+
+    let provider_client = config
+        .provider_client()
+        .expect("Provider client configuration");
+
+    let provider = config
+        .provider(provider_client.clone())
+        .expect("Banking provider configuration");
+
+    let consent_policy = config.consent_policy();
+
+    if std::env::args().nth(1).as_deref() == Some(commands::ROTATE_KEYS) {
+        return commands::rotate_keys(pool, &hashing).await;
+    }
+
+    commands::encrypt_legacy(pool.clone(), &hashing)
+        .await
+        .expect("Encrypting legacy tokens");
+
+    let syncer = config.syncer(pool.clone(), hashing.clone(), provider.clone());
+    actix_rt::spawn(syncer.clone().run());
+    actix_rt::spawn(config.purger(pool.clone()).run());
+
+    let exporter = config.exporter(pool.clone());
+    actix_rt::spawn(exporter.clone().run());
 
     info!("Starting server at http://{}:{}/", config.host, config.port);
 
     let prometheus = PrometheusMetrics::new("api", Some("/metrics"),None);
+    provider_client
+        .register(&prometheus.registry)
+        .expect("Provider metrics registration");
 
         HttpServer::new(move || {
             App::new()
@@ -37,7 +75,14 @@ async fn main() -> Result<()> {
                 .wrap(prometheus.clone())
                 .data(pool.clone())
                 .data(hashing.clone())
-                .data(params.clone())
+                .data(lockout.clone())
+                .data(password_policy.clone())
+                .data(mail_service.clone())
+                .data(provider_client.clone())
+                .app_data(Data::from(provider.clone()))
+                .data(consent_policy.clone())
+                .data(syncer.clone())
+                .data(exporter.clone())
                 .configure(app_config)
         })
         .bind(format!("{}:{}", config.host, config.port))?
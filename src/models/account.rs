@@ -0,0 +1,97 @@
+use super::money::{Money, MoneyError};
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+/// Days of balance history returned when no range is given.
+pub const DEFAULT_HISTORY_DAYS: i64 = 90;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AccountsResults {
+    pub results: Vec<Account>,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow, Deserialize, Serialize)]
+pub struct Account {
+    pub account_id: String,
+    pub account_type: Option<String>,
+    pub display_name: Option<String>,
+    pub currency: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BalanceResults {
+    pub results: Vec<Balance>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Balance {
+    pub currency: String,
+    pub available: Option<BigDecimal>,
+    pub current: BigDecimal,
+    pub overdraft: Option<BigDecimal>,
+    pub update_timestamp: Option<String>,
+}
+
+/// The balance of an account on a day, as stored.
+#[derive(Debug, sqlx::FromRow, Serialize)]
+pub struct BalanceSnapshot {
+    pub provider: String,
+    pub account_id: String,
+    pub snapshot_date: NaiveDate,
+    pub currency: String,
+    pub current: BigDecimal,
+    pub available: Option<BigDecimal>,
+    pub overdraft: Option<BigDecimal>,
+    pub fetched_at: DateTime<Utc>,
+}
+
+impl BalanceSnapshot {
+    pub fn into_balance(self) -> Result<AccountBalance, MoneyError> {
+        let money = |amount: Option<BigDecimal>| {
+            amount
+                .map(|amount| Money::new(amount, &self.currency))
+                .transpose()
+        };
+        Ok(AccountBalance {
+            current: Money::new(self.current.clone(), &self.currency)?,
+            available: money(self.available.clone())?,
+            overdraft: money(self.overdraft.clone())?,
+            account_id: self.account_id,
+            date: self.snapshot_date,
+            updated_at: self.fetched_at,
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccountBalance {
+    pub account_id: String,
+    pub date: NaiveDate,
+    pub current: Money,
+    pub available: Option<Money>,
+    pub overdraft: Option<Money>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BalanceHistory {
+    pub account_id: String,
+    pub results: Vec<AccountBalance>,
+}
+
+/// Range of days of the balance history, both ends included.
+#[derive(Debug, Default, Deserialize, Validate)]
+#[validate(schema(function = "validate_range"))]
+pub struct BalanceHistoryQuery {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+fn validate_range(query: &BalanceHistoryQuery) -> Result<(), ValidationError> {
+    match (&query.from, &query.to) {
+        (Some(from), Some(to)) if from > to => Err(ValidationError::new("date_range")),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,82 @@
+use super::role::Permission;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+/// Start of every API key, which tells them apart from JWTs in the
+/// `Authorization` header.
+pub const API_KEY_PREFIX: &str = "bak_";
+
+/// Permissions an API key can be scoped to: read-only access to the profile
+/// and the transactions.
+pub const API_KEY_SCOPES: [Permission; 2] = [Permission::ReadProfile, Permission::ReadTransactions];
+
+/// A personal API key as stored, only its hash is kept and it is never read
+/// back.
+#[derive(Debug, sqlx::FromRow)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    /// The first characters of the key, to recognise it in listings.
+    pub prefix: String,
+    pub scopes: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ApiKey {
+    /// The permissions the key was created with. Unknown ones are ignored.
+    pub fn scopes(&self) -> Vec<Permission> {
+        self.scopes
+            .split(',')
+            .filter_map(|scope| scope.trim().parse().ok())
+            .collect()
+    }
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct NewApiKey {
+    #[validate(length(min = 1, max = 100))]
+    pub name: String,
+    /// Defaults to `read_transactions`.
+    pub scopes: Option<Vec<Permission>>,
+    /// Keys don't expire unless set.
+    #[validate(range(min = 1, max = 365))]
+    pub expires_in_days: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct ApiKeyInfo {
+    pub id: Uuid,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<Permission>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<ApiKey> for ApiKeyInfo {
+    fn from(key: ApiKey) -> Self {
+        ApiKeyInfo {
+            scopes: key.scopes(),
+            id: key.id,
+            name: key.name,
+            prefix: key.prefix,
+            expires_at: key.expires_at,
+            last_used_at: key.last_used_at,
+            created_at: key.created_at,
+        }
+    }
+}
+
+/// A newly created key, the only time the key itself is returned.
+#[derive(Serialize)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub info: ApiKeyInfo,
+    pub key: String,
+}
@@ -0,0 +1,48 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+pub const LOGIN_SUCCEEDED: &str = "login_succeeded";
+pub const LOGIN_FAILED: &str = "login_failed";
+pub const LOGIN_BLOCKED: &str = "login_blocked";
+pub const ACCOUNT_INACTIVE: &str = "account_inactive";
+pub const MFA_REQUIRED: &str = "mfa_required";
+pub const MFA_FAILED: &str = "mfa_failed";
+
+#[derive(Debug, sqlx::FromRow, Serialize)]
+pub struct AuthEvent {
+    #[serde(skip_serializing)]
+    pub id: i64,
+    pub event: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Who attempted to log in, and from where.
+#[derive(Debug, Clone)]
+pub struct LoginAttempt {
+    pub username: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Throttle {
+    pub failures: i32,
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AuthEventsCursor {
+    pub id: i64,
+}
+
+impl LoginAttempt {
+    pub fn user_key(&self) -> String {
+        format!("user:{}", self.username.to_lowercase())
+    }
+
+    pub fn ip_key(&self) -> Option<String> {
+        self.ip.as_ref().map(|ip| format!("ip:{}", ip))
+    }
+}
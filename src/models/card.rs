@@ -0,0 +1,21 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CardsResults {
+    pub results: Vec<Card>,
+}
+
+/// A card, in the shape the provider sends it. Its transactions are
+/// `TransactionsAccount` like those of accounts.
+#[derive(Debug, Clone, sqlx::FromRow, Deserialize, Serialize)]
+pub struct Card {
+    pub account_id: String,
+    pub card_network: Option<String>,
+    pub card_type: Option<String>,
+    pub currency: Option<String>,
+    pub display_name: Option<String>,
+    pub partial_card_number: Option<String>,
+    pub name_on_card: Option<String>,
+    pub valid_from: Option<String>,
+    pub valid_to: Option<String>,
+}
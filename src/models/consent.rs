@@ -0,0 +1,84 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+pub const CONSENT_ACTIVE: &str = "active";
+pub const CONSENT_EXPIRED: &str = "expired";
+pub const CONSENT_REVOKED: &str = "revoked";
+
+/// Access a user granted us to their data at a provider. Consents lapse after
+/// a while and the user has to grant a new one.
+#[derive(Debug, sqlx::FromRow, Serialize)]
+pub struct Consent {
+    pub id: Uuid,
+    pub provider: String,
+    pub scopes: Option<String>,
+    pub status: String,
+    pub granted_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Consent {
+    /// The consent as of `now`: an active one past its expiry is expired,
+    /// whether or not that was written back yet.
+    pub fn at(mut self, now: DateTime<Utc>) -> Self {
+        if self.status == CONSENT_ACTIVE && self.expires_at <= now {
+            self.status = CONSENT_EXPIRED.to_string();
+        }
+        self
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConsentsResults {
+    pub results: Vec<Consent>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    fn consent(status: &str, expires_at: DateTime<Utc>) -> Consent {
+        let granted_at = expires_at - Duration::days(90);
+        Consent {
+            id: Uuid::nil(),
+            provider: "mock".to_string(),
+            scopes: None,
+            status: status.to_string(),
+            granted_at,
+            expires_at,
+            revoked_at: None,
+            updated_at: granted_at,
+        }
+    }
+
+    #[test]
+    fn active_consent_lapses_at_expiry() {
+        let expires_at = Utc.with_ymd_and_hms(2020, 12, 4, 9, 0, 0).unwrap();
+        let status = |now| consent(CONSENT_ACTIVE, expires_at).at(now).status;
+
+        assert_eq!(status(expires_at - Duration::seconds(1)), CONSENT_ACTIVE);
+        assert_eq!(status(expires_at), CONSENT_EXPIRED);
+        assert_eq!(status(expires_at + Duration::days(1)), CONSENT_EXPIRED);
+    }
+
+    #[test]
+    fn ended_consents_keep_their_status() {
+        let expires_at = Utc.with_ymd_and_hms(2020, 12, 4, 9, 0, 0).unwrap();
+        let later = expires_at + Duration::days(1);
+
+        assert_eq!(
+            consent(CONSENT_REVOKED, expires_at).at(later).status,
+            CONSENT_REVOKED
+        );
+        assert_eq!(
+            consent(CONSENT_EXPIRED, expires_at + Duration::days(7))
+                .at(expires_at)
+                .status,
+            CONSENT_EXPIRED
+        );
+    }
+}
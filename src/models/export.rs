@@ -0,0 +1,95 @@
+use super::{
+    account::{Account, BalanceSnapshot},
+    api_key::ApiKeyInfo,
+    auth_event::AuthEvent,
+    card::Card,
+    consent::Consent,
+    payment::{DirectDebitRecord, StandingOrderRecord},
+    user::User,
+};
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+pub const EXPORT_PENDING: &str = "pending";
+pub const EXPORT_RUNNING: &str = "running";
+pub const EXPORT_READY: &str = "ready";
+pub const EXPORT_FAILED: &str = "failed";
+pub const EXPORT_EXPIRED: &str = "expired";
+
+/// A data export request. The archive itself is only loaded to be
+/// downloaded.
+#[derive(Debug, sqlx::FromRow)]
+pub struct DataExport {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub status: String,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+pub struct ExportStatus {
+    pub id: Uuid,
+    pub status: String,
+    /// Why the export failed, when it did.
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Set once the archive is ready, until it expires.
+    pub download_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DownloadExport {
+    pub token: String,
+}
+
+/// A bank the user linked, without the tokens.
+#[derive(Debug, sqlx::FromRow, Serialize)]
+pub struct LinkedProvider {
+    pub provider: String,
+    pub scopes: Option<String>,
+    pub expires_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+/// A transaction as stored, along with the data the provider sent for it.
+#[derive(Debug, sqlx::FromRow, Serialize)]
+pub struct ExportedTransaction {
+    pub account_id: String,
+    pub provider_transaction_id: String,
+    pub booked_at: DateTime<Utc>,
+    pub amount: BigDecimal,
+    pub currency: String,
+    pub transaction_type: Option<String>,
+    pub transaction_category: Option<String>,
+    pub description: Option<String>,
+    pub raw: serde_json::Value,
+    pub created_at: NaiveDateTime,
+}
+
+/// Everything stored about a user, as written to `export.json`.
+#[derive(Serialize)]
+pub struct ExportData {
+    pub exported_at: DateTime<Utc>,
+    pub profile: User,
+    pub providers: Vec<LinkedProvider>,
+    pub consents: Vec<Consent>,
+    pub api_keys: Vec<ApiKeyInfo>,
+    pub auth_events: Vec<AuthEvent>,
+    pub transactions: Vec<ExportedTransaction>,
+    pub accounts: Vec<Account>,
+    pub balances: Vec<BalanceSnapshot>,
+    pub cards: Vec<Card>,
+    pub card_transactions: Vec<ExportedTransaction>,
+    pub direct_debits: Vec<DirectDebitRecord>,
+    pub standing_orders: Vec<StandingOrderRecord>,
+}
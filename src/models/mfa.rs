@@ -0,0 +1,42 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// TOTP settings of a user. The secret is encrypted at rest, and 2FA only
+/// applies once `enabled_at` is set by a confirmed code. The last step used is
+/// only compared in SQL, to refuse replayed codes.
+#[derive(Debug, sqlx::FromRow)]
+pub struct UserMfa {
+    pub user_id: Uuid,
+    pub secret: String,
+    pub enabled_at: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize)]
+pub struct MfaEnrollment {
+    pub secret: String,
+    pub provisioning_uri: String,
+}
+
+#[derive(Serialize)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MfaCode {
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MfaLogin {
+    pub mfa_token: String,
+    /// A TOTP code or one of the recovery codes.
+    pub code: String,
+}
+
+#[derive(Serialize)]
+pub struct MfaRequired {
+    pub mfa_required: bool,
+    pub mfa_token: String,
+}
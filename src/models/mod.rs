@@ -1,2 +1,16 @@
+pub mod account;
+pub mod api_key;
+pub mod auth_event;
+pub mod card;
+pub mod consent;
+pub mod export;
+pub mod mfa;
+pub mod money;
+pub mod page;
+pub mod payment;
+pub mod provider;
+pub mod role;
+pub mod sync;
+pub mod token;
 pub mod user;
 pub mod trans;
@@ -0,0 +1,204 @@
+use bigdecimal::BigDecimal;
+use num_bigint::BigInt;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+/// An exact monetary amount in an ISO-4217 currency.
+///
+/// Amounts are kept at full precision and only rounded to the currency minor
+/// units when serialized, where they are written as strings so clients never
+/// see binary floating point values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Money {
+    amount: BigDecimal,
+    currency: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MoneyError {
+    InvalidCurrency(String),
+    CurrencyMismatch(String, String),
+}
+
+impl fmt::Display for MoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoneyError::InvalidCurrency(currency) => {
+                write!(f, "Invalid ISO-4217 currency code \"{}\"", currency)
+            }
+            MoneyError::CurrencyMismatch(left, right) => {
+                write!(f, "Cannot combine amounts in {} and {}", left, right)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MoneyError {}
+
+/// Number of digits after the decimal separator used by a currency.
+fn minor_units(currency: &str) -> i64 {
+    match currency {
+        "BIF" | "CLP" | "DJF" | "GNF" | "ISK" | "JPY" | "KMF" | "KRW" | "PYG" | "RWF" | "UGX"
+        | "UYI" | "VND" | "VUV" | "XAF" | "XOF" | "XPF" => 0,
+        "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => 3,
+        "CLF" | "UYW" => 4,
+        _ => 2,
+    }
+}
+
+impl Money {
+    pub fn new(amount: BigDecimal, currency: &str) -> Result<Money, MoneyError> {
+        let currency = currency.trim().to_uppercase();
+        if currency.len() != 3 || !currency.chars().all(|c| c.is_ascii_uppercase()) {
+            return Err(MoneyError::InvalidCurrency(currency));
+        }
+        Ok(Money { amount, currency })
+    }
+
+    pub fn amount(&self) -> &BigDecimal {
+        &self.amount
+    }
+
+    pub fn currency(&self) -> &str {
+        &self.currency
+    }
+
+    /// The amount rounded half away from zero to the currency minor units.
+    pub fn rounded(&self) -> BigDecimal {
+        let scale = minor_units(&self.currency);
+        let half = BigDecimal::new(BigInt::from(5), scale + 1);
+        let adjusted = if self.amount < BigDecimal::from(0) {
+            &self.amount - half
+        } else {
+            &self.amount + half
+        };
+        // with_scale truncates toward zero
+        adjusted.with_scale(scale)
+    }
+
+    pub fn checked_add(&self, other: &Money) -> Result<Money, MoneyError> {
+        self.same_currency(other)?;
+        Ok(Money {
+            amount: &self.amount + &other.amount,
+            currency: self.currency.clone(),
+        })
+    }
+
+    fn same_currency(&self, other: &Money) -> Result<(), MoneyError> {
+        if self.currency == other.currency {
+            Ok(())
+        } else {
+            Err(MoneyError::CurrencyMismatch(
+                self.currency.clone(),
+                other.currency.clone(),
+            ))
+        }
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.rounded(), self.currency)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct MoneyJson {
+    amount: BigDecimal,
+    currency: String,
+}
+
+impl Serialize for Money {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        MoneyJson {
+            amount: self.rounded(),
+            currency: self.currency.clone(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let json = MoneyJson::deserialize(deserializer)?;
+        Money::new(json.amount, &json.currency).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn money(amount: &str, currency: &str) -> Money {
+        Money::new(BigDecimal::from_str(amount).unwrap(), currency).unwrap()
+    }
+
+    fn decimal(value: &str) -> BigDecimal {
+        BigDecimal::from_str(value).unwrap()
+    }
+
+    #[test]
+    fn rounds_half_away_from_zero() {
+        assert_eq!(money("1.005", "EUR").rounded(), decimal("1.01"));
+        assert_eq!(money("1.004", "EUR").rounded(), decimal("1.00"));
+        assert_eq!(money("-1.005", "EUR").rounded(), decimal("-1.01"));
+        assert_eq!(money("-1.004", "EUR").rounded(), decimal("-1.00"));
+        assert_eq!(money("12", "EUR").rounded(), decimal("12.00"));
+    }
+
+    #[test]
+    fn rounds_to_currency_minor_units() {
+        assert_eq!(money("1234.5", "JPY").rounded(), decimal("1235"));
+        assert_eq!(money("-0.5", "JPY").rounded(), decimal("-1"));
+        assert_eq!(money("1.2345", "KWD").rounded(), decimal("1.235"));
+        assert_eq!(money("1.23456", "CLF").rounded(), decimal("1.2346"));
+    }
+
+    #[test]
+    fn normalizes_and_validates_currency() {
+        assert_eq!(money("1", " gbp ").currency(), "GBP");
+        assert_eq!(
+            Money::new(decimal("1"), "EURO"),
+            Err(MoneyError::InvalidCurrency("EURO".to_string()))
+        );
+        assert!(Money::new(decimal("1"), "E1R").is_err());
+    }
+
+    #[test]
+    fn combines_only_same_currency() {
+        let sum = money("0.1", "EUR")
+            .checked_add(&money("0.2", "EUR"))
+            .unwrap();
+        assert_eq!(sum.amount(), &decimal("0.3"));
+        assert_eq!(
+            money("1", "EUR").checked_add(&money("1", "USD")),
+            Err(MoneyError::CurrencyMismatch(
+                "EUR".to_string(),
+                "USD".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn serializes_rounded_amount() {
+        let json = serde_json::to_value(money("10.125", "USD")).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"amount": "10.13", "currency": "USD"})
+        );
+
+        let parsed: Money = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, money("10.13", "USD"));
+        assert!(serde_json::from_value::<Money>(
+            serde_json::json!({"amount": "1", "currency": "usd1"})
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,84 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use validator::Validate;
+
+/// One page of a listing, `next_cursor` is absent on the last page.
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub results: Vec<T>,
+    pub next_cursor: Option<String>,
+    pub total_count: i64,
+}
+
+pub const DEFAULT_LIMIT: i64 = 50;
+
+/// Pagination parameters of listings with a fixed order.
+#[derive(Debug, Deserialize, Validate)]
+pub struct PageQuery {
+    #[validate(range(min = 1, max = 500))]
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+}
+
+/// Encodes a keyset position as an opaque, URL safe cursor.
+pub fn encode_cursor<T: Serialize>(position: &T) -> String {
+    let json = serde_json::to_vec(position).expect("Serializing cursor");
+    base64::encode_config(&json, base64::URL_SAFE_NO_PAD)
+}
+
+/// Decodes a cursor produced by `encode_cursor`, `None` if it was tampered
+/// with or belongs to another listing.
+pub fn decode_cursor<T: DeserializeOwned>(cursor: &str) -> Option<T> {
+    let json = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD).ok()?;
+    serde_json::from_slice(&json).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Position {
+        booked_at: String,
+        id: i32,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct OtherPosition {
+        #[allow(dead_code)]
+        created_at: String,
+    }
+
+    fn position() -> Position {
+        Position {
+            booked_at: "2020-12-04T09:00:00Z".to_string(),
+            id: 42,
+        }
+    }
+
+    #[test]
+    fn cursor_round_trips() {
+        let cursor = encode_cursor(&position());
+        assert!(cursor
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        assert_eq!(decode_cursor::<Position>(&cursor), Some(position()));
+    }
+
+    #[test]
+    fn rejects_tampered_cursor() {
+        let mut cursor = encode_cursor(&position());
+        cursor.insert(3, '*');
+        assert_eq!(decode_cursor::<Position>(&cursor), None);
+        assert_eq!(decode_cursor::<Position>(&cursor[..cursor.len() / 2]), None);
+        assert_eq!(decode_cursor::<Position>(""), None);
+
+        let not_json = base64::encode_config(b"id=42", base64::URL_SAFE_NO_PAD);
+        assert_eq!(decode_cursor::<Position>(&not_json), None);
+    }
+
+    #[test]
+    fn rejects_cursor_of_another_listing() {
+        let cursor = encode_cursor(&position());
+        assert!(decode_cursor::<OtherPosition>(&cursor).is_none());
+    }
+}
@@ -0,0 +1,115 @@
+use super::money::Money;
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DirectDebitsResults {
+    pub results: Vec<DirectDebit>,
+}
+
+/// A direct debit mandate, in the shape the provider sends it.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DirectDebit {
+    pub direct_debit_id: String,
+    pub timestamp: Option<String>,
+    pub name: String,
+    pub status: Option<String>,
+    pub previous_payment_timestamp: Option<String>,
+    pub previous_payment_amount: Option<BigDecimal>,
+    pub currency: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct StandingOrdersResults {
+    pub results: Vec<StandingOrder>,
+}
+
+/// A standing order, in the shape the provider sends it. Providers don't
+/// give them an id.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StandingOrder {
+    pub frequency: Option<String>,
+    pub status: Option<String>,
+    pub timestamp: Option<String>,
+    pub currency: String,
+    pub next_payment_date: Option<String>,
+    pub next_payment_amount: Option<BigDecimal>,
+    pub first_payment_date: Option<String>,
+    pub first_payment_amount: Option<BigDecimal>,
+    pub final_payment_date: Option<String>,
+    pub final_payment_amount: Option<BigDecimal>,
+    pub reference: Option<String>,
+    pub payee: Option<String>,
+}
+
+/// A stored direct debit, field names match the provider's.
+#[derive(Debug, Serialize)]
+pub struct DirectDebitRecord {
+    #[serde(skip_serializing)]
+    pub id: i64,
+    pub account_id: String,
+    pub direct_debit_id: String,
+    pub name: String,
+    pub status: Option<String>,
+    #[serde(rename = "timestamp")]
+    pub mandated_at: Option<DateTime<Utc>>,
+    #[serde(rename = "previous_payment_timestamp")]
+    pub previous_payment_at: Option<DateTime<Utc>>,
+    pub previous_payment_amount: Option<Money>,
+}
+
+/// A stored standing order, field names match the provider's.
+#[derive(Debug, Serialize)]
+pub struct StandingOrderRecord {
+    #[serde(skip_serializing)]
+    pub id: i64,
+    pub account_id: String,
+    pub frequency: Option<String>,
+    pub status: Option<String>,
+    pub reference: Option<String>,
+    pub payee: Option<String>,
+    #[serde(rename = "next_payment_date")]
+    pub next_payment_at: Option<DateTime<Utc>>,
+    pub next_payment_amount: Option<Money>,
+    #[serde(rename = "first_payment_date")]
+    pub first_payment_at: Option<DateTime<Utc>>,
+    pub first_payment_amount: Option<Money>,
+    #[serde(rename = "final_payment_date")]
+    pub final_payment_at: Option<DateTime<Utc>>,
+    pub final_payment_amount: Option<Money>,
+}
+
+/// Filters accepted by the direct debits and standing orders listings,
+/// combined like those of transactions.
+#[derive(Debug, Default, Deserialize, Validate)]
+#[validate(schema(function = "validate_range"))]
+pub struct PaymentsQuery {
+    /// Payment at or after: the previous payment of direct debits, the next
+    /// one of standing orders.
+    pub from: Option<DateTime<Utc>>,
+    /// Payment before.
+    pub to: Option<DateTime<Utc>>,
+    #[validate(length(min = 1))]
+    pub account_id: Option<String>,
+    #[validate(length(min = 1))]
+    pub status: Option<String>,
+    /// Text searched in the name of direct debits, in the payee and reference
+    /// of standing orders.
+    #[validate(length(min = 2, max = 100))]
+    pub q: Option<String>,
+}
+
+/// Position of the last direct debit or standing order of a page.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PaymentsCursor {
+    pub id: i64,
+}
+
+fn validate_range(query: &PaymentsQuery) -> Result<(), ValidationError> {
+    match (&query.from, &query.to) {
+        (Some(from), Some(to)) if from > to => Err(ValidationError::new("date_range")),
+        _ => Ok(()),
+    }
+}
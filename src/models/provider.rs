@@ -0,0 +1,18 @@
+use chrono::NaiveDateTime;
+use serde::Deserialize;
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct ProviderCredentials {
+    pub id: i64,
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Token {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    pub expires_in: i64,
+    pub scope: Option<String>,
+}
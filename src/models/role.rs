@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    #[default]
+    Customer,
+    Support,
+    Admin,
+}
+
+/// What a token allows. Routes declare the permission they require in
+/// `handlers::app_config`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    ReadProfile,
+    ManageProfile,
+    ReadTransactions,
+    SyncTransactions,
+    ReadUsers,
+    ManageUsers,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Customer => "customer",
+            Role::Support => "support",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn permissions(self) -> Vec<Permission> {
+        match self {
+            Role::Customer => vec![
+                Permission::ReadProfile,
+                Permission::ManageProfile,
+                Permission::ReadTransactions,
+                Permission::SyncTransactions,
+            ],
+            Role::Support => vec![
+                Permission::ReadProfile,
+                Permission::ManageProfile,
+                Permission::ReadTransactions,
+                Permission::ReadUsers,
+            ],
+            Role::Admin => vec![
+                Permission::ReadProfile,
+                Permission::ManageProfile,
+                Permission::ReadTransactions,
+                Permission::SyncTransactions,
+                Permission::ReadUsers,
+                Permission::ManageUsers,
+            ],
+        }
+    }
+}
+
+impl Permission {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Permission::ReadProfile => "read_profile",
+            Permission::ManageProfile => "manage_profile",
+            Permission::ReadTransactions => "read_transactions",
+            Permission::SyncTransactions => "sync_transactions",
+            Permission::ReadUsers => "read_users",
+            Permission::ManageUsers => "manage_users",
+        }
+    }
+}
+
+impl FromStr for Permission {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "read_profile" => Ok(Permission::ReadProfile),
+            "manage_profile" => Ok(Permission::ManageProfile),
+            "read_transactions" => Ok(Permission::ReadTransactions),
+            "sync_transactions" => Ok(Permission::SyncTransactions),
+            "read_users" => Ok(Permission::ReadUsers),
+            "manage_users" => Ok(Permission::ManageUsers),
+            _ => Err(format!("Unknown permission {}", value)),
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "customer" => Ok(Role::Customer),
+            "support" => Ok(Role::Support),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!("Unknown role {}", value)),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -0,0 +1,45 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// A refresh token as stored, only its hash is kept.
+///
+/// Every refresh rotates the token: the presented one is marked used and a new
+/// one is issued in the same family. A used or revoked token presented again
+/// means it leaked, and the whole family is revoked.
+#[derive(Debug, sqlx::FromRow)]
+pub struct RefreshToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub family_id: Uuid,
+    pub expires_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct LogoutRequest {
+    pub refresh_token: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Tokens {
+    pub token: String,
+    pub refresh_token: String,
+}
+
+pub const PASSWORD_RESET: &str = "password_reset";
+pub const EMAIL_VERIFICATION: &str = "email_verification";
+
+/// A single use token sent by email, for `PASSWORD_RESET` or
+/// `EMAIL_VERIFICATION`.
+#[derive(Debug, sqlx::FromRow)]
+pub struct UserToken {
+    pub user_id: Uuid,
+    /// The address the token was sent to.
+    pub email: String,
+}
@@ -1,10 +1,8 @@
+use super::money::{Money, MoneyError};
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-
-#[derive(Debug, sqlx::FromRow, Serialize)]
-//#[serde(rename_all = "camelCase")]
-pub struct Transactions {
-    pub results: String,
-}
+use validator::{Validate, ValidationError};
 
 #[derive(Debug, sqlx::FromRow, Serialize)]
 //#[serde(rename_all = "camelCase")]
@@ -12,19 +10,203 @@ pub struct CheckCache {
     pub results: i64,
 }
 
+/// A stored transaction. Field names in the JSON output match the provider's
+/// so clients see the same shape they always did.
+#[derive(Debug, Serialize)]
+pub struct Transaction {
+    #[serde(skip_serializing)]
+    pub id: i64,
+    pub account_id: String,
+    #[serde(rename = "transaction_id")]
+    pub provider_transaction_id: String,
+    #[serde(rename = "timestamp")]
+    pub booked_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub amount: Money,
+    pub transaction_type: Option<String>,
+    pub transaction_category: Option<String>,
+    pub description: Option<String>,
+}
+
+/// Total of a category, amounts in different currencies are never summed
+/// together.
+#[derive(Debug, Serialize)]
+pub struct CategoryTotal {
+    pub transaction_category: Option<String>,
+    #[serde(flatten)]
+    pub total: Money,
+}
+
+impl CategoryTotal {
+    /// Merges per currency totals into one total per category. Fails when a
+    /// category has amounts in more than one currency.
+    pub fn by_category(totals: Vec<CategoryTotal>) -> Result<Vec<CategoryTotal>, MoneyError> {
+        let mut merged: Vec<CategoryTotal> = Vec::with_capacity(totals.len());
+        for total in totals {
+            match merged
+                .iter_mut()
+                .find(|merged| merged.transaction_category == total.transaction_category)
+            {
+                Some(merged) => merged.total = merged.total.checked_add(&total.total)?,
+                None => merged.push(total),
+            }
+        }
+        Ok(merged)
+    }
+}
+
 #[derive(Debug, sqlx::FromRow, Deserialize, Serialize)]
 pub struct TransactionsResults {
     pub results: Vec<TransactionsAccount>,
     //status: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct TransactionsAccount {
     pub timestamp: String,
     pub description: String,
     pub transaction_type: String,
     pub transaction_category: String,
-    pub amount: f32,
+    pub amount: BigDecimal,
     pub currency: String,
     pub transaction_id: String,
+    #[serde(default)]
+    pub account_id: Option<String>,
+}
+
+impl TransactionsAccount {
+    pub fn money(&self) -> Result<Money, MoneyError> {
+        Money::new(self.amount.clone(), &self.currency)
+    }
+}
+
+/// Filters accepted by the transactions listings. Every filter is optional and
+/// they are all combined.
+#[derive(Debug, Default, Deserialize, Validate)]
+#[validate(schema(function = "validate_ranges"))]
+pub struct TransactionsQuery {
+    /// Booked at or after.
+    pub from: Option<DateTime<Utc>>,
+    /// Booked before.
+    pub to: Option<DateTime<Utc>>,
+    #[validate(length(min = 1))]
+    pub account_id: Option<String>,
+    #[serde(rename = "type")]
+    #[validate(custom = "validate_transaction_type")]
+    pub transaction_type: Option<String>,
+    #[validate(length(min = 1))]
+    pub category: Option<String>,
+    pub min_amount: Option<BigDecimal>,
+    pub max_amount: Option<BigDecimal>,
+    /// Text searched in the description.
+    #[validate(length(min = 2, max = 100))]
+    pub q: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionsSort {
+    #[default]
+    BookedAtDesc,
+    BookedAtAsc,
+    AmountDesc,
+    AmountAsc,
+}
+
+/// Pagination of the transactions listings.
+#[derive(Debug, Default, Deserialize, Validate)]
+pub struct TransactionsPage {
+    #[validate(range(min = 1, max = 500))]
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+    #[serde(default)]
+    pub sort: TransactionsSort,
+}
+
+/// Position of the last transaction of a page. Ties on the sort column are
+/// broken by id so the order is stable.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TransactionsCursor {
+    pub sort: TransactionsSort,
+    pub booked_at: Option<DateTime<Utc>>,
+    pub amount: Option<BigDecimal>,
+    pub id: i64,
+}
+
+impl TransactionsCursor {
+    pub fn after(transaction: &Transaction, sort: TransactionsSort) -> Self {
+        let (booked_at, amount) = match sort {
+            TransactionsSort::BookedAtDesc | TransactionsSort::BookedAtAsc => {
+                (Some(transaction.booked_at), None)
+            }
+            TransactionsSort::AmountDesc | TransactionsSort::AmountAsc => {
+                (None, Some(transaction.amount.amount().clone()))
+            }
+        };
+        TransactionsCursor {
+            sort,
+            booked_at,
+            amount,
+            id: transaction.id,
+        }
+    }
+}
+
+fn validate_transaction_type(transaction_type: &str) -> Result<(), ValidationError> {
+    match transaction_type {
+        "CREDIT" | "DEBIT" => Ok(()),
+        _ => Err(ValidationError::new("transaction_type")),
+    }
+}
+
+fn validate_ranges(query: &TransactionsQuery) -> Result<(), ValidationError> {
+    if let (Some(from), Some(to)) = (&query.from, &query.to) {
+        if from > to {
+            return Err(ValidationError::new("date_range"));
+        }
+    }
+    if let (Some(min), Some(max)) = (&query.min_amount, &query.max_amount) {
+        if min > max {
+            return Err(ValidationError::new("amount_range"));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn total(category: &str, amount: &str, currency: &str) -> CategoryTotal {
+        CategoryTotal {
+            transaction_category: Some(category.to_string()),
+            total: Money::new(BigDecimal::from_str(amount).unwrap(), currency).unwrap(),
+        }
+    }
+
+    #[test]
+    fn merges_totals_per_category() {
+        let totals = CategoryTotal::by_category(vec![
+            total("PURCHASE", "-3.50", "EUR"),
+            total("ATM", "-50", "EUR"),
+            total("PURCHASE", "-42.15", "EUR"),
+        ])
+        .unwrap();
+        assert_eq!(totals.len(), 2);
+        assert_eq!(totals[0].total, total("PURCHASE", "-45.65", "EUR").total);
+        assert_eq!(totals[1].total, total("ATM", "-50", "EUR").total);
+    }
+
+    #[test]
+    fn rejects_category_in_several_currencies() {
+        let totals = CategoryTotal::by_category(vec![
+            total("PURCHASE", "-3.50", "EUR"),
+            total("PURCHASE", "-10", "USD"),
+        ]);
+        assert_eq!(
+            totals.unwrap_err(),
+            MoneyError::CurrencyMismatch("EUR".to_string(), "USD".to_string())
+        );
+    }
 }
@@ -1,3 +1,4 @@
+use super::role::Role;
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
@@ -13,32 +14,74 @@ pub struct User {
     pub full_name: Option<String>,
     #[serde(skip_serializing)]
     pub active: bool,
+    #[serde(skip_serializing)]
     pub code: Option<String>,
-    //#[serde(skip_serializing)]
-    pub access_token: Option<String>,
+    pub role: String,
+    pub email_verified_at: Option<NaiveDateTime>,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
 }
 
+impl User {
+    /// The user's role, falling back to the least privileged one.
+    pub fn role(&self) -> Role {
+        self.role.parse().unwrap_or_default()
+    }
+}
+
 #[derive(Debug, Deserialize, Validate)]
 pub struct NewUser {
     #[validate(length(min = 3))]
     pub username: String,
-    #[validate(length(min = 3))]
     pub password: String,
     #[validate(email)]
     pub email: String,
     
 }
 
+/// Changes to the profile of the authenticated user, fields left out are kept.
 #[derive(Debug, Deserialize, Validate)]
-pub struct UpdateCode {
-    pub code: String,
+pub struct UpdateProfile {
+    #[validate(length(min = 3))]
+    pub username: Option<String>,
+    /// A new address has to be verified again.
+    #[validate(email)]
+    pub email: Option<String>,
+    #[validate(length(max = 200))]
+    pub full_name: Option<String>,
 }
 
-#[derive(Debug, sqlx::FromRow, Deserialize, Validate)]
-pub struct GetToken {
-    pub access_token: String,
+#[derive(Debug, Deserialize, Validate)]
+pub struct ForgotPassword {
+    #[validate(email)]
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetPassword {
+    pub token: String,
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePassword {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyEmail {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateRole {
+    pub role: Role,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdateCode {
+    pub code: String,
 }
 
 #[derive(Debug, Deserialize, Validate)]
@@ -0,0 +1,368 @@
+use super::{ProviderError, ProviderResult};
+use chrono::{DateTime, Utc};
+use color_eyre::Result;
+use prometheus::{IntGaugeVec, Opts, Registry};
+use rand::Rng;
+use reqwest::{header::RETRY_AFTER, Method, RequestBuilder, Response, StatusCode};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, instrument, warn};
+
+/// Longest `Retry-After` the client waits for, the request fails instead of
+/// holding the caller any longer.
+const MAX_RETRY_DELAY_SECONDS: u64 = 30;
+
+/// Values of the `provider_circuit_state` gauge.
+const CLOSED: i64 = 0;
+const HALF_OPEN: i64 = 1;
+const OPEN: i64 = 2;
+
+#[derive(Debug, Clone)]
+pub struct ClientSettings {
+    pub connect_timeout: Duration,
+    /// Whole request, including reading the response.
+    pub timeout: Duration,
+    /// Retries of a failed GET, other methods are never retried.
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled on each of the next ones.
+    pub backoff: Duration,
+    /// Consecutive failures that open the circuit of a provider.
+    pub breaker_failures: u32,
+    /// How long an open circuit rejects requests before letting one through.
+    pub breaker_cooldown: Duration,
+}
+
+/// HTTP client shared by the providers.
+///
+/// GETs failing with a transport error, a 5xx or a 429 are retried with
+/// exponential backoff, or after the `Retry-After` the provider asked for.
+/// Each provider has a circuit breaker: once it fails `breaker_failures`
+/// times in a row its requests are rejected without being sent, until the
+/// cooldown is over and a single request is let through to probe it.
+#[derive(Clone)]
+pub struct ProviderClient {
+    client: reqwest::Client,
+    settings: ClientSettings,
+    breakers: Arc<Breakers>,
+}
+
+impl ProviderClient {
+    pub fn new(settings: ClientSettings) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .connect_timeout(settings.connect_timeout)
+            .timeout(settings.timeout)
+            .build()?;
+        Ok(ProviderClient {
+            client,
+            breakers: Arc::new(Breakers::new(
+                settings.breaker_failures,
+                settings.breaker_cooldown,
+            )?),
+            settings,
+        })
+    }
+
+    /// Registers the circuit breaker gauges, served on `/metrics`.
+    pub fn register(&self, registry: &Registry) -> Result<()> {
+        registry.register(Box::new(self.breakers.state.clone()))?;
+        registry.register(Box::new(self.breakers.failures.clone()))?;
+        Ok(())
+    }
+
+    pub fn get(&self, url: &str) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub fn post(&self, url: &str) -> RequestBuilder {
+        self.client.post(url)
+    }
+
+    pub fn delete(&self, url: &str) -> RequestBuilder {
+        self.client.delete(url)
+    }
+
+    /// Sends a request to `provider` through its circuit breaker. Error
+    /// statuses are returned as responses once retries are exhausted.
+    #[instrument(skip(self, request))]
+    pub async fn send(
+        &self,
+        provider: &'static str,
+        request: RequestBuilder,
+    ) -> ProviderResult<Response> {
+        let mut request = request.build()?;
+        let idempotent = request.method() == Method::GET;
+        let mut attempt = 0;
+
+        loop {
+            self.breakers.acquire(provider, Instant::now())?;
+
+            let retry = if idempotent && attempt < self.settings.max_retries {
+                request.try_clone()
+            } else {
+                None
+            };
+            let result = self.client.execute(request).await;
+
+            let (failed, asked_delay) = match &result {
+                Ok(res) => (
+                    res.status().is_server_error(),
+                    res.headers()
+                        .get(RETRY_AFTER)
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(|value| retry_after(value, Utc::now())),
+                ),
+                Err(_) => (true, None),
+            };
+            if failed {
+                self.breakers.failure(provider, Instant::now());
+            } else {
+                self.breakers.success(provider);
+            }
+
+            let transient = match &result {
+                Ok(res) => failed || res.status() == StatusCode::TOO_MANY_REQUESTS,
+                Err(_) => true,
+            };
+            let next = match retry {
+                Some(next) if transient => next,
+                _ => return result.map_err(Into::into),
+            };
+            let delay = asked_delay.unwrap_or_else(|| self.backoff(attempt));
+            if delay > Duration::from_secs(MAX_RETRY_DELAY_SECONDS) {
+                return result.map_err(Into::into);
+            }
+
+            debug!(
+                "Retrying {} request in {}ms after attempt {}",
+                provider,
+                delay.as_millis(),
+                attempt + 1
+            );
+            tokio::time::delay_for(delay).await;
+            request = next;
+            attempt += 1;
+        }
+    }
+
+    /// Exponential backoff with some jitter, so requests that failed together
+    /// are not retried together.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self.settings.backoff * 2u32.pow(attempt);
+        let jitter = rand::thread_rng().gen_range(0, delay.as_millis() as u64 / 4 + 1);
+        delay + Duration::from_millis(jitter)
+    }
+}
+
+/// Delay asked by the provider in a `Retry-After` value, either in seconds or
+/// as an HTTP date.
+fn retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value)
+        .ok()?
+        .with_timezone(&Utc);
+    Some((date - now).to_std().unwrap_or_default())
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Closed,
+    Open {
+        until: Instant,
+    },
+    /// A probe request is in flight, others are rejected until `until`, after
+    /// which another probe is let through in case the first one was dropped.
+    HalfOpen {
+        until: Instant,
+    },
+}
+
+#[derive(Debug)]
+struct Breaker {
+    state: State,
+    failures: u32,
+}
+
+struct Breakers {
+    threshold: u32,
+    cooldown: Duration,
+    breakers: Mutex<HashMap<&'static str, Breaker>>,
+    state: IntGaugeVec,
+    failures: IntGaugeVec,
+}
+
+impl Breakers {
+    fn new(threshold: u32, cooldown: Duration) -> Result<Self> {
+        let state = IntGaugeVec::new(
+            Opts::new(
+                "provider_circuit_state",
+                "Circuit breaker state per provider: 0 closed, 1 half-open, 2 open.",
+            )
+            .namespace("api"),
+            &["provider"],
+        )?;
+        let failures = IntGaugeVec::new(
+            Opts::new(
+                "provider_circuit_failures",
+                "Consecutive failed requests per provider.",
+            )
+            .namespace("api"),
+            &["provider"],
+        )?;
+        Ok(Breakers {
+            threshold: threshold.max(1),
+            cooldown,
+            breakers: Mutex::new(HashMap::new()),
+            state,
+            failures,
+        })
+    }
+
+    /// Lets a request through, or rejects it while the circuit is open.
+    fn acquire(&self, provider: &'static str, now: Instant) -> ProviderResult<()> {
+        self.update(provider, |breaker| match breaker.state {
+            State::Closed => Ok(()),
+            State::Open { until } | State::HalfOpen { until } if now >= until => {
+                breaker.state = State::HalfOpen {
+                    until: now + self.cooldown,
+                };
+                Ok(())
+            }
+            _ => Err(ProviderError::CircuitOpen),
+        })
+    }
+
+    fn success(&self, provider: &'static str) {
+        self.update(provider, |breaker| {
+            if let State::HalfOpen { .. } = breaker.state {
+                debug!("Closing {} circuit", provider);
+            }
+            breaker.state = State::Closed;
+            breaker.failures = 0;
+        })
+    }
+
+    fn failure(&self, provider: &'static str, now: Instant) {
+        self.update(provider, |breaker| {
+            breaker.failures += 1;
+            let open = match breaker.state {
+                State::HalfOpen { .. } => true,
+                State::Closed => breaker.failures >= self.threshold,
+                State::Open { .. } => false,
+            };
+            if open {
+                warn!(
+                    "Opening {} circuit for {}s after {} failures",
+                    provider,
+                    self.cooldown.as_secs(),
+                    breaker.failures
+                );
+                breaker.state = State::Open {
+                    until: now + self.cooldown,
+                };
+            }
+        })
+    }
+
+    fn update<T>(&self, provider: &'static str, f: impl FnOnce(&mut Breaker) -> T) -> T {
+        let mut breakers = self.breakers.lock().unwrap_or_else(|err| err.into_inner());
+        let breaker = breakers.entry(provider).or_insert(Breaker {
+            state: State::Closed,
+            failures: 0,
+        });
+        let result = f(breaker);
+
+        let state = match breaker.state {
+            State::Closed => CLOSED,
+            State::HalfOpen { .. } => HALF_OPEN,
+            State::Open { .. } => OPEN,
+        };
+        self.state.with_label_values(&[provider]).set(state);
+        self.failures
+            .with_label_values(&[provider])
+            .set(breaker.failures as i64);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    const PROVIDER: &str = "test";
+
+    fn state(breakers: &Breakers) -> i64 {
+        breakers.state.with_label_values(&[PROVIDER]).get()
+    }
+
+    #[test]
+    fn breaker_opens_probes_and_closes() {
+        let cooldown = Duration::from_secs(10);
+        let breakers = Breakers::new(2, cooldown).unwrap();
+        let start = Instant::now();
+
+        breakers.acquire(PROVIDER, start).unwrap();
+        breakers.failure(PROVIDER, start);
+        assert_eq!(state(&breakers), CLOSED);
+        breakers.acquire(PROVIDER, start).unwrap();
+        breakers.failure(PROVIDER, start);
+        assert_eq!(state(&breakers), OPEN);
+
+        let cooling = start + cooldown / 2;
+        assert!(matches!(
+            breakers.acquire(PROVIDER, cooling),
+            Err(ProviderError::CircuitOpen)
+        ));
+
+        // Only one probe is let through once the cooldown is over
+        let probe = start + cooldown;
+        breakers.acquire(PROVIDER, probe).unwrap();
+        assert_eq!(state(&breakers), HALF_OPEN);
+        assert!(breakers.acquire(PROVIDER, probe).is_err());
+
+        breakers.success(PROVIDER);
+        assert_eq!(state(&breakers), CLOSED);
+        breakers.acquire(PROVIDER, probe).unwrap();
+    }
+
+    #[test]
+    fn failed_probe_reopens_breaker() {
+        let cooldown = Duration::from_secs(10);
+        let breakers = Breakers::new(1, cooldown).unwrap();
+        let start = Instant::now();
+
+        breakers.failure(PROVIDER, start);
+        let probe = start + cooldown;
+        breakers.acquire(PROVIDER, probe).unwrap();
+        breakers.failure(PROVIDER, probe);
+        assert_eq!(state(&breakers), OPEN);
+        assert!(breakers.acquire(PROVIDER, probe + cooldown / 2).is_err());
+        breakers.acquire(PROVIDER, probe + cooldown).unwrap();
+    }
+
+    #[test]
+    fn parses_retry_after_seconds() {
+        let now = Utc::now();
+        assert_eq!(retry_after("120", now), Some(Duration::from_secs(120)));
+        assert_eq!(retry_after(" 0 ", now), Some(Duration::from_secs(0)));
+        assert_eq!(retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn parses_retry_after_http_date() {
+        let now = Utc.with_ymd_and_hms(2020, 12, 4, 9, 0, 0).unwrap();
+        assert_eq!(
+            retry_after("Fri, 04 Dec 2020 09:01:30 GMT", now),
+            Some(Duration::from_secs(90))
+        );
+        // A date already past means retrying right away
+        assert_eq!(
+            retry_after("Fri, 04 Dec 2020 08:59:00 GMT", now),
+            Some(Duration::from_secs(0))
+        );
+    }
+}
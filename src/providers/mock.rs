@@ -0,0 +1,376 @@
+use super::{BankingProvider, ProviderError, ProviderResult, MOCK};
+use crate::{
+    config::params::Params,
+    models::{
+        account::{Account, Balance},
+        card::Card,
+        payment::{DirectDebit, StandingOrder},
+        provider::Token,
+        trans::TransactionsAccount,
+    },
+};
+use async_trait::async_trait;
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Duration, Utc};
+use std::str::FromStr;
+
+const MOCK_CODE: &str = "mock-code";
+const MOCK_ACCESS_TOKEN: &str = "mock-access-token";
+const MOCK_REFRESH_TOKEN: &str = "mock-refresh-token";
+const MOCK_SCOPES: &str =
+    "info accounts balance cards transactions direct_debits standing_orders offline_access";
+
+/// In-process provider returning fixed data, for local development and tests
+/// without network access.
+///
+/// Its consent URL points straight back at the callback with a valid code, and
+/// transactions are dated relative to the current day so date filters keep
+/// returning data.
+pub struct MockProvider {
+    params: Params,
+}
+
+impl MockProvider {
+    pub fn new(params: Params) -> Self {
+        MockProvider { params }
+    }
+
+    fn token(&self) -> Token {
+        Token {
+            access_token: MOCK_ACCESS_TOKEN.to_string(),
+            refresh_token: Some(MOCK_REFRESH_TOKEN.to_string()),
+            expires_in: 3600,
+            scope: Some(MOCK_SCOPES.to_string()),
+        }
+    }
+}
+
+fn check_token(access_token: &str) -> ProviderResult<()> {
+    if access_token == MOCK_ACCESS_TOKEN {
+        Ok(())
+    } else {
+        Err(ProviderError::Unauthorized)
+    }
+}
+
+fn mock_accounts() -> Vec<Account> {
+    vec![
+        Account {
+            account_id: "mock-current-account".to_string(),
+            account_type: Some("TRANSACTION".to_string()),
+            display_name: Some("Mock Current Account".to_string()),
+            currency: Some("GBP".to_string()),
+        },
+        Account {
+            account_id: "mock-savings-account".to_string(),
+            account_type: Some("SAVINGS".to_string()),
+            display_name: Some("Mock Savings Account".to_string()),
+            currency: Some("GBP".to_string()),
+        },
+    ]
+}
+
+fn mock_cards() -> Vec<Card> {
+    vec![Card {
+        account_id: "mock-credit-card".to_string(),
+        card_network: Some("VISA".to_string()),
+        card_type: Some("CREDIT".to_string()),
+        currency: Some("GBP".to_string()),
+        display_name: Some("Mock Credit Card".to_string()),
+        partial_card_number: Some("4242".to_string()),
+        name_on_card: Some("MOCK USER".to_string()),
+        valid_from: None,
+        valid_to: None,
+    }]
+}
+
+fn today_at(hour: u32) -> DateTime<Utc> {
+    let today = Utc::now().date_naive();
+    today.and_hms_opt(hour, 0, 0).expect("Valid hour").and_utc()
+}
+
+/// Transactions booked the given number of days ago, as
+/// `(days_ago, description, type, category, amount)`.
+fn mock_transactions(
+    account_id: &str,
+    fixtures: &[(i64, &str, &str, &str, &str)],
+    from: Option<DateTime<Utc>>,
+) -> Vec<TransactionsAccount> {
+    let today = today_at(12);
+    fixtures
+        .iter()
+        .enumerate()
+        .filter(|(_, (days_ago, ..))| match from {
+            Some(from) => today - Duration::days(*days_ago) > from,
+            None => true,
+        })
+        .map(
+            |(index, (days_ago, description, kind, category, value))| TransactionsAccount {
+                timestamp: (today - Duration::days(*days_ago)).to_rfc3339(),
+                description: description.to_string(),
+                transaction_type: kind.to_string(),
+                transaction_category: category.to_string(),
+                amount: amount(value),
+                currency: "GBP".to_string(),
+                transaction_id: format!("{}-{}", account_id, index),
+                account_id: Some(account_id.to_string()),
+            },
+        )
+        .collect()
+}
+
+fn amount(value: &str) -> BigDecimal {
+    BigDecimal::from_str(value).expect("Mock amount")
+}
+
+fn not_found(account_id: &str) -> ProviderError {
+    ProviderError::Response {
+        status: 404,
+        body: format!(
+            r#"{{"error":"account_not_found","account_id":"{}"}}"#,
+            account_id
+        ),
+    }
+}
+
+#[async_trait]
+impl BankingProvider for MockProvider {
+    fn name(&self) -> &'static str {
+        MOCK
+    }
+
+    fn auth_url(&self, state: &str) -> String {
+        format!(
+            "{}?code={}&state={}",
+            self.params.redirect_uri, MOCK_CODE, state
+        )
+    }
+
+    async fn exchange_code(&self, code: &str) -> ProviderResult<Token> {
+        if code == MOCK_CODE {
+            Ok(self.token())
+        } else {
+            Err(ProviderError::Response {
+                status: 400,
+                body: r#"{"error":"invalid_grant"}"#.to_string(),
+            })
+        }
+    }
+
+    async fn refresh_token(&self, refresh_token: &str) -> ProviderResult<Token> {
+        if refresh_token == MOCK_REFRESH_TOKEN {
+            Ok(self.token())
+        } else {
+            Err(ProviderError::Response {
+                status: 400,
+                body: r#"{"error":"invalid_grant"}"#.to_string(),
+            })
+        }
+    }
+
+    async fn revoke(&self, access_token: &str) -> ProviderResult<()> {
+        check_token(access_token)
+    }
+
+    async fn accounts(&self, access_token: &str) -> ProviderResult<Vec<Account>> {
+        check_token(access_token)?;
+        Ok(mock_accounts())
+    }
+
+    async fn balance(&self, access_token: &str, account_id: &str) -> ProviderResult<Balance> {
+        check_token(access_token)?;
+        let current = match account_id {
+            "mock-current-account" => amount("1250.40"),
+            "mock-savings-account" => amount("8000.00"),
+            _ => return Err(not_found(account_id)),
+        };
+        Ok(Balance {
+            currency: "GBP".to_string(),
+            available: Some(current.clone()),
+            current,
+            overdraft: None,
+            update_timestamp: Some(today_at(0).to_rfc3339()),
+        })
+    }
+
+    async fn transactions(
+        &self,
+        access_token: &str,
+        account_id: &str,
+        from: Option<DateTime<Utc>>,
+    ) -> ProviderResult<Vec<TransactionsAccount>> {
+        check_token(access_token)?;
+        if !mock_accounts()
+            .iter()
+            .any(|account| account.account_id == account_id)
+        {
+            return Err(not_found(account_id));
+        }
+
+        Ok(mock_transactions(
+            account_id,
+            &[
+                (0, "Coffee Shop", "DEBIT", "PURCHASE", "-3.50"),
+                (2, "Supermarket", "DEBIT", "PURCHASE", "-42.15"),
+                (5, "Salary", "CREDIT", "CREDIT", "2500.00"),
+                (12, "Electricity", "DEBIT", "DIRECT_DEBIT", "-60.00"),
+                (20, "Cash Machine", "DEBIT", "ATM", "-50.00"),
+                (40, "Refund", "CREDIT", "CREDIT", "19.99"),
+            ],
+            from,
+        ))
+    }
+
+    async fn cards(&self, access_token: &str) -> ProviderResult<Vec<Card>> {
+        check_token(access_token)?;
+        Ok(mock_cards())
+    }
+
+    async fn card_transactions(
+        &self,
+        access_token: &str,
+        card_id: &str,
+        from: Option<DateTime<Utc>>,
+    ) -> ProviderResult<Vec<TransactionsAccount>> {
+        check_token(access_token)?;
+        if !mock_cards().iter().any(|card| card.account_id == card_id) {
+            return Err(not_found(card_id));
+        }
+
+        Ok(mock_transactions(
+            card_id,
+            &[
+                (1, "Online Bookshop", "DEBIT", "PURCHASE", "-18.99"),
+                (4, "Restaurant", "DEBIT", "PURCHASE", "-64.20"),
+                (9, "Card Repayment", "CREDIT", "CREDIT", "300.00"),
+                (15, "Airline", "DEBIT", "PURCHASE", "-212.00"),
+            ],
+            from,
+        ))
+    }
+
+    async fn direct_debits(
+        &self,
+        access_token: &str,
+        account_id: &str,
+    ) -> ProviderResult<Vec<DirectDebit>> {
+        check_token(access_token)?;
+        match account_id {
+            "mock-current-account" => {
+                let today = today_at(0);
+                Ok(vec![DirectDebit {
+                    direct_debit_id: "mock-direct-debit-electricity".to_string(),
+                    timestamp: Some((today - Duration::days(400)).to_rfc3339()),
+                    name: "Electricity".to_string(),
+                    status: Some("Active".to_string()),
+                    previous_payment_timestamp: Some((today - Duration::days(12)).to_rfc3339()),
+                    previous_payment_amount: Some(amount("60.00")),
+                    currency: Some("GBP".to_string()),
+                }])
+            }
+            "mock-savings-account" => Ok(Vec::new()),
+            _ => Err(not_found(account_id)),
+        }
+    }
+
+    async fn standing_orders(
+        &self,
+        access_token: &str,
+        account_id: &str,
+    ) -> ProviderResult<Vec<StandingOrder>> {
+        check_token(access_token)?;
+        match account_id {
+            "mock-current-account" => {
+                let today = today_at(0);
+                Ok(vec![StandingOrder {
+                    frequency: Some("EvryWorkgDay".to_string()),
+                    status: Some("Active".to_string()),
+                    timestamp: Some(today.to_rfc3339()),
+                    currency: "GBP".to_string(),
+                    next_payment_date: Some((today + Duration::days(3)).to_rfc3339()),
+                    next_payment_amount: Some(amount("250.00")),
+                    first_payment_date: Some((today - Duration::days(180)).to_rfc3339()),
+                    first_payment_amount: Some(amount("250.00")),
+                    final_payment_date: None,
+                    final_payment_amount: None,
+                    reference: Some("Savings".to_string()),
+                    payee: Some("Mock Savings Account".to_string()),
+                }])
+            }
+            "mock-savings-account" => Ok(Vec::new()),
+            _ => Err(not_found(account_id)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::Url;
+    use std::sync::Arc;
+
+    fn provider() -> MockProvider {
+        let param = |value: &str| Arc::new(value.to_string());
+        MockProvider::new(Params {
+            client_id: param("client"),
+            client_secret: param("secret"),
+            token_uri: param("http://localhost/token"),
+            redirect_uri: param("http://localhost:3000/callback"),
+            auth_uri: param("http://localhost/auth"),
+            api_uri: param("http://localhost/api"),
+        })
+    }
+
+    #[actix_rt::test]
+    async fn links_and_lists_transactions() {
+        let provider = provider();
+        let url = Url::parse(&provider.auth_url("some-state")).unwrap();
+        let query: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+        assert_eq!(url.path(), "/callback");
+        assert!(query.contains(&("state".to_string(), "some-state".to_string())));
+        let code = query
+            .iter()
+            .find(|(name, _)| name == "code")
+            .map(|(_, code)| code.as_str())
+            .unwrap();
+
+        let token = provider.exchange_code(code).await.unwrap();
+        let accounts = provider.accounts(&token.access_token).await.unwrap();
+        assert_eq!(accounts.len(), 2);
+
+        let account_id = &accounts[0].account_id;
+        let all = provider
+            .transactions(&token.access_token, account_id, None)
+            .await
+            .unwrap();
+        assert_eq!(all.len(), 6);
+
+        let from = Utc::now() - Duration::days(7);
+        let recent = provider
+            .transactions(&token.access_token, account_id, Some(from))
+            .await
+            .unwrap();
+        let descriptions: Vec<&str> = recent
+            .iter()
+            .map(|transaction| transaction.description.as_str())
+            .collect();
+        assert_eq!(descriptions, vec!["Coffee Shop", "Supermarket", "Salary"]);
+    }
+
+    #[actix_rt::test]
+    async fn rejects_unknown_token() {
+        let result = provider().accounts("not-a-token").await;
+        assert!(matches!(result, Err(ProviderError::Unauthorized)));
+    }
+
+    #[actix_rt::test]
+    async fn unknown_account_is_not_found() {
+        let result = provider()
+            .transactions(MOCK_ACCESS_TOKEN, "unknown-account", None)
+            .await;
+        assert!(matches!(
+            result,
+            Err(ProviderError::Response { status: 404, .. })
+        ));
+    }
+}
@@ -0,0 +1,182 @@
+pub mod client;
+pub mod mock;
+pub mod session;
+pub mod truelayer;
+
+use crate::models::{
+    account::{Account, Balance},
+    card::Card,
+    payment::{DirectDebit, StandingOrder},
+    provider::Token,
+    trans::TransactionsAccount,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::fmt;
+
+pub const MOCK: &str = "mock";
+pub const TRUELAYER: &str = "truelayer";
+
+#[derive(Debug)]
+pub enum ProviderError {
+    /// The provider rejected the access token, it may be refreshed and retried.
+    Unauthorized,
+    /// Any other non successful response, with the body the provider sent.
+    Response {
+        status: u16,
+        body: String,
+    },
+    /// The provider did not answer in time.
+    Timeout,
+    /// The provider could not be reached.
+    Unreachable(eyre::Report),
+    /// The provider answered with a body that could not be parsed.
+    InvalidPayload(eyre::Report),
+    /// The provider kept failing, requests are not sent to it for a while.
+    CircuitOpen,
+    /// There are no usable credentials, the user has to link their account again.
+    RelinkRequired,
+    /// The consent the user granted lapsed, they have to grant a new one.
+    ConsentExpired,
+    Other(eyre::Report),
+}
+
+impl ProviderError {
+    /// Whether the provider is failing rather than rejecting the request, so
+    /// trying again later may succeed.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ProviderError::Timeout | ProviderError::Unreachable(_) | ProviderError::CircuitOpen => {
+                true
+            }
+            ProviderError::Response { status, .. } => *status >= 500 || *status == 429,
+            _ => false,
+        }
+    }
+
+    /// Whether the provider definitively refused a grant: a 401, or the OAuth
+    /// `invalid_grant` error for an expired or revoked refresh token.
+    pub fn is_rejected_grant(&self) -> bool {
+        match self {
+            ProviderError::Unauthorized => true,
+            ProviderError::Response { status, body } => {
+                *status == 400 && body.contains("invalid_grant")
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the bank doesn't offer the requested data at all.
+    pub fn is_not_supported(&self) -> bool {
+        matches!(self, ProviderError::Response { status: 501, .. })
+    }
+
+    /// A description without the details of the provider response, safe to
+    /// show to users.
+    pub fn summary(&self) -> &'static str {
+        match self {
+            ProviderError::Unauthorized | ProviderError::RelinkRequired => {
+                "Bank account link expired."
+            }
+            ProviderError::ConsentExpired => "Bank consent expired.",
+            ProviderError::Timeout => "The bank did not respond in time.",
+            ProviderError::InvalidPayload(_) => "The bank sent an invalid response.",
+            ProviderError::Other(_) => "Unexpected error.",
+            err if err.is_transient() => "The bank is unavailable.",
+            _ => "The bank rejected the request.",
+        }
+    }
+}
+
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProviderError::Unauthorized => write!(f, "Provider rejected the access token"),
+            ProviderError::Response { status, body } => {
+                write!(f, "Provider responded with {}: {}", status, body)
+            }
+            ProviderError::Timeout => write!(f, "Provider request timed out"),
+            ProviderError::Unreachable(err) => write!(f, "Provider unreachable: {}", err),
+            ProviderError::InvalidPayload(err) => write!(f, "Invalid provider response: {}", err),
+            ProviderError::CircuitOpen => write!(f, "Provider circuit is open"),
+            ProviderError::RelinkRequired => write!(f, "Provider account must be linked again"),
+            ProviderError::ConsentExpired => write!(f, "Provider consent expired"),
+            ProviderError::Other(err) => write!(f, "Provider request failed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ProviderError {}
+
+impl From<reqwest::Error> for ProviderError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            ProviderError::Timeout
+        } else if err.is_decode() {
+            ProviderError::InvalidPayload(err.into())
+        } else if err.is_request() || err.is_body() {
+            ProviderError::Unreachable(err.into())
+        } else {
+            ProviderError::Other(err.into())
+        }
+    }
+}
+
+impl From<eyre::Report> for ProviderError {
+    fn from(err: eyre::Report) -> Self {
+        ProviderError::Other(err)
+    }
+}
+
+pub type ProviderResult<T> = Result<T, ProviderError>;
+
+/// An Open Banking data provider.
+#[async_trait]
+pub trait BankingProvider: Send + Sync {
+    /// Name the provider credentials are stored under.
+    fn name(&self) -> &'static str;
+
+    /// Consent page the user is sent to, carrying the signed `state`.
+    fn auth_url(&self, state: &str) -> String;
+
+    async fn exchange_code(&self, code: &str) -> ProviderResult<Token>;
+
+    async fn refresh_token(&self, refresh_token: &str) -> ProviderResult<Token>;
+
+    /// Revokes the grant behind an access token, and the refresh token with it.
+    async fn revoke(&self, access_token: &str) -> ProviderResult<()>;
+
+    async fn accounts(&self, access_token: &str) -> ProviderResult<Vec<Account>>;
+
+    async fn balance(&self, access_token: &str, account_id: &str) -> ProviderResult<Balance>;
+
+    /// Transactions of an account, only those booked after `from` if given.
+    async fn transactions(
+        &self,
+        access_token: &str,
+        account_id: &str,
+        from: Option<DateTime<Utc>>,
+    ) -> ProviderResult<Vec<TransactionsAccount>>;
+
+    async fn cards(&self, access_token: &str) -> ProviderResult<Vec<Card>>;
+
+    /// Transactions of a card, only those booked after `from` if given.
+    async fn card_transactions(
+        &self,
+        access_token: &str,
+        card_id: &str,
+        from: Option<DateTime<Utc>>,
+    ) -> ProviderResult<Vec<TransactionsAccount>>;
+
+    async fn direct_debits(
+        &self,
+        access_token: &str,
+        account_id: &str,
+    ) -> ProviderResult<Vec<DirectDebit>>;
+
+    async fn standing_orders(
+        &self,
+        access_token: &str,
+        account_id: &str,
+    ) -> ProviderResult<Vec<StandingOrder>>;
+}
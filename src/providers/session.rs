@@ -0,0 +1,645 @@
+use super::{BankingProvider, ProviderError, ProviderResult};
+use crate::{
+    config::crypto::CryptoService,
+    db::{consent::ConsentRepository, provider::ProviderRepository},
+    models::{
+        account::{Account, Balance},
+        card::Card,
+        consent::CONSENT_EXPIRED,
+        payment::{DirectDebit, StandingOrder},
+        provider::{ProviderCredentials, Token},
+        trans::TransactionsAccount,
+    },
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use color_eyre::Result;
+use tracing::{debug, instrument};
+use uuid::Uuid;
+
+/// Provider tokens this close to expiry are refreshed before use.
+const TOKEN_EXPIRY_MARGIN_SECONDS: i64 = 60;
+
+/// Where sessions keep the provider credentials of users, and the consent
+/// they were granted under.
+#[async_trait]
+pub trait CredentialStore: Send + Sync {
+    async fn consent_expired(&self, user_id: Uuid, provider: &str) -> Result<bool>;
+
+    async fn find_credentials(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+    ) -> Result<Option<ProviderCredentials>>;
+
+    async fn save_credentials(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        token: &Token,
+    ) -> Result<ProviderCredentials>;
+
+    async fn delete_credentials(&self, user_id: Uuid, provider: &str) -> Result<()>;
+
+    /// Marks the active consent as expired, returns whether there was one.
+    async fn expire_consent(&self, user_id: Uuid, provider: &str) -> Result<bool>;
+}
+
+/// Credentials stored in the database, encrypted at rest.
+struct StoredCredentials<'a> {
+    repository: &'a ProviderRepository,
+    consents: &'a ConsentRepository,
+    crypto: &'a CryptoService,
+}
+
+#[async_trait]
+impl<'a> CredentialStore for StoredCredentials<'a> {
+    async fn consent_expired(&self, user_id: Uuid, provider: &str) -> Result<bool> {
+        let consent = self.consents.latest(user_id, provider).await?;
+        Ok(matches!(consent, Some(consent) if consent.status == CONSENT_EXPIRED))
+    }
+
+    async fn find_credentials(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+    ) -> Result<Option<ProviderCredentials>> {
+        self.repository
+            .find_credentials(user_id, provider, self.crypto)
+            .await
+    }
+
+    async fn save_credentials(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        token: &Token,
+    ) -> Result<ProviderCredentials> {
+        self.repository
+            .save_credentials(user_id, provider, token, self.crypto)
+            .await
+    }
+
+    async fn delete_credentials(&self, user_id: Uuid, provider: &str) -> Result<()> {
+        self.repository.delete_credentials(user_id, provider).await
+    }
+
+    async fn expire_consent(&self, user_id: Uuid, provider: &str) -> Result<bool> {
+        self.consents.expire(user_id, provider).await
+    }
+}
+
+/// A user's authenticated connection to a provider.
+///
+/// The access token is refreshed when it is about to expire and again when the
+/// provider answers 401, in which case the call is retried once. When the
+/// refresh itself is rejected the stored credentials are dropped and
+/// `ProviderError::RelinkRequired` is returned, or
+/// `ProviderError::ConsentExpired` when it is the consent that lapsed.
+pub struct ProviderSession<'a> {
+    provider: &'a dyn BankingProvider,
+    store: Box<dyn CredentialStore + 'a>,
+    user_id: Uuid,
+    access_token: String,
+}
+
+impl<'a> ProviderSession<'a> {
+    #[instrument(skip(provider, repository, consents, crypto))]
+    pub async fn open(
+        provider: &'a dyn BankingProvider,
+        repository: &'a ProviderRepository,
+        consents: &'a ConsentRepository,
+        crypto: &'a CryptoService,
+        user_id: Uuid,
+    ) -> ProviderResult<ProviderSession<'a>> {
+        let store = StoredCredentials {
+            repository,
+            consents,
+            crypto,
+        };
+        ProviderSession::open_with(provider, Box::new(store), user_id).await
+    }
+
+    async fn open_with(
+        provider: &'a dyn BankingProvider,
+        store: Box<dyn CredentialStore + 'a>,
+        user_id: Uuid,
+    ) -> ProviderResult<ProviderSession<'a>> {
+        if store.consent_expired(user_id, provider.name()).await? {
+            debug!("Provider consent of user {} expired", user_id);
+            store.delete_credentials(user_id, provider.name()).await?;
+            return Err(ProviderError::ConsentExpired);
+        }
+
+        let credentials = store
+            .find_credentials(user_id, provider.name())
+            .await?
+            .ok_or(ProviderError::RelinkRequired)?;
+
+        let mut session = ProviderSession {
+            provider,
+            store,
+            user_id,
+            access_token: credentials.access_token,
+        };
+
+        let refresh_at = Utc::now().naive_utc() + Duration::seconds(TOKEN_EXPIRY_MARGIN_SECONDS);
+        if credentials.expires_at <= refresh_at {
+            session.refresh(credentials.refresh_token).await?;
+        }
+        Ok(session)
+    }
+
+    async fn refresh(&mut self, refresh_token: Option<String>) -> ProviderResult<()> {
+        let refresh_token = refresh_token.ok_or(ProviderError::RelinkRequired)?;
+
+        match self.provider.refresh_token(&refresh_token).await {
+            Ok(token) => {
+                let credentials = self
+                    .store
+                    .save_credentials(self.user_id, self.provider.name(), &token)
+                    .await?;
+                self.access_token = credentials.access_token;
+                Ok(())
+            }
+            // Only a rejected refresh token means the link is gone, other
+            // failures leave the credentials in place to be tried again
+            Err(err) if err.is_rejected_grant() => {
+                debug!("Cannot refresh provider token. {:?}", err);
+                self.store
+                    .delete_credentials(self.user_id, self.provider.name())
+                    .await?;
+                if self
+                    .store
+                    .expire_consent(self.user_id, self.provider.name())
+                    .await?
+                {
+                    Err(ProviderError::ConsentExpired)
+                } else {
+                    Err(ProviderError::RelinkRequired)
+                }
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn refresh_stored(&mut self) -> ProviderResult<()> {
+        let credentials = self
+            .store
+            .find_credentials(self.user_id, self.provider.name())
+            .await?
+            .ok_or(ProviderError::RelinkRequired)?;
+        self.refresh(credentials.refresh_token).await
+    }
+
+    /// Revokes the access granted to us at the provider.
+    #[instrument(skip(self))]
+    pub async fn revoke(&mut self) -> ProviderResult<()> {
+        match self.provider.revoke(&self.access_token).await {
+            Err(ProviderError::Unauthorized) => {
+                self.refresh_stored().await?;
+                self.provider.revoke(&self.access_token).await
+            }
+            result => result,
+        }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn accounts(&mut self) -> ProviderResult<Vec<Account>> {
+        match self.provider.accounts(&self.access_token).await {
+            Err(ProviderError::Unauthorized) => {
+                self.refresh_stored().await?;
+                self.provider.accounts(&self.access_token).await
+            }
+            result => result,
+        }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn balance(&mut self, account_id: &str) -> ProviderResult<Balance> {
+        match self.provider.balance(&self.access_token, account_id).await {
+            Err(ProviderError::Unauthorized) => {
+                self.refresh_stored().await?;
+                self.provider.balance(&self.access_token, account_id).await
+            }
+            result => result,
+        }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn transactions(
+        &mut self,
+        account_id: &str,
+        from: Option<DateTime<Utc>>,
+    ) -> ProviderResult<Vec<TransactionsAccount>> {
+        match self
+            .provider
+            .transactions(&self.access_token, account_id, from)
+            .await
+        {
+            Err(ProviderError::Unauthorized) => {
+                self.refresh_stored().await?;
+                self.provider
+                    .transactions(&self.access_token, account_id, from)
+                    .await
+            }
+            result => result,
+        }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn cards(&mut self) -> ProviderResult<Vec<Card>> {
+        match self.provider.cards(&self.access_token).await {
+            Err(ProviderError::Unauthorized) => {
+                self.refresh_stored().await?;
+                self.provider.cards(&self.access_token).await
+            }
+            result => result,
+        }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn card_transactions(
+        &mut self,
+        card_id: &str,
+        from: Option<DateTime<Utc>>,
+    ) -> ProviderResult<Vec<TransactionsAccount>> {
+        match self
+            .provider
+            .card_transactions(&self.access_token, card_id, from)
+            .await
+        {
+            Err(ProviderError::Unauthorized) => {
+                self.refresh_stored().await?;
+                self.provider
+                    .card_transactions(&self.access_token, card_id, from)
+                    .await
+            }
+            result => result,
+        }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn direct_debits(&mut self, account_id: &str) -> ProviderResult<Vec<DirectDebit>> {
+        match self
+            .provider
+            .direct_debits(&self.access_token, account_id)
+            .await
+        {
+            Err(ProviderError::Unauthorized) => {
+                self.refresh_stored().await?;
+                self.provider
+                    .direct_debits(&self.access_token, account_id)
+                    .await
+            }
+            result => result,
+        }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn standing_orders(
+        &mut self,
+        account_id: &str,
+    ) -> ProviderResult<Vec<StandingOrder>> {
+        match self
+            .provider
+            .standing_orders(&self.access_token, account_id)
+            .await
+        {
+            Err(ProviderError::Unauthorized) => {
+                self.refresh_stored().await?;
+                self.provider
+                    .standing_orders(&self.access_token, account_id)
+                    .await
+            }
+            result => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    };
+
+    const FRESH_TOKEN: &str = "fresh-access-token";
+
+    fn credentials(access_token: &str, expires_in: Duration) -> ProviderCredentials {
+        ProviderCredentials {
+            id: 1,
+            access_token: access_token.to_string(),
+            refresh_token: Some("refresh-token".to_string()),
+            expires_at: (Utc::now() + expires_in).naive_utc(),
+        }
+    }
+
+    #[derive(Default)]
+    struct Stored {
+        credentials: Option<ProviderCredentials>,
+        consent_expired: bool,
+        consent_active: bool,
+        saved: usize,
+    }
+
+    #[derive(Clone, Default)]
+    struct FakeStore(Arc<Mutex<Stored>>);
+
+    impl FakeStore {
+        fn with(credentials: ProviderCredentials) -> Self {
+            let store = FakeStore::default();
+            store.0.lock().unwrap().credentials = Some(credentials);
+            store
+        }
+    }
+
+    #[async_trait]
+    impl CredentialStore for FakeStore {
+        async fn consent_expired(&self, _: Uuid, _: &str) -> Result<bool> {
+            Ok(self.0.lock().unwrap().consent_expired)
+        }
+
+        async fn find_credentials(&self, _: Uuid, _: &str) -> Result<Option<ProviderCredentials>> {
+            let stored = self.0.lock().unwrap();
+            Ok(stored
+                .credentials
+                .as_ref()
+                .map(|stored| ProviderCredentials {
+                    id: stored.id,
+                    access_token: stored.access_token.clone(),
+                    refresh_token: stored.refresh_token.clone(),
+                    expires_at: stored.expires_at,
+                }))
+        }
+
+        async fn save_credentials(
+            &self,
+            _: Uuid,
+            _: &str,
+            token: &Token,
+        ) -> Result<ProviderCredentials> {
+            let mut stored = self.0.lock().unwrap();
+            stored.saved += 1;
+            stored.credentials = Some(credentials(
+                &token.access_token,
+                Duration::seconds(token.expires_in),
+            ));
+            Ok(credentials(
+                &token.access_token,
+                Duration::seconds(token.expires_in),
+            ))
+        }
+
+        async fn delete_credentials(&self, _: Uuid, _: &str) -> Result<()> {
+            self.0.lock().unwrap().credentials = None;
+            Ok(())
+        }
+
+        async fn expire_consent(&self, _: Uuid, _: &str) -> Result<bool> {
+            let mut stored = self.0.lock().unwrap();
+            let expired = stored.consent_active;
+            stored.consent_active = false;
+            Ok(expired)
+        }
+    }
+
+    /// Accepts a single access token, and hands it out on refresh unless told
+    /// to fail.
+    #[derive(Default)]
+    struct FakeProvider {
+        accepted_token: Option<&'static str>,
+        refresh_error: Mutex<Option<ProviderError>>,
+        refreshes: AtomicUsize,
+        calls: AtomicUsize,
+    }
+
+    impl FakeProvider {
+        fn accepting(token: &'static str) -> Self {
+            FakeProvider {
+                accepted_token: Some(token),
+                ..FakeProvider::default()
+            }
+        }
+
+        fn failing_refresh(err: ProviderError) -> Self {
+            FakeProvider {
+                accepted_token: Some(FRESH_TOKEN),
+                refresh_error: Mutex::new(Some(err)),
+                ..FakeProvider::default()
+            }
+        }
+
+        fn refreshes(&self) -> usize {
+            self.refreshes.load(Ordering::SeqCst)
+        }
+
+        fn calls(&self) -> usize {
+            self.calls.load(Ordering::SeqCst)
+        }
+    }
+
+    #[async_trait]
+    impl BankingProvider for FakeProvider {
+        fn name(&self) -> &'static str {
+            "fake"
+        }
+
+        fn auth_url(&self, _: &str) -> String {
+            unimplemented!()
+        }
+
+        async fn exchange_code(&self, _: &str) -> ProviderResult<Token> {
+            unimplemented!()
+        }
+
+        async fn refresh_token(&self, _: &str) -> ProviderResult<Token> {
+            self.refreshes.fetch_add(1, Ordering::SeqCst);
+            if let Some(err) = self.refresh_error.lock().unwrap().take() {
+                return Err(err);
+            }
+            Ok(Token {
+                access_token: FRESH_TOKEN.to_string(),
+                refresh_token: Some("rotated-refresh-token".to_string()),
+                expires_in: 3600,
+                scope: None,
+            })
+        }
+
+        async fn revoke(&self, _: &str) -> ProviderResult<()> {
+            unimplemented!()
+        }
+
+        async fn accounts(&self, access_token: &str) -> ProviderResult<Vec<Account>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if self.accepted_token == Some(access_token) {
+                Ok(Vec::new())
+            } else {
+                Err(ProviderError::Unauthorized)
+            }
+        }
+
+        async fn balance(&self, _: &str, _: &str) -> ProviderResult<Balance> {
+            unimplemented!()
+        }
+
+        async fn transactions(
+            &self,
+            _: &str,
+            _: &str,
+            _: Option<DateTime<Utc>>,
+        ) -> ProviderResult<Vec<TransactionsAccount>> {
+            unimplemented!()
+        }
+
+        async fn cards(&self, _: &str) -> ProviderResult<Vec<Card>> {
+            unimplemented!()
+        }
+
+        async fn card_transactions(
+            &self,
+            _: &str,
+            _: &str,
+            _: Option<DateTime<Utc>>,
+        ) -> ProviderResult<Vec<TransactionsAccount>> {
+            unimplemented!()
+        }
+
+        async fn direct_debits(&self, _: &str, _: &str) -> ProviderResult<Vec<DirectDebit>> {
+            unimplemented!()
+        }
+
+        async fn standing_orders(&self, _: &str, _: &str) -> ProviderResult<Vec<StandingOrder>> {
+            unimplemented!()
+        }
+    }
+
+    async fn open<'a>(
+        provider: &'a FakeProvider,
+        store: &FakeStore,
+    ) -> ProviderResult<ProviderSession<'a>> {
+        ProviderSession::open_with(provider, Box::new(store.clone()), Uuid::new_v4()).await
+    }
+
+    #[actix_rt::test]
+    async fn keeps_token_until_close_to_expiry() {
+        let provider = FakeProvider::accepting("current-token");
+        let store = FakeStore::with(credentials("current-token", Duration::minutes(30)));
+
+        let mut session = open(&provider, &store).await.unwrap();
+        assert!(session.accounts().await.is_ok());
+        assert_eq!(provider.refreshes(), 0);
+        assert_eq!(store.0.lock().unwrap().saved, 0);
+    }
+
+    #[actix_rt::test]
+    async fn refreshes_expired_token_on_open() {
+        let provider = FakeProvider::accepting(FRESH_TOKEN);
+        let store = FakeStore::with(credentials("expired-token", Duration::seconds(30)));
+
+        let mut session = open(&provider, &store).await.unwrap();
+        assert_eq!(provider.refreshes(), 1);
+        assert_eq!(store.0.lock().unwrap().saved, 1);
+
+        assert!(session.accounts().await.is_ok());
+        assert_eq!(provider.calls(), 1);
+        assert_eq!(provider.refreshes(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn retries_once_after_unauthorized() {
+        let provider = FakeProvider::accepting(FRESH_TOKEN);
+        let store = FakeStore::with(credentials("revoked-token", Duration::minutes(30)));
+
+        let mut session = open(&provider, &store).await.unwrap();
+        assert!(session.accounts().await.is_ok());
+        assert_eq!(provider.calls(), 2);
+        assert_eq!(provider.refreshes(), 1);
+        assert_eq!(
+            store
+                .0
+                .lock()
+                .unwrap()
+                .credentials
+                .as_ref()
+                .unwrap()
+                .access_token,
+            FRESH_TOKEN
+        );
+    }
+
+    #[actix_rt::test]
+    async fn does_not_retry_a_second_time() {
+        let provider = FakeProvider::default();
+        let store = FakeStore::with(credentials("revoked-token", Duration::minutes(30)));
+
+        let mut session = open(&provider, &store).await.unwrap();
+        assert!(matches!(
+            session.accounts().await,
+            Err(ProviderError::Unauthorized)
+        ));
+        assert_eq!(provider.calls(), 2);
+        assert_eq!(provider.refreshes(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn rejected_refresh_requires_relink() {
+        let provider = FakeProvider::failing_refresh(ProviderError::Response {
+            status: 400,
+            body: r#"{"error":"invalid_grant"}"#.to_string(),
+        });
+        let store = FakeStore::with(credentials("expired-token", Duration::zero()));
+
+        let result = open(&provider, &store).await;
+        assert!(matches!(result, Err(ProviderError::RelinkRequired)));
+        assert!(store.0.lock().unwrap().credentials.is_none());
+    }
+
+    #[actix_rt::test]
+    async fn rejected_refresh_under_active_consent_expires_it() {
+        let provider = FakeProvider::failing_refresh(ProviderError::Unauthorized);
+        let store = FakeStore::with(credentials("expired-token", Duration::zero()));
+        store.0.lock().unwrap().consent_active = true;
+
+        let result = open(&provider, &store).await;
+        assert!(matches!(result, Err(ProviderError::ConsentExpired)));
+        assert!(store.0.lock().unwrap().credentials.is_none());
+    }
+
+    #[actix_rt::test]
+    async fn transient_refresh_failure_keeps_credentials() {
+        let provider = FakeProvider::failing_refresh(ProviderError::Timeout);
+        let store = FakeStore::with(credentials("expired-token", Duration::zero()));
+
+        let result = open(&provider, &store).await;
+        assert!(matches!(result, Err(ProviderError::Timeout)));
+        assert!(store.0.lock().unwrap().credentials.is_some());
+
+        // the next attempt refreshes with the credentials that were kept
+        let mut session = open(&provider, &store).await.unwrap();
+        assert!(session.accounts().await.is_ok());
+        assert_eq!(provider.refreshes(), 2);
+    }
+
+    #[actix_rt::test]
+    async fn expired_consent_drops_credentials() {
+        let provider = FakeProvider::accepting("current-token");
+        let store = FakeStore::with(credentials("current-token", Duration::minutes(30)));
+        store.0.lock().unwrap().consent_expired = true;
+
+        let result = open(&provider, &store).await;
+        assert!(matches!(result, Err(ProviderError::ConsentExpired)));
+        assert!(store.0.lock().unwrap().credentials.is_none());
+        assert_eq!(provider.calls(), 0);
+    }
+
+    #[actix_rt::test]
+    async fn missing_credentials_require_relink() {
+        let provider = FakeProvider::accepting("current-token");
+        let store = FakeStore::default();
+
+        let result = open(&provider, &store).await;
+        assert!(matches!(result, Err(ProviderError::RelinkRequired)));
+    }
+}
@@ -0,0 +1,200 @@
+use super::{client::ProviderClient, BankingProvider, ProviderError, ProviderResult, TRUELAYER};
+use crate::{
+    config::params::Params,
+    models::{
+        account::{Account, AccountsResults, Balance, BalanceResults},
+        card::{Card, CardsResults},
+        payment::{DirectDebit, DirectDebitsResults, StandingOrder, StandingOrdersResults},
+        provider::Token,
+        trans::{TransactionsAccount, TransactionsResults},
+    },
+};
+use async_trait::async_trait;
+use chrono::{DateTime, SecondsFormat, Utc};
+use eyre::eyre;
+use serde::de::DeserializeOwned;
+use tracing::instrument;
+
+pub struct TrueLayer {
+    params: Params,
+    client: ProviderClient,
+}
+
+impl TrueLayer {
+    pub fn new(params: Params, client: ProviderClient) -> Self {
+        TrueLayer { params, client }
+    }
+
+    async fn request_token(&self, form: &[(&str, &str)]) -> ProviderResult<Token> {
+        let request = self.client.post(&self.params.token_uri).form(form);
+        let res = self.client.send(TRUELAYER, request).await?;
+
+        check_status(res).await?.json().await.map_err(Into::into)
+    }
+
+    async fn get<T: DeserializeOwned>(&self, access_token: &str, path: &str) -> ProviderResult<T> {
+        let request = self
+            .client
+            .get(&format!("{}{}", self.params.api_uri, path))
+            .bearer_auth(access_token);
+        let res = self.client.send(TRUELAYER, request).await?;
+
+        check_status(res).await?.json().await.map_err(Into::into)
+    }
+}
+
+/// Adds the range of a transactions request, TrueLayer needs both ends of it.
+fn with_range(path: String, from: Option<DateTime<Utc>>) -> String {
+    match from {
+        Some(from) => format!(
+            "{}?from={}&to={}",
+            path,
+            from.to_rfc3339_opts(SecondsFormat::Secs, true),
+            Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+        ),
+        None => path,
+    }
+}
+
+async fn check_status(res: reqwest::Response) -> ProviderResult<reqwest::Response> {
+    let status = res.status();
+    if status.is_success() {
+        Ok(res)
+    } else if status == reqwest::StatusCode::UNAUTHORIZED {
+        Err(ProviderError::Unauthorized)
+    } else {
+        let body = res.text().await.unwrap_or_default();
+        Err(ProviderError::Response {
+            status: status.as_u16(),
+            body,
+        })
+    }
+}
+
+#[async_trait]
+impl BankingProvider for TrueLayer {
+    fn name(&self) -> &'static str {
+        TRUELAYER
+    }
+
+    fn auth_url(&self, state: &str) -> String {
+        let auth_uri = format!("{}", self.params.auth_uri);
+        let response_type = "response_type=code".to_string();
+        let client_id = format!("client_id={}", self.params.client_id);
+        let scope = "scope=info%20accounts%20balance%20cards%20transactions%20direct_debits%20standing_orders%20offline_access".to_string();
+        let redirect_uri = format!("redirect_uri={}", self.params.redirect_uri);
+        let providers = "providers=uk-ob-all%20uk-oauth-all%20uk-cs-mock".to_string();
+        let state = format!("state={}", state);
+
+        format!(
+            "{}/?{}&{}&{}&{}&{}&{}",
+            auth_uri, response_type, client_id, scope, redirect_uri, providers, state
+        )
+    }
+
+    #[instrument(skip(self, code))]
+    async fn exchange_code(&self, code: &str) -> ProviderResult<Token> {
+        self.request_token(&[
+            ("grant_type", "authorization_code"),
+            ("client_id", self.params.client_id.as_str()),
+            ("client_secret", self.params.client_secret.as_str()),
+            ("redirect_uri", self.params.redirect_uri.as_str()),
+            ("code", code),
+        ])
+        .await
+    }
+
+    #[instrument(skip(self, refresh_token))]
+    async fn refresh_token(&self, refresh_token: &str) -> ProviderResult<Token> {
+        self.request_token(&[
+            ("grant_type", "refresh_token"),
+            ("client_id", self.params.client_id.as_str()),
+            ("client_secret", self.params.client_secret.as_str()),
+            ("refresh_token", refresh_token),
+        ])
+        .await
+    }
+
+    #[instrument(skip(self, access_token))]
+    async fn revoke(&self, access_token: &str) -> ProviderResult<()> {
+        let request = self
+            .client
+            .delete(&format!("{}/api/delete", self.params.auth_uri))
+            .bearer_auth(access_token);
+        let res = self.client.send(TRUELAYER, request).await?;
+
+        check_status(res).await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self, access_token))]
+    async fn accounts(&self, access_token: &str) -> ProviderResult<Vec<Account>> {
+        let accounts: AccountsResults = self.get(access_token, "/data/v1/accounts").await?;
+        Ok(accounts.results)
+    }
+
+    #[instrument(skip(self, access_token))]
+    async fn balance(&self, access_token: &str, account_id: &str) -> ProviderResult<Balance> {
+        let path = format!("/data/v1/accounts/{}/balance", account_id);
+        let balances: BalanceResults = self.get(access_token, &path).await?;
+        balances
+            .results
+            .into_iter()
+            .next()
+            .ok_or_else(|| ProviderError::Other(eyre!("No balance for account {}", account_id)))
+    }
+
+    #[instrument(skip(self, access_token))]
+    async fn transactions(
+        &self,
+        access_token: &str,
+        account_id: &str,
+        from: Option<DateTime<Utc>>,
+    ) -> ProviderResult<Vec<TransactionsAccount>> {
+        let path = format!("/data/v1/accounts/{}/transactions", account_id);
+        let transactions: TransactionsResults =
+            self.get(access_token, &with_range(path, from)).await?;
+        Ok(transactions.results)
+    }
+
+    #[instrument(skip(self, access_token))]
+    async fn cards(&self, access_token: &str) -> ProviderResult<Vec<Card>> {
+        let cards: CardsResults = self.get(access_token, "/data/v1/cards").await?;
+        Ok(cards.results)
+    }
+
+    #[instrument(skip(self, access_token))]
+    async fn card_transactions(
+        &self,
+        access_token: &str,
+        card_id: &str,
+        from: Option<DateTime<Utc>>,
+    ) -> ProviderResult<Vec<TransactionsAccount>> {
+        let path = format!("/data/v1/cards/{}/transactions", card_id);
+        let transactions: TransactionsResults =
+            self.get(access_token, &with_range(path, from)).await?;
+        Ok(transactions.results)
+    }
+
+    #[instrument(skip(self, access_token))]
+    async fn direct_debits(
+        &self,
+        access_token: &str,
+        account_id: &str,
+    ) -> ProviderResult<Vec<DirectDebit>> {
+        let path = format!("/data/v1/accounts/{}/direct_debits", account_id);
+        let direct_debits: DirectDebitsResults = self.get(access_token, &path).await?;
+        Ok(direct_debits.results)
+    }
+
+    #[instrument(skip(self, access_token))]
+    async fn standing_orders(
+        &self,
+        access_token: &str,
+        account_id: &str,
+    ) -> ProviderResult<Vec<StandingOrder>> {
+        let path = format!("/data/v1/accounts/{}/standing_orders", account_id);
+        let standing_orders: StandingOrdersResults = self.get(access_token, &path).await?;
+        Ok(standing_orders.results)
+    }
+}
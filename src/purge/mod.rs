@@ -0,0 +1,51 @@
+use crate::db::user::UserRepository;
+use chrono::{Duration, Utc};
+use sqlx::PgPool;
+use std::sync::Arc;
+use tracing::{error, info};
+
+/// How often the worker looks for deleted accounts to purge.
+const POLL_SECONDS: u64 = 3600;
+/// Users purged per poll, the rest are picked up on the next one.
+const BATCH_SIZE: i64 = 100;
+
+/// Purges the data of deleted accounts once their grace period is over.
+#[derive(Clone)]
+pub struct Purger {
+    pool: Arc<PgPool>,
+    grace_period: Duration,
+}
+
+impl Purger {
+    pub fn new(pool: PgPool, grace_period: Duration) -> Self {
+        Purger {
+            pool: Arc::new(pool),
+            grace_period,
+        }
+    }
+
+    /// Runs the purge forever, to be spawned on the actix runtime.
+    pub async fn run(self) {
+        info!(
+            "Purging deleted accounts after {} days",
+            self.grace_period.num_days()
+        );
+        loop {
+            if let Err(err) = self.purge_due().await {
+                error!("Account purge failed. {:?}", err);
+            }
+            tokio::time::delay_for(std::time::Duration::from_secs(POLL_SECONDS)).await;
+        }
+    }
+
+    async fn purge_due(&self) -> color_eyre::Result<()> {
+        let deleted_before = (Utc::now() - self.grace_period).naive_utc();
+        let purged = UserRepository::new(self.pool.clone())
+            .purge_deleted(deleted_before, BATCH_SIZE)
+            .await?;
+        if purged > 0 {
+            info!("Purged the data of {} deleted accounts", purged);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,311 @@
+use crate::{
+    config::crypto::CryptoService,
+    db::{
+        account::AccountRepository, card::CardRepository, consent::ConsentRepository,
+        payment::PaymentRepository, provider::ProviderRepository, sync::SyncRepository,
+        trans::TransactionRepository,
+    },
+    models::sync::SyncStatus,
+    providers::{session::ProviderSession, BankingProvider, ProviderError, ProviderResult},
+};
+use chrono::{DateTime, Duration, Utc};
+use rand::Rng;
+use sqlx::PgPool;
+use std::sync::Arc;
+use tracing::{debug, error, info, instrument};
+use uuid::Uuid;
+
+/// How often the worker looks for users whose sync is due.
+const POLL_SECONDS: u64 = 60;
+/// Users synced per poll, the rest are picked up on the next one.
+const BATCH_SIZE: i64 = 50;
+/// Transactions can be booked a few days after they happened, so every sync
+/// looks back this far before the watermark. Upserts drop the duplicates.
+const WATERMARK_OVERLAP_DAYS: i64 = 3;
+
+/// Data the bank doesn't offer is skipped instead of failing the sync,
+/// `None` then.
+fn unless_not_supported<T>(result: ProviderResult<T>) -> ProviderResult<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(err) if err.is_not_supported() => {
+            debug!("Skipping data not supported by the provider. {}", err);
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// A failed account is logged and recorded, the sync goes on with the next
+/// one. Errors of the whole link still stop it.
+fn record_failure(
+    failures: &mut Vec<String>,
+    account_id: &str,
+    result: ProviderResult<()>,
+) -> ProviderResult<()> {
+    match result {
+        Ok(()) => Ok(()),
+        Err(err @ ProviderError::Unauthorized)
+        | Err(err @ ProviderError::RelinkRequired)
+        | Err(err @ ProviderError::ConsentExpired) => Err(err),
+        Err(err) => {
+            error!("Cannot sync account {}. {}", account_id, err);
+            failures.push(format!("{}: {}", account_id, err.summary()));
+            Ok(())
+        }
+    }
+}
+
+/// Pulls new transactions, the balances of the day, cards, direct debits and
+/// standing orders from the provider, either on a schedule from the background
+/// worker or on demand.
+#[derive(Clone)]
+pub struct Syncer {
+    pool: Arc<PgPool>,
+    crypto: CryptoService,
+    provider: Arc<dyn BankingProvider>,
+    interval: Duration,
+    jitter: Duration,
+}
+
+impl Syncer {
+    pub fn new(
+        pool: PgPool,
+        crypto: CryptoService,
+        provider: Arc<dyn BankingProvider>,
+        interval: Duration,
+        jitter: Duration,
+    ) -> Self {
+        Syncer {
+            pool: Arc::new(pool),
+            crypto,
+            provider,
+            interval,
+            jitter,
+        }
+    }
+
+    /// Runs the periodic sync forever, to be spawned on the actix runtime.
+    pub async fn run(self) {
+        info!(
+            "Starting transactions sync every {}s",
+            self.interval.num_seconds()
+        );
+        loop {
+            if let Err(err) = self.sync_due().await {
+                error!("Transactions sync failed. {:?}", err);
+            }
+            tokio::time::delay_for(std::time::Duration::from_secs(POLL_SECONDS)).await;
+        }
+    }
+
+    async fn sync_due(&self) -> color_eyre::Result<()> {
+        let users = SyncRepository::new(self.pool.clone())
+            .due_users(self.provider.name(), BATCH_SIZE)
+            .await?;
+
+        for user_id in users {
+            if let Err(err) = self.sync_user(user_id).await {
+                debug!("Sync of user {} failed. {}", user_id, err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Next scheduled sync, spread by a random jitter so users linked at the
+    /// same time don't all hit the provider together.
+    fn next_sync_at(&self) -> DateTime<Utc> {
+        let jitter = match self.jitter.num_seconds() {
+            0 => 0,
+            jitter => rand::thread_rng().gen_range(0, jitter + 1),
+        };
+        Utc::now() + self.interval + Duration::seconds(jitter)
+    }
+
+    /// Syncs every account of the user and records the outcome, with the
+    /// accounts that failed if any. When the user is already being synced, by
+    /// the worker or on demand, the status is returned without syncing again.
+    #[instrument(skip(self))]
+    pub async fn sync_user(&self, user_id: Uuid) -> ProviderResult<SyncStatus> {
+        let syncs = SyncRepository::new(self.pool.clone());
+        let lock = match syncs.lock(user_id).await? {
+            Some(lock) => lock,
+            None => {
+                debug!("User {} is already being synced", user_id);
+                return Ok(syncs.status(user_id).await?.unwrap_or_default());
+            }
+        };
+        syncs.start(user_id).await?;
+
+        let result = match self.sync_accounts(user_id, &syncs).await {
+            Ok(failures) if failures.is_empty() => {
+                Ok(syncs.succeed(user_id, self.next_sync_at()).await?)
+            }
+            Ok(failures) => {
+                let error = format!("Some accounts were not synced. {}", failures.join(" "));
+                syncs.fail(user_id, error, self.next_sync_at()).await?;
+                Ok(syncs.status(user_id).await?.unwrap_or_default())
+            }
+            Err(err) => {
+                // The status is shown to the user, the details are only logged
+                syncs
+                    .fail(user_id, err.summary().to_string(), self.next_sync_at())
+                    .await?;
+                Err(err)
+            }
+        };
+        lock.release().await?;
+        result
+    }
+
+    /// Returns the failures of the accounts that could not be synced.
+    async fn sync_accounts(
+        &self,
+        user_id: Uuid,
+        syncs: &SyncRepository,
+    ) -> ProviderResult<Vec<String>> {
+        let providers = ProviderRepository::new(self.pool.clone());
+        let consents = ConsentRepository::new(self.pool.clone());
+        let accounts = AccountRepository::new(self.pool.clone());
+        let cards = CardRepository::new(self.pool.clone());
+        let payments = PaymentRepository::new(self.pool.clone());
+        let transactions = TransactionRepository::new(self.pool.clone());
+        let mut session = ProviderSession::open(
+            self.provider.as_ref(),
+            &providers,
+            &consents,
+            &self.crypto,
+            user_id,
+        )
+        .await?;
+
+        let fetched_accounts = session.accounts().await?;
+        accounts
+            .save(user_id, self.provider.name(), &fetched_accounts)
+            .await?;
+
+        let mut failures = Vec::new();
+        for account in fetched_accounts {
+            let result = async {
+                // A missing balance doesn't keep the transactions from being synced
+                match unless_not_supported(session.balance(&account.account_id).await) {
+                    Ok(Some(balance)) => {
+                        accounts
+                            .save_balance(
+                                user_id,
+                                self.provider.name(),
+                                &account.account_id,
+                                &balance,
+                            )
+                            .await?;
+                    }
+                    Ok(None) => {}
+                    Err(err @ ProviderError::RelinkRequired)
+                    | Err(err @ ProviderError::ConsentExpired) => return Err(err),
+                    Err(err) => {
+                        error!(
+                            "Cannot fetch balance of account {}. {}",
+                            account.account_id, err
+                        );
+                    }
+                }
+
+                let started_at = Utc::now();
+                let from = syncs
+                    .watermark(user_id, self.provider.name(), &account.account_id)
+                    .await?
+                    .map(|synced_until| synced_until - Duration::days(WATERMARK_OVERLAP_DAYS));
+
+                let fetched = session.transactions(&account.account_id, from).await?;
+                debug!(
+                    "Fetched {} transactions for account {}",
+                    fetched.len(),
+                    account.account_id
+                );
+                for mut model in fetched {
+                    model.account_id = Some(account.account_id.clone());
+                    transactions
+                        .save(user_id, &account.account_id, &model)
+                        .await?;
+                }
+
+                syncs
+                    .save_watermark(
+                        user_id,
+                        self.provider.name(),
+                        &account.account_id,
+                        started_at,
+                    )
+                    .await?;
+
+                if let Some(direct_debits) =
+                    unless_not_supported(session.direct_debits(&account.account_id).await)?
+                {
+                    payments
+                        .replace_direct_debits(
+                            user_id,
+                            self.provider.name(),
+                            &account.account_id,
+                            &direct_debits,
+                        )
+                        .await?;
+                }
+                if let Some(standing_orders) =
+                    unless_not_supported(session.standing_orders(&account.account_id).await)?
+                {
+                    payments
+                        .replace_standing_orders(
+                            user_id,
+                            self.provider.name(),
+                            &account.account_id,
+                            &standing_orders,
+                        )
+                        .await?;
+                }
+                Ok::<(), ProviderError>(())
+            }
+            .await;
+            record_failure(&mut failures, &account.account_id, result)?;
+        }
+
+        let fetched_cards = match unless_not_supported(session.cards().await)? {
+            Some(fetched_cards) => fetched_cards,
+            None => return Ok(failures),
+        };
+        cards
+            .save(user_id, self.provider.name(), &fetched_cards)
+            .await?;
+
+        // Card ids don't clash with account ids, they share the watermarks
+        for card in fetched_cards {
+            let result = async {
+                let started_at = Utc::now();
+                let from = syncs
+                    .watermark(user_id, self.provider.name(), &card.account_id)
+                    .await?
+                    .map(|synced_until| synced_until - Duration::days(WATERMARK_OVERLAP_DAYS));
+
+                let fetched = session.card_transactions(&card.account_id, from).await?;
+                debug!(
+                    "Fetched {} transactions for card {}",
+                    fetched.len(),
+                    card.account_id
+                );
+                for mut model in fetched {
+                    model.account_id = Some(card.account_id.clone());
+                    transactions
+                        .save_card(user_id, &card.account_id, &model)
+                        .await?;
+                }
+
+                syncs
+                    .save_watermark(user_id, self.provider.name(), &card.account_id, started_at)
+                    .await?;
+                Ok::<(), ProviderError>(())
+            }
+            .await;
+            record_failure(&mut failures, &card.account_id, result)?;
+        }
+        Ok(failures)
+    }
+}